@@ -6,13 +6,37 @@ use {
     },
     alloc::{format, rc::Rc, vec::Vec},
     anyhow::{anyhow, ensure, Error},
-    core::{cell::RefCell, ops::RangeInclusive, ptr, slice},
+    core::{
+        cell::{RefCell, RefMut},
+        ops::{Deref, RangeInclusive},
+        ptr, slice,
+    },
     crankstart_sys::{ctypes::c_int, LCDBitmapTable, LCDPattern},
     cstr_core::{CStr, CString},
     euclid::default::{Point2D, Vector2D},
     hashbrown::HashMap,
 };
 
+pub mod fnt;
+mod font;
+pub mod pdi;
+pub mod pdv;
+mod pixels;
+pub mod text;
+mod video;
+
+pub use fnt::{FntFile, FntGlyph, FontBuilder, GlyphImage};
+pub use font::{Font, FontGlyph, FontPage};
+pub use pdi::{PdiImage, PdtTable};
+pub use pdv::{PdvDecoder, PdvFile, PdvFrameType};
+pub use pixels::BitmapPixels;
+pub use text::{
+    FontFamily, TextAlignment, TextLayout, TextLayoutOptions, TextLine, TextMetrics, TextRun,
+    TextStyle,
+};
+use video::VideoPlayerInner;
+pub use video::{VideoInfo, VideoPlayer};
+
 pub use crankstart_sys::{
     LCDBitmapDrawMode, LCDBitmapFlip, LCDLineCapStyle, LCDPolygonFillRule, LCDRect, LCDSolidColor,
     PDRect, PDStringEncoding, LCD_COLUMNS, LCD_ROWS, LCD_ROWSIZE,
@@ -57,6 +81,18 @@ pub struct BitmapData {
 pub struct BitmapInner {
     pub(crate) raw_bitmap: *mut crankstart_sys::LCDBitmap,
     owned: bool,
+    // What the bitmap's data belongs to, if that's something else, held to keep the data alive.
+    source: Option<BitmapSource>,
+}
+
+#[derive(Clone, Debug)]
+enum BitmapSource {
+    // A mask from getBitmapMask points into its bitmap's data.
+    Bitmap(BitmapInnerPtr),
+    // A glyph's image is part of its font.
+    Font(Font),
+    // A video player's own context is freed with the player.
+    Video(Rc<VideoPlayerInner>),
 }
 
 impl BitmapInner {
@@ -82,6 +118,15 @@ impl BitmapInner {
         })
     }
 
+    pub fn get_pixel(&self, point: ScreenPoint) -> Result<LCDSolidColor, Error> {
+        pd_func_caller!(
+            (*Graphics::get_ptr()).getBitmapPixel,
+            self.raw_bitmap,
+            point.x,
+            point.y
+        )
+    }
+
     pub fn draw(&self, location: ScreenPoint, flip: LCDBitmapFlip) -> Result<(), Error> {
         pd_func_caller!(
             (*Graphics::get_ptr()).drawBitmap,
@@ -137,6 +182,7 @@ impl BitmapInner {
         Ok(Self {
             raw_bitmap,
             owned: true,
+            source: None,
         })
     }
 
@@ -172,6 +218,7 @@ impl BitmapInner {
         Ok(Self {
             raw_bitmap,
             owned: self.owned,
+            source: None,
         })
     }
 
@@ -259,6 +306,22 @@ impl Drop for BitmapInner {
 
 pub type BitmapInnerPtr = Rc<RefCell<BitmapInner>>;
 
+// A mutable borrow of a bitmap that also holds its parent's, for masks sharing their parent's
+// data, so neither can be written through while the other is.
+#[derive(Debug)]
+pub(crate) struct BitmapRefMut<'a> {
+    inner: RefMut<'a, BitmapInner>,
+    _parent: Option<RefMut<'a, BitmapInner>>,
+}
+
+impl Deref for BitmapRefMut<'_> {
+    type Target = BitmapInner;
+
+    fn deref(&self) -> &BitmapInner {
+        &self.inner
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Bitmap {
     pub(crate) inner: BitmapInnerPtr,
@@ -266,8 +329,20 @@ pub struct Bitmap {
 
 impl Bitmap {
     fn new(raw_bitmap: *mut crankstart_sys::LCDBitmap, owned: bool) -> Self {
+        Self::with_source(raw_bitmap, owned, None)
+    }
+
+    fn with_source(
+        raw_bitmap: *mut crankstart_sys::LCDBitmap,
+        owned: bool,
+        source: Option<BitmapSource>,
+    ) -> Self {
         Bitmap {
-            inner: Rc::new(RefCell::new(BitmapInner { raw_bitmap, owned })),
+            inner: Rc::new(RefCell::new(BitmapInner {
+                raw_bitmap,
+                owned,
+                source,
+            })),
         }
     }
 
@@ -275,6 +350,95 @@ impl Bitmap {
         self.inner.borrow().get_data()
     }
 
+    /// Returns a view for reading and writing the bitmap's pixels directly.  Fails if the
+    /// bitmap is already borrowed, e.g. by another view or a `DrawContext` targeting it.  A mask
+    /// from `mask` also borrows the bitmap it belongs to, since they share data.
+    pub fn pixels(&self) -> Result<BitmapPixels<'_>, Error> {
+        BitmapPixels::new(self.borrow_mut()?)
+    }
+
+    // Mutably borrows the bitmap, along with the bitmap its data belongs to if it's a mask.
+    pub(crate) fn borrow_mut(&self) -> Result<BitmapRefMut<'_>, Error> {
+        let inner = self.inner.try_borrow_mut().map_err(Error::msg)?;
+        let parent = match &inner.source {
+            Some(BitmapSource::Bitmap(parent)) => {
+                // The mask holds an Rc to its parent that's never replaced, and the mask itself
+                // is kept alive by `self`, so the parent outlives the borrow of `self`.
+                let parent: &RefCell<BitmapInner> = unsafe { &*Rc::as_ptr(parent) };
+                Some(parent.try_borrow_mut().map_err(Error::msg)?)
+            }
+            _ => None,
+        };
+        Ok(BitmapRefMut {
+            inner,
+            _parent: parent,
+        })
+    }
+
+    /// Returns the color of the pixel at `point`.
+    pub fn get_pixel(&self, point: ScreenPoint) -> Result<LCDSolidColor, Error> {
+        self.inner.borrow().get_pixel(point)
+    }
+
+    /// Sets the bitmap's mask to a copy of `mask`, which must be the same size; white pixels in
+    /// the mask are drawn and black ones are transparent.  Adds a mask if there wasn't one.
+    pub fn set_mask(&self, mask: &Bitmap) -> Result<(), Error> {
+        let result = pd_func_caller!(
+            (*Graphics::get_ptr()).setBitmapMask,
+            self.inner.borrow().raw_bitmap,
+            mask.inner.borrow().raw_bitmap,
+        )?;
+        ensure!(result != 0, "Mask size doesn't match bitmap in set_mask");
+        Ok(())
+    }
+
+    /// Returns the bitmap's mask, or None if it doesn't have one.  The mask shares the bitmap's
+    /// data, so drawing into it changes the bitmap's transparency, and it keeps the bitmap
+    /// alive.
+    pub fn mask(&self) -> Result<Option<Bitmap>, Error> {
+        let raw_mask = pd_func_caller!(
+            (*Graphics::get_ptr()).getBitmapMask,
+            self.inner.borrow().raw_bitmap
+        )?;
+        if raw_mask.is_null() {
+            return Ok(None);
+        }
+        // The caller owns the returned bitmap, though not the data it points to.
+        Ok(Some(Bitmap::with_source(
+            raw_mask,
+            true,
+            Some(BitmapSource::Bitmap(self.inner.clone())),
+        )))
+    }
+
+    /// Creates a mask the size of this bitmap that's opaque wherever this bitmap isn't `key`,
+    /// e.g. to make the black background of a runtime-drawn image transparent.  Pixels already
+    /// transparent in this bitmap's mask stay transparent.
+    pub fn color_key_mask(&self, key: LCDSolidColor) -> Result<Bitmap, Error> {
+        let source = self.pixels()?;
+        let size = ScreenSize::new(source.width() as i32, source.height() as i32);
+        let mask = Graphics::get().new_bitmap(size, LCDColor::Solid(LCDSolidColor::kColorBlack))?;
+        {
+            let mut mask_pixels = mask.pixels()?;
+            for y in 0..source.height() {
+                for x in 0..source.width() {
+                    let opaque =
+                        source.get(x, y) != Some(key) && source.get_mask(x, y).unwrap_or(true);
+                    if opaque {
+                        mask_pixels.set(x, y, LCDSolidColor::kColorWhite);
+                    }
+                }
+            }
+        }
+        Ok(mask)
+    }
+
+    /// Makes pixels of color `key` transparent; see `color_key_mask`.
+    pub fn set_color_key(&self, key: LCDSolidColor) -> Result<(), Error> {
+        let mask = self.color_key_mask(key)?;
+        self.set_mask(&mask)
+    }
+
     pub fn draw(&self, location: ScreenPoint, flip: LCDBitmapFlip) -> Result<(), Error> {
         self.inner.borrow().draw(location, flip)
     }
@@ -364,21 +528,6 @@ fn raw_bitmap(bitmap: OptionalBitmap<'_>) -> *mut crankstart_sys::LCDBitmap {
     }
 }
 
-pub struct Font(*mut crankstart_sys::LCDFont);
-
-impl Font {
-    pub fn new(font: *mut crankstart_sys::LCDFont) -> Result<Self, Error> {
-        anyhow::ensure!(!font.is_null(), "Null pointer passed to Font::new");
-        Ok(Self(font))
-    }
-}
-
-impl Drop for Font {
-    fn drop(&mut self) {
-        log_to_console!("Leaking a font");
-    }
-}
-
 #[derive(Debug)]
 struct BitmapTableInner {
     raw_bitmap_table: *mut LCDBitmapTable,
@@ -459,10 +608,167 @@ impl BitmapTable {
     pub fn get_bitmap(&self, index: usize) -> Result<Bitmap, Error> {
         self.inner.borrow_mut().get_bitmap(index)
     }
+
+    /// Returns the number of bitmaps in the table and how many make up each row of its grid.
+    pub fn get_info(&self) -> Result<(usize, usize), Error> {
+        let mut count = 0;
+        let mut width = 0;
+        pd_func_caller!(
+            (*Graphics::get_ptr()).getBitmapTableInfo,
+            self.inner.borrow().raw_bitmap_table,
+            &mut count,
+            &mut width
+        )?;
+        Ok((count.max(0) as usize, width.max(0) as usize))
+    }
 }
 
 static mut GRAPHICS: Graphics = Graphics(ptr::null_mut());
 
+// The clip rects set through `Graphics::clip` and `Graphics::screen_clip`, innermost last.
+static mut CLIP_STACK: Vec<ClipRect> = Vec::new();
+
+fn clip_rect(clip: ClipRect) -> ScreenRect {
+    match clip {
+        ClipRect::World(rect) | ClipRect::Screen(rect) => rect,
+    }
+}
+
+// Returns the innermost clip of the current drawing context; clips from outer contexts don't
+// apply to it.
+fn current_clip_entry() -> Option<ClipRect> {
+    let base = draw_states().last().map_or(0, |state| state.clip_base);
+    #[allow(static_mut_refs)]
+    let stack = unsafe { &CLIP_STACK };
+    stack.get(base..).and_then(|clips| clips.last().copied())
+}
+
+// The drawing state of each drawing context, innermost last.  The first entry is the state of
+// the framebuffer outside any `DrawContext`.
+static mut DRAW_STATES: Vec<DrawState> = Vec::new();
+
+fn draw_states() -> &'static mut Vec<DrawState> {
+    #[allow(static_mut_refs)]
+    let states = unsafe { &mut DRAW_STATES };
+    if states.is_empty() {
+        states.push(DrawState::default());
+    }
+    states
+}
+
+// The state the system keeps per drawing context, recorded as it's set through `Graphics` so it
+// can be restored when a `DrawContext` ends.
+#[derive(Clone, Debug)]
+struct DrawState {
+    // The bitmap drawn into, or null for the framebuffer.
+    target: *mut crankstart_sys::LCDBitmap,
+    draw_mode: LCDBitmapDrawMode,
+    draw_offset: ScreenVector,
+    // None for the system font.  Held so the font isn't freed while it's in use.
+    font: Option<Font>,
+    text_tracking: i32,
+    text_leading: i32,
+    line_cap_style: LCDLineCapStyle,
+    // Held so the stencil isn't freed while it's in use.
+    stencil: Option<Bitmap>,
+    stencil_tiled: bool,
+    // Length of the clip stack when the context was pushed.
+    clip_base: usize,
+}
+
+impl Default for DrawState {
+    fn default() -> Self {
+        Self {
+            target: ptr::null_mut(),
+            draw_mode: LCDBitmapDrawMode::kDrawModeCopy,
+            draw_offset: ScreenVector::zero(),
+            font: None,
+            text_tracking: 0,
+            text_leading: 0,
+            line_cap_style: LCDLineCapStyle::kLineCapStyleButt,
+            stencil: None,
+            stencil_tiled: false,
+            clip_base: 0,
+        }
+    }
+}
+
+/// A drawing context, returned by `Graphics::push_context`.  While it's alive, drawing goes to
+/// its target, and drawing state set through it (draw mode, offset, font, tracking, line cap
+/// style, stencil and clip) only applies to it; dropping it goes back to the previous context
+/// and restores that context's state, even if drawing failed part way through.
+///
+/// Drawing methods are available through `Deref` to `Graphics`.
+#[must_use = "the context is popped as soon as the guard is dropped"]
+#[derive(Debug)]
+pub struct DrawContext<'a> {
+    graphics: Graphics,
+    // How deep the state stack was before this context was pushed.
+    depth: usize,
+    // Anything drawn in this context modifies the bitmap, so borrow it mutably for safety.
+    _target: Option<BitmapRefMut<'a>>,
+}
+
+impl Deref for DrawContext<'_> {
+    type Target = Graphics;
+
+    fn deref(&self) -> &Graphics {
+        &self.graphics
+    }
+}
+
+impl Drop for DrawContext<'_> {
+    fn drop(&mut self) {
+        let states = draw_states();
+        // Dropping an outer context first also pops the contexts inside it, so by the time
+        // theirs are dropped there's nothing left to pop.
+        if self.depth >= states.len() {
+            return;
+        }
+        for _ in self.depth..states.len() {
+            if let Err(err) = self.graphics.pop_context() {
+                log_to_console!("Error popping drawing context: {err:#}");
+            }
+        }
+        if let Some(state) = states.get(self.depth) {
+            #[allow(static_mut_refs)]
+            unsafe { &mut CLIP_STACK }.truncate(state.clip_base);
+        }
+        states.truncate(self.depth.max(1));
+        if let Err(err) = self.graphics.apply_draw_state() {
+            log_to_console!("Error restoring drawing state: {err:#}");
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum ClipRect {
+    // Affected by the draw offset.
+    World(ScreenRect),
+    Screen(ScreenRect),
+}
+
+/// Restores the previous clip rect when dropped; see `Graphics::clip`.
+#[must_use = "the clip rect is removed as soon as the guard is dropped"]
+#[derive(Debug)]
+pub struct ClipGuard {
+    // How deep the clip stack was before this guard's rect was pushed.
+    depth: usize,
+}
+
+impl Drop for ClipGuard {
+    fn drop(&mut self) {
+        #[allow(static_mut_refs)]
+        let stack = unsafe { &mut CLIP_STACK };
+        // Truncating rather than popping means dropping an outer guard first also removes the
+        // rects inside it.
+        stack.truncate(self.depth);
+        if let Err(err) = Graphics::get().apply_clip(current_clip_entry()) {
+            log_to_console!("Error restoring clip rect: {err:#}");
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Graphics(*const crankstart_sys::playdate_graphics);
 
@@ -482,22 +788,97 @@ impl Graphics {
     }
 
     /// Allows drawing directly into an image rather than the framebuffer, for example for
-    /// drawing text into a sprite's image.
+    /// drawing text into a sprite's image.  See `push_context` for a guard that isn't limited
+    /// to a closure.
     pub fn with_context<F, T>(&self, bitmap: &Bitmap, f: F) -> Result<T, Error>
     where
         F: FnOnce() -> Result<T, Error>,
     {
-        // Any calls in this context are directly modifying the bitmap, so borrow mutably
-        // for safety.
-        self.push_context(bitmap.inner.borrow_mut().raw_bitmap)?;
-        let res = f();
-        self.pop_context()?;
-        res
+        let _context = self.push_context(Some(bitmap))?;
+        f()
+    }
+
+    /// Starts drawing into `target`, or into the framebuffer if it's None, until the returned
+    /// `DrawContext` is dropped.  The new context starts with the current draw mode, offset,
+    /// font, tracking, line cap style and stencil, but no clip rect.  Fails if `target` is in
+    /// use, e.g. while its pixels are held or it's already being drawn into.
+    pub fn push_context<'a>(&self, target: Option<&'a Bitmap>) -> Result<DrawContext<'a>, Error> {
+        let target = target.map(|bitmap| bitmap.borrow_mut()).transpose()?;
+        let raw_target = target
+            .as_ref()
+            .map_or(ptr::null_mut(), |inner| inner.raw_bitmap);
+        self.push_raw_context(raw_target, target)
+    }
+
+    // Pushes a context drawing into the current target with the current state and clip rect, so
+    // that state changes made in it, like setting a font, are undone when it's dropped, even
+    // going back to the system font.
+    pub(crate) fn push_nested_context(&self) -> Result<DrawContext<'static>, Error> {
+        let clip = current_clip_entry();
+        let raw_target = draw_states()
+            .last()
+            .map_or(ptr::null_mut(), |state| state.target);
+        // The target is already borrowed by the context that drew into it.
+        let context = self.push_raw_context(raw_target, None)?;
+        self.apply_clip(clip)?;
+        Ok(context)
+    }
+
+    fn push_raw_context<'a>(
+        &self,
+        raw_target: *mut crankstart_sys::LCDBitmap,
+        target: Option<BitmapRefMut<'a>>,
+    ) -> Result<DrawContext<'a>, Error> {
+        pd_func_caller!((*self.0).pushContext, raw_target)?;
+
+        let states = draw_states();
+        let depth = states.len();
+        let mut state = states[depth - 1].clone();
+        #[allow(static_mut_refs)]
+        let clip_base = unsafe { CLIP_STACK.len() };
+        state.target = raw_target;
+        state.clip_base = clip_base;
+        states.push(state);
+        // Build the guard first so the context is popped if applying the state fails.
+        let context = DrawContext {
+            graphics: self.clone(),
+            depth,
+            _target: target,
+        };
+        self.apply_draw_state()?;
+        Ok(context)
     }
 
-    /// Internal function; use `with_context`.
-    fn push_context(&self, raw_bitmap: *mut crankstart_sys::LCDBitmap) -> Result<(), Error> {
-        pd_func_caller!((*self.0).pushContext, raw_bitmap)
+    fn pop_context(&self) -> Result<(), Error> {
+        pd_func_caller!((*self.0).popContext)
+    }
+
+    // Sets everything in the current context's recorded state on the system.
+    fn apply_draw_state(&self) -> Result<(), Error> {
+        let state = draw_states().last().cloned().unwrap_or_default();
+        pd_func_caller!((*self.0).setDrawMode, state.draw_mode)?;
+        pd_func_caller!(
+            (*self.0).setDrawOffset,
+            state.draw_offset.x,
+            state.draw_offset.y
+        )?;
+        // There's no way to go back to the system font, but a context that never set a font
+        // gets the system one anyway.
+        if let Some(font) = &state.font {
+            pd_func_caller!((*self.0).setFont, font.raw_font())?;
+        }
+        pd_func_caller!((*self.0).setTextTracking, state.text_tracking)?;
+        pd_func_caller!((*self.0).setTextLeading, state.text_leading)?;
+        pd_func_caller!((*self.0).setLineCapStyle, state.line_cap_style)?;
+        match &state.stencil {
+            Some(stencil) => pd_func_caller!(
+                (*self.0).setStencilImage,
+                stencil.inner.borrow().raw_bitmap,
+                state.stencil_tiled as c_int
+            )?,
+            None => pd_func_caller!((*self.0).setStencil, ptr::null_mut())?,
+        }
+        self.apply_clip(current_clip_entry())
     }
 
     /// Clear the context stack for graphics to make all drawing go to the display framebuffer.
@@ -505,11 +886,6 @@ impl Graphics {
         pd_func_caller!((*self.0).pushContext, core::ptr::null_mut())
     }
 
-    /// Internal function; use `with_context`.
-    fn pop_context(&self) -> Result<(), Error> {
-        pd_func_caller!((*self.0).popContext)
-    }
-
     pub fn get_frame(&self) -> Result<&'static mut [u8], Error> {
         let ptr = pd_func_caller!((*self.0).getFrame)?;
         anyhow::ensure!(!ptr.is_null(), "Null pointer returned from getFrame");
@@ -547,7 +923,9 @@ impl Graphics {
     }
 
     pub fn set_draw_mode(&self, mode: LCDBitmapDrawMode) -> Result<LCDBitmapDrawMode, Error> {
-        pd_func_caller!((*self.0).setDrawMode, mode)
+        let previous = pd_func_caller!((*self.0).setDrawMode, mode)?;
+        draw_states().last_mut().unwrap().draw_mode = mode;
+        Ok(previous)
     }
 
     pub fn mark_updated_rows(&self, range: RangeInclusive<i32>) -> Result<(), Error> {
@@ -560,7 +938,169 @@ impl Graphics {
     }
 
     pub fn set_draw_offset(&self, offset: ScreenVector) -> Result<(), Error> {
-        pd_func_caller!((*self.0).setDrawOffset, offset.x, offset.y)
+        pd_func_caller!((*self.0).setDrawOffset, offset.x, offset.y)?;
+        draw_states().last_mut().unwrap().draw_offset = offset;
+        Ok(())
+    }
+
+    /// Sets the style of the ends of lines drawn with `draw_line`.
+    pub fn set_line_cap_style(&self, style: LCDLineCapStyle) -> Result<(), Error> {
+        pd_func_caller!((*self.0).setLineCapStyle, style)?;
+        draw_states().last_mut().unwrap().line_cap_style = style;
+        Ok(())
+    }
+
+    /// Only draws where the stencil bitmap has set (white) pixels, or everywhere if None.  The
+    /// stencil is kept alive until it's replaced or its context ends.
+    pub fn set_stencil(&self, stencil: Option<&Bitmap>) -> Result<(), Error> {
+        match stencil {
+            Some(stencil) => self.set_stencil_image(stencil, false),
+            None => self.clear_stencil(),
+        }
+    }
+
+    /// Only draws where the stencil bitmap has set (white) pixels.  If `tile` is true, the
+    /// stencil is repeated to cover the whole drawing area, otherwise nothing is drawn outside
+    /// it; tiled stencils must be a multiple of 32 pixels wide.  The stencil is kept alive until
+    /// it's replaced or its context ends.
+    pub fn set_stencil_image(&self, stencil: &Bitmap, tile: bool) -> Result<(), Error> {
+        if tile {
+            let width = stencil.get_data()?.width;
+            ensure!(
+                width % 32 == 0,
+                "Tiled stencil width {} isn't a multiple of 32",
+                width
+            );
+        }
+        pd_func_caller!(
+            (*self.0).setStencilImage,
+            stencil.inner.borrow().raw_bitmap,
+            tile as c_int
+        )?;
+        let state = draw_states().last_mut().unwrap();
+        state.stencil = Some(stencil.clone());
+        state.stencil_tiled = tile;
+        Ok(())
+    }
+
+    /// Only draws where the 8x8 pattern has set (white) pixels, repeated over the whole
+    /// drawing area; useful for dithered fades.  Only the first 8 bytes of the pattern, the
+    /// pixels, are used; the mask is ignored.
+    pub fn set_stencil_pattern(&self, pattern: &LCDPattern) -> Result<(), Error> {
+        let stencil = self.new_pattern_bitmap(pattern)?;
+        self.set_stencil_image(&stencil, true)
+    }
+
+    /// Removes the stencil, so drawing can go anywhere.
+    pub fn clear_stencil(&self) -> Result<(), Error> {
+        pd_func_caller!((*self.0).setStencil, ptr::null_mut())?;
+        let state = draw_states().last_mut().unwrap();
+        state.stencil = None;
+        state.stencil_tiled = false;
+        Ok(())
+    }
+
+    // Makes a 32x8 bitmap from the pixel rows of a pattern, repeating each row across it, since
+    // tiled stencils must be a multiple of 32 pixels wide.
+    fn new_pattern_bitmap(&self, pattern: &LCDPattern) -> Result<Bitmap, Error> {
+        let bitmap = self.new_bitmap(
+            ScreenSize::new(32, 8),
+            LCDColor::Solid(LCDSolidColor::kColorBlack),
+        )?;
+        let mut rowbytes = 0;
+        let mut data = ptr::null_mut();
+        pd_func_caller!(
+            (*self.0).getBitmapData,
+            bitmap.inner.borrow().raw_bitmap,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            &mut rowbytes,
+            ptr::null_mut(),
+            &mut data,
+        )?;
+        ensure!(!data.is_null(), "Null pointer returned from getBitmapData");
+        for (row, bits) in pattern[..8].iter().enumerate() {
+            let row = unsafe { slice::from_raw_parts_mut(data.add(row * rowbytes as usize), 4) };
+            row.fill(*bits);
+        }
+        Ok(bitmap)
+    }
+
+    /// Limits drawing to `rect`, in the same coordinates as other drawing (i.e. affected by the
+    /// draw offset).  Note that `clip` and `screen_clip` guards will replace it.
+    pub fn set_clip_rect(&self, rect: ScreenRect) -> Result<(), Error> {
+        pd_func_caller!(
+            (*self.0).setClipRect,
+            rect.origin.x,
+            rect.origin.y,
+            rect.size.width,
+            rect.size.height,
+        )
+    }
+
+    /// Limits drawing to `rect`, in screen coordinates (i.e. ignoring the draw offset).
+    pub fn set_screen_clip_rect(&self, rect: ScreenRect) -> Result<(), Error> {
+        pd_func_caller!(
+            (*self.0).setScreenClipRect,
+            rect.origin.x,
+            rect.origin.y,
+            rect.size.width,
+            rect.size.height,
+        )
+    }
+
+    /// Removes the clip rect, so drawing can go anywhere.
+    pub fn clear_clip_rect(&self) -> Result<(), Error> {
+        pd_func_caller!((*self.0).clearClipRect)
+    }
+
+    /// Limits drawing to `rect` until the returned guard is dropped, which restores the
+    /// previous clip.  Nested clips are intersected with the one outside them, so a panel
+    /// can't draw outside its parent.  `rect` is affected by the draw offset.
+    pub fn clip(&self, rect: ScreenRect) -> Result<ClipGuard, Error> {
+        self.push_clip(ClipRect::World(rect))
+    }
+
+    /// Like `clip`, but `rect` is in screen coordinates, ignoring the draw offset.
+    pub fn screen_clip(&self, rect: ScreenRect) -> Result<ClipGuard, Error> {
+        self.push_clip(ClipRect::Screen(rect))
+    }
+
+    /// Returns the innermost rect set with `clip` in the current context, if that's the current
+    /// kind of clip.
+    pub fn current_clip(&self) -> Option<ScreenRect> {
+        match current_clip_entry() {
+            Some(ClipRect::World(rect)) => Some(rect),
+            _ => None,
+        }
+    }
+
+    fn push_clip(&self, clip: ClipRect) -> Result<ClipGuard, Error> {
+        #[allow(static_mut_refs)]
+        let stack = unsafe { &mut CLIP_STACK };
+        // Only rects in the same coordinates can be intersected; otherwise the new one wins.
+        let empty = ScreenRect::new(clip_rect(clip).origin, ScreenSize::zero());
+        let clip = match (current_clip_entry(), clip) {
+            (Some(ClipRect::World(outer)), ClipRect::World(rect)) => {
+                ClipRect::World(outer.intersection(&rect).unwrap_or(empty))
+            }
+            (Some(ClipRect::Screen(outer)), ClipRect::Screen(rect)) => {
+                ClipRect::Screen(outer.intersection(&rect).unwrap_or(empty))
+            }
+            _ => clip,
+        };
+        self.apply_clip(Some(clip))?;
+        let depth = stack.len();
+        stack.push(clip);
+        Ok(ClipGuard { depth })
+    }
+
+    fn apply_clip(&self, clip: Option<ClipRect>) -> Result<(), Error> {
+        match clip {
+            Some(ClipRect::World(rect)) => self.set_clip_rect(rect),
+            Some(ClipRect::Screen(rect)) => self.set_screen_clip_rect(rect),
+            None => self.clear_clip_rect(),
+        }
     }
 
     pub fn new_bitmap(&self, size: ScreenSize, bg_color: LCDColor) -> Result<Bitmap, Error> {
@@ -577,6 +1117,42 @@ impl Graphics {
         Ok(Bitmap::new(raw_bitmap, true))
     }
 
+    /// Creates a mask from 8-bit alpha values, one per pixel in rows of `size.width`, that's
+    /// opaque wherever the alpha is at least `threshold`.  Useful with `Bitmap::set_mask` for
+    /// images converted at runtime from formats with an alpha channel.
+    pub fn new_mask_from_alpha(
+        &self,
+        size: ScreenSize,
+        alpha: &[u8],
+        threshold: u8,
+    ) -> Result<Bitmap, Error> {
+        ensure!(
+            size.width >= 0 && size.height >= 0,
+            "Negative size given to new_mask_from_alpha"
+        );
+        let (width, height) = (size.width as usize, size.height as usize);
+        ensure!(
+            alpha.len() == width * height,
+            "Expected {} alpha values for a {}x{} mask, got {}",
+            width * height,
+            width,
+            height,
+            alpha.len()
+        );
+        let mask = self.new_bitmap(size, LCDColor::Solid(LCDSolidColor::kColorBlack))?;
+        {
+            let mut pixels = mask.pixels()?;
+            for (y, row) in alpha.chunks_exact(width.max(1)).enumerate() {
+                for (x, value) in row.iter().enumerate() {
+                    if *value >= threshold {
+                        pixels.set(x, y, LCDSolidColor::kColorWhite);
+                    }
+                }
+            }
+        }
+        Ok(mask)
+    }
+
     pub fn load_bitmap(&self, path: &str) -> Result<Bitmap, Error> {
         let c_path = CString::new(path).map_err(Error::msg)?;
         let mut out_err: *const crankstart_sys::ctypes::c_char = ptr::null_mut();
@@ -595,6 +1171,12 @@ impl Graphics {
         }
     }
 
+    /// Makes a bitmap from the contents of a compiled `.pdi` file, e.g. one received over
+    /// serial; see `PdiImage`.
+    pub fn load_bitmap_from_data(&self, data: &[u8]) -> Result<Bitmap, Error> {
+        PdiImage::parse(data)?.to_bitmap()
+    }
+
     pub fn new_bitmap_table(&self, count: usize, size: ScreenSize) -> Result<BitmapTable, Error> {
         let raw_bitmap_table = pd_func_caller!(
             (*self.0).newBitmapTable,
@@ -625,10 +1207,20 @@ impl Graphics {
         }
     }
 
+    /// Makes a bitmap table from the contents of a compiled `.pdt` file; see `PdtTable`.
+    pub fn load_bitmap_table_from_data(&self, data: &[u8]) -> Result<BitmapTable, Error> {
+        PdtTable::parse(data)?.to_bitmap_table()
+    }
+
     pub fn clear(&self, color: LCDColor) -> Result<(), Error> {
         pd_func_caller!((*self.0).clear, color.into())
     }
 
+    /// Sets a single pixel in the current drawing context.
+    pub fn set_pixel(&self, point: ScreenPoint, color: LCDColor) -> Result<(), Error> {
+        pd_func_caller!((*self.0).setPixel, point.x, point.y, color.into())
+    }
+
     pub fn draw_line(
         &self,
         p1: ScreenPoint,
@@ -757,6 +1349,16 @@ impl Graphics {
         )
     }
 
+    /// Loads a `.pdv` video.
+    pub fn load_video(&self, path: &str) -> Result<VideoPlayer, Error> {
+        let c_path = CString::new(path).map_err(Error::msg)?;
+        let raw_video = unsafe { (*self.0).video };
+        ensure!(!raw_video.is_null(), "Null pointer for video subsystem");
+        let raw_player = pd_func_caller!((*raw_video).loadVideo, c_path.as_ptr())?;
+        ensure!(!raw_player.is_null(), "Failed to load video {}", path);
+        VideoPlayer::new(raw_video, raw_player)
+    }
+
     pub fn load_font(&self, path: &str) -> Result<Font, Error> {
         let c_path = CString::new(path).map_err(Error::msg)?;
         let mut out_err: *const crankstart_sys::ctypes::c_char = ptr::null_mut();
@@ -775,11 +1377,61 @@ impl Graphics {
         }
     }
 
+    /// Creates a font from the contents of an uncompressed .pft file without its 16-byte header,
+    /// the layout `FontBuilder::build` produces.  The font points into `data` rather than copying
+    /// it, so it keeps it.  `wide` should be true if the font has characters above U+1FFFF, as
+    /// the .pft header's flag says.
+    pub fn make_font_from_data(&self, mut data: Vec<u8>, wide: bool) -> Result<Font, Error> {
+        let font = pd_func_caller!(
+            (*self.0).makeFontFromData,
+            data.as_mut_ptr() as *mut crankstart_sys::LCDFontData,
+            wide as c_int
+        )?;
+        ensure!(
+            !font.is_null(),
+            "Null pointer returned from makeFontFromData"
+        );
+        // Moving the Vec doesn't move its heap buffer, which is what the font points into.
+        Font::with_data(font, Some(data))
+    }
+
+    /// Sets the font used by `draw_text`.  The font is kept alive until it's replaced or its
+    /// context ends.
     pub fn set_font(&self, font: &Font) -> Result<(), Error> {
-        pd_func_caller_log!((*self.0).setFont, font.0);
+        pd_func_caller_log!((*self.0).setFont, font.raw_font());
+        draw_states().last_mut().unwrap().font = Some(font.clone());
+        Ok(())
+    }
+
+    /// Returns the font set with `set_font` in the current context, or None for the system font.
+    pub fn get_font(&self) -> Option<Font> {
+        draw_states().last().and_then(|state| state.font.clone())
+    }
+
+    /// Sets the extra space between lines of text drawn with `draw_text`, in pixels.
+    pub fn set_text_leading(&self, leading: i32) -> Result<(), Error> {
+        pd_func_caller!((*self.0).setTextLeading, leading)?;
+        draw_states().last_mut().unwrap().text_leading = leading;
         Ok(())
     }
 
+    /// Returns the leading set with `set_text_leading` in the current context.
+    pub fn get_text_leading(&self) -> i32 {
+        draw_states().last().map_or(0, |state| state.text_leading)
+    }
+
+    /// Sets the extra space between characters drawn with `draw_text`, in pixels.
+    pub fn set_text_tracking(&self, tracking: i32) -> Result<(), Error> {
+        pd_func_caller!((*self.0).setTextTracking, tracking)?;
+        draw_states().last_mut().unwrap().text_tracking = tracking;
+        Ok(())
+    }
+
+    /// Returns the tracking set with `set_text_tracking` in the current context.
+    pub fn get_text_tracking(&self) -> i32 {
+        draw_states().last().map_or(0, |state| state.text_tracking)
+    }
+
     pub fn draw_text(&self, text: &str, position: ScreenPoint) -> Result<i32, Error> {
         let c_text = CString::new(text).map_err(Error::msg)?;
         pd_func_caller!(
@@ -796,7 +1448,7 @@ impl Graphics {
         let c_text = CString::new(text).map_err(Error::msg)?;
         pd_func_caller!(
             (*self.0).getTextWidth,
-            font.0,
+            font.raw_font(),
             c_text.as_ptr() as *const core::ffi::c_void,
             text.len(),
             PDStringEncoding::kUTF8Encoding,
@@ -805,7 +1457,7 @@ impl Graphics {
     }
 
     pub fn get_font_height(&self, font: &Font) -> Result<u8, Error> {
-        pd_func_caller!((*self.0).getFontHeight, font.0)
+        pd_func_caller!((*self.0).getFontHeight, font.raw_font())
     }
 
     pub fn get_system_text_width(&self, text: &str, tracking: i32) -> Result<i32, Error> {
@@ -820,3 +1472,39 @@ impl Graphics {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Bitmaps that aren't owned never reach the system when dropped.
+    fn mask_and_parent() -> (Bitmap, Bitmap) {
+        let parent = Bitmap::new(ptr::null_mut(), false);
+        let mask = Bitmap::with_source(
+            ptr::null_mut(),
+            false,
+            Some(BitmapSource::Bitmap(parent.inner.clone())),
+        );
+        (mask, parent)
+    }
+
+    #[test]
+    fn mask_borrow_holds_parent() {
+        let (mask, parent) = mask_and_parent();
+        let borrow = mask.borrow_mut().unwrap();
+        assert!(parent.borrow_mut().is_err());
+        drop(borrow);
+        assert!(parent.borrow_mut().is_ok());
+    }
+
+    #[test]
+    fn mask_borrow_fails_while_parent_borrowed() {
+        let (mask, parent) = mask_and_parent();
+        let borrow = parent.borrow_mut().unwrap();
+        assert!(mask.borrow_mut().is_err());
+        // The failed attempt leaves the mask itself free.
+        assert!(mask.inner.try_borrow_mut().is_ok());
+        drop(borrow);
+        assert!(mask.borrow_mut().is_ok());
+    }
+}
@@ -0,0 +1,510 @@
+//! Reading Playdate `.fnt` font descriptions and building fonts at runtime.
+//!
+//! A `.fnt` file lists each glyph's advance, in the same order as the cells of the image table
+//! holding the glyph images, along with kerning pairs and properties like tracking.
+//! `FontBuilder` combines those with glyph images into the font data `makeFontFromData` takes,
+//! which is laid out like an uncompressed `.pft` file without its 16-byte header.
+
+use super::{BitmapTable, Font, Graphics};
+use alloc::{
+    collections::BTreeMap,
+    string::{String, ToString},
+    vec::Vec,
+};
+use anyhow::{anyhow, bail, ensure, Error, Result};
+use core::convert::TryFrom;
+
+/// A glyph listed in a `.fnt` file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FntGlyph {
+    pub code: char,
+    /// How far to move right after drawing the glyph, in pixels.
+    pub advance: i32,
+}
+
+/// The parsed contents of a `.fnt` file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct FntFile {
+    /// Extra space between characters, in pixels.
+    pub tracking: i32,
+    /// Glyphs in file order, which is also the order of their images in the image table.
+    pub glyphs: Vec<FntGlyph>,
+    /// Kerning adjustments between pairs of characters, in pixels.
+    pub kerning: BTreeMap<(char, char), i32>,
+    /// Any other `key=value` properties, like the cell size or embedded image data.
+    pub properties: BTreeMap<String, String>,
+}
+
+impl FntFile {
+    /// Parses the text of a `.fnt` file.  Lines are a glyph and its advance, two glyphs and
+    /// their kerning, or `key=value` properties; lines starting with `--` are comments.  Glyphs
+    /// are written as the character itself, `space`, or `U+` and a hexadecimal code.
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let mut fnt = Self::default();
+        for (index, line) in text.lines().enumerate() {
+            fnt.parse_line(line.trim())
+                .map_err(|err| anyhow!("Line {} of .fnt file: {}", index + 1, err))?;
+        }
+        Ok(fnt)
+    }
+
+    fn parse_line(&mut self, line: &str) -> Result<(), Error> {
+        if line.is_empty() || line.starts_with("--") {
+            return Ok(());
+        }
+        let mut tokens = line.split_whitespace();
+        let (first, second) = (tokens.next(), tokens.next());
+        ensure!(tokens.next().is_none(), "Too many fields in '{}'", line);
+        match (first, second) {
+            (Some(property), None) => {
+                let (key, value) = property
+                    .split_once('=')
+                    .ok_or_else(|| anyhow!("Expected key=value, got '{}'", property))?;
+                if key == "tracking" {
+                    self.tracking = parse_number(value)?;
+                } else {
+                    self.properties.insert(key.to_string(), value.to_string());
+                }
+            }
+            (Some(glyphs), Some(value)) => {
+                let value = parse_number(value)?;
+                if let Some(code) = parse_glyph(glyphs) {
+                    ensure!(self.glyph(code).is_none(), "Glyph '{}' listed twice", code);
+                    self.glyphs.push(FntGlyph {
+                        code,
+                        advance: value,
+                    });
+                } else {
+                    let mut chars = glyphs.chars();
+                    match (chars.next(), chars.next(), chars.next()) {
+                        (Some(first), Some(second), None) => {
+                            self.kerning.insert((first, second), value);
+                        }
+                        _ => bail!("Unknown glyph '{}'", glyphs),
+                    }
+                }
+            }
+            _ => unreachable!("lines aren't empty"),
+        }
+        Ok(())
+    }
+
+    /// Returns the glyph for `code`, if the file lists it.
+    pub fn glyph(&self, code: char) -> Option<&FntGlyph> {
+        self.glyphs.iter().find(|glyph| glyph.code == code)
+    }
+
+    /// Returns the kerning adjustment between `first` and `second`, or 0 if there's none.
+    pub fn kerning(&self, first: char, second: char) -> i32 {
+        self.kerning.get(&(first, second)).copied().unwrap_or(0)
+    }
+}
+
+fn parse_number(value: &str) -> Result<i32, Error> {
+    value
+        .parse()
+        .map_err(|_| anyhow!("Expected a number, got '{}'", value))
+}
+
+// Returns the character a single glyph token stands for, or None if it isn't one.
+fn parse_glyph(token: &str) -> Option<char> {
+    if token == "space" {
+        return Some(' ');
+    }
+    if let Some(hex) = token
+        .strip_prefix("U+")
+        .or_else(|| token.strip_prefix("u+"))
+    {
+        return u32::from_str_radix(hex, 16).ok().and_then(char::from_u32);
+    }
+    let mut chars = token.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+/// A 1-bit glyph image, rows packed most significant bit first like bitmap data.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GlyphImage {
+    pub width: u16,
+    pub height: u16,
+    /// Bytes per row in `data` and `mask`.
+    pub rowbytes: usize,
+    /// Set bits are white.
+    pub data: Vec<u8>,
+    /// Set bits are opaque; None if the whole image is opaque.
+    pub mask: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Debug)]
+struct BuilderGlyph {
+    advance: u8,
+    image: GlyphImage,
+    kerning: BTreeMap<char, i8>,
+}
+
+/// Builds font data for `Graphics::make_font_from_data` from glyph images and metrics.  Only
+/// characters up to U+FFFF are supported, so the result is never a wide font.
+#[derive(Clone, Debug, Default)]
+pub struct FontBuilder {
+    tracking: i16,
+    glyphs: BTreeMap<char, BuilderGlyph>,
+}
+
+impl FontBuilder {
+    pub fn new(tracking: i16) -> Self {
+        Self {
+            tracking,
+            glyphs: BTreeMap::new(),
+        }
+    }
+
+    /// Adds the glyphs, kerning and tracking of a `.fnt` file, taking each glyph's image from
+    /// the cell of `images` at the same position.
+    pub fn from_fnt(fnt: &FntFile, images: &BitmapTable) -> Result<Self, Error> {
+        let tracking = i16::try_from(fnt.tracking).map_err(Error::msg)?;
+        let mut builder = Self::new(tracking);
+        for (index, glyph) in fnt.glyphs.iter().enumerate() {
+            let bitmap = images.get_bitmap(index)?;
+            let pixels = bitmap.pixels()?;
+            let width = u16::try_from(pixels.width()).map_err(Error::msg)?;
+            let height = u16::try_from(pixels.height()).map_err(Error::msg)?;
+            let rowbytes = pixels.width().div_ceil(8);
+            let data = pixels
+                .rows()
+                .flat_map(|row| row[..rowbytes].iter().copied())
+                .collect();
+            let mask = pixels.has_mask().then(|| {
+                pixels
+                    .mask_rows()
+                    .flat_map(|row| row[..rowbytes].iter().copied())
+                    .collect()
+            });
+            let image = GlyphImage {
+                width,
+                height,
+                rowbytes,
+                data,
+                mask,
+            };
+            builder.add_glyph(glyph.code, glyph.advance, image)?;
+        }
+        for (&(first, second), &amount) in &fnt.kerning {
+            builder.add_kerning(first, second, amount)?;
+        }
+        Ok(builder)
+    }
+
+    /// Adds a glyph, replacing any glyph already added for `code`.
+    pub fn add_glyph(&mut self, code: char, advance: i32, image: GlyphImage) -> Result<(), Error> {
+        ensure!(
+            (code as u32) <= 0xffff,
+            "Glyph U+{:X} is beyond U+FFFF",
+            code as u32
+        );
+        let advance = u8::try_from(advance)
+            .map_err(|_| anyhow!("Advance {} of glyph '{}' out of range", advance, code))?;
+        ensure!(
+            image.rowbytes * 8 >= image.width as usize,
+            "Row bytes too small for glyph '{}'",
+            code
+        );
+        let plane_len = image.rowbytes * image.height as usize;
+        ensure!(
+            image.data.len() == plane_len,
+            "Expected {} bytes of data for glyph '{}', got {}",
+            plane_len,
+            code,
+            image.data.len()
+        );
+        if let Some(mask) = &image.mask {
+            ensure!(
+                mask.len() == plane_len,
+                "Expected {} bytes of mask for glyph '{}', got {}",
+                plane_len,
+                code,
+                mask.len()
+            );
+        }
+        ensure!(
+            image.width <= u8::MAX as u16 && image.height <= u8::MAX as u16,
+            "Glyph '{}' is larger than 255 pixels",
+            code
+        );
+        let kerning = self
+            .glyphs
+            .remove(&code)
+            .map(|glyph| glyph.kerning)
+            .unwrap_or_default();
+        self.glyphs.insert(
+            code,
+            BuilderGlyph {
+                advance,
+                image,
+                kerning,
+            },
+        );
+        Ok(())
+    }
+
+    /// Sets the kerning between `first`, which must already have been added, and `second`.
+    pub fn add_kerning(&mut self, first: char, second: char, amount: i32) -> Result<(), Error> {
+        let amount = i8::try_from(amount)
+            .map_err(|_| anyhow!("Kerning {} of '{}{}' out of range", amount, first, second))?;
+        let glyph = self
+            .glyphs
+            .get_mut(&first)
+            .ok_or_else(|| anyhow!("Kerning for glyph '{}', which hasn't been added", first))?;
+        glyph.kerning.insert(second, amount);
+        Ok(())
+    }
+
+    /// Lays out the font data.  This doesn't call into the system, so it can run anywhere.
+    pub fn build(&self) -> Result<Vec<u8>, Error> {
+        let max_width = self.glyphs.values().map(|g| g.image.width).max();
+        let max_height = self.glyphs.values().map(|g| g.image.height).max();
+        let mut out = Vec::new();
+        out.push(max_width.unwrap_or(0) as u8);
+        out.push(max_height.unwrap_or(0) as u8);
+        out.extend_from_slice(&self.tracking.to_le_bytes());
+
+        let mut pages: BTreeMap<u8, Vec<(&char, &BuilderGlyph)>> = BTreeMap::new();
+        for (code, glyph) in &self.glyphs {
+            pages
+                .entry((*code as u32 >> 8) as u8)
+                .or_default()
+                .push((code, glyph));
+        }
+        out.extend_from_slice(&usage_flags(pages.keys().copied()));
+
+        let page_data = pages
+            .values()
+            .map(|glyphs| build_page(glyphs))
+            .collect::<Result<Vec<_>, Error>>()?;
+        let mut offset = 0u32;
+        for page in &page_data {
+            out.extend_from_slice(&offset.to_le_bytes());
+            offset += page.len() as u32;
+        }
+        for page in page_data {
+            out.extend_from_slice(&page);
+        }
+        Ok(out)
+    }
+
+    /// Builds the font data and makes a `Font` from it.
+    pub fn make_font(&self) -> Result<Font, Error> {
+        Graphics::get().make_font_from_data(self.build()?, false)
+    }
+}
+
+// Returns 256 bits, least significant first, with the given bits set.
+fn usage_flags(indices: impl Iterator<Item = u8>) -> [u8; 32] {
+    let mut flags = [0; 32];
+    for index in indices {
+        flags[index as usize / 8] |= 1 << (index % 8);
+    }
+    flags
+}
+
+fn pad_to_4(out: &mut Vec<u8>) {
+    while !out.len().is_multiple_of(4) {
+        out.push(0);
+    }
+}
+
+fn build_page(glyphs: &[(&char, &BuilderGlyph)]) -> Result<Vec<u8>, Error> {
+    let mut out = Vec::from([0, 0, 0]);
+    // A full page of 256 glyphs wraps to 0, as in .pft files.
+    out.push(glyphs.len() as u8);
+    out.extend_from_slice(&usage_flags(
+        glyphs.iter().map(|(code, _)| **code as u32 as u8),
+    ));
+
+    let glyph_data = glyphs
+        .iter()
+        .map(|(code, glyph)| build_glyph(**code, glyph))
+        .collect::<Result<Vec<_>, Error>>()?;
+    // Glyph offsets are 16 bits, which limits how much glyph data a page can hold.
+    let mut offset = 0usize;
+    for ((code, _), glyph) in glyphs.iter().zip(&glyph_data) {
+        let glyph_offset = u16::try_from(offset).map_err(|_| {
+            anyhow!(
+                "Too much glyph data in page {:X}xx at glyph '{}'",
+                **code as u32 >> 8,
+                code
+            )
+        })?;
+        out.extend_from_slice(&glyph_offset.to_le_bytes());
+        offset += glyph.len();
+    }
+    pad_to_4(&mut out);
+    for glyph in glyph_data {
+        out.extend_from_slice(&glyph);
+    }
+    Ok(out)
+}
+
+fn build_glyph(code: char, glyph: &BuilderGlyph) -> Result<Vec<u8>, Error> {
+    let page = code as u32 >> 8;
+    // Kerning with characters in the same page fits in two bytes; the rest need four.
+    let (short, long): (Vec<_>, Vec<_>) = glyph
+        .kerning
+        .iter()
+        .partition(|(next, _)| **next as u32 >> 8 == page);
+    let short_len = u8::try_from(short.len())
+        .map_err(|_| anyhow!("Too many kerning pairs for glyph '{}'", code))?;
+    let mut out = Vec::from([glyph.advance, short_len]);
+    out.extend_from_slice(&(long.len() as u16).to_le_bytes());
+    for (next, amount) in short {
+        out.push(*next as u32 as u8);
+        out.push(*amount as u8);
+    }
+    pad_to_4(&mut out);
+    for (next, amount) in long {
+        let entry = (*next as u32 & 0x00ff_ffff) | ((*amount as u8 as u32) << 24);
+        out.extend_from_slice(&entry.to_le_bytes());
+    }
+
+    // The image, as an uncropped bitmap cell.
+    let image = &glyph.image;
+    let rowbytes = u16::try_from(image.rowbytes)
+        .map_err(|_| anyhow!("Row bytes too large for glyph '{}'", code))?;
+    let header = [
+        image.width,
+        image.height,
+        rowbytes,
+        0,
+        0,
+        0,
+        0,
+        image.mask.is_some() as u16,
+    ];
+    for value in header {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    out.extend_from_slice(&image.data);
+    if let Some(mask) = &image.mask {
+        out.extend_from_slice(mask);
+    }
+    pad_to_4(&mut out);
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(width: u16, height: u16, data: &[u8]) -> GlyphImage {
+        GlyphImage {
+            width,
+            height,
+            rowbytes: (width as usize).div_ceil(8),
+            data: data.to_vec(),
+            mask: None,
+        }
+    }
+
+    #[test]
+    fn parses_fnt() {
+        let fnt = FntFile::parse(
+            "-- a comment\n\
+             tracking=2\n\
+             cellWidth=8\n\
+             \n\
+             space 3\n\
+             A 6\n\
+             U+00E9 5\n\
+             AV -2\n",
+        )
+        .unwrap();
+        assert_eq!(fnt.tracking, 2);
+        assert_eq!(
+            fnt.properties.get("cellWidth").map(String::as_str),
+            Some("8")
+        );
+        let codes: Vec<(char, i32)> = fnt.glyphs.iter().map(|g| (g.code, g.advance)).collect();
+        assert_eq!(codes, [(' ', 3), ('A', 6), ('é', 5)]);
+        assert_eq!(fnt.kerning('A', 'V'), -2);
+        assert_eq!(fnt.kerning('V', 'A'), 0);
+    }
+
+    #[test]
+    fn rejects_bad_fnt_lines() {
+        assert!(FntFile::parse("A 6\nA 7").is_err());
+        assert!(FntFile::parse("A six").is_err());
+        assert!(FntFile::parse("ABC 1").is_err());
+        assert!(FntFile::parse("A 1 2").is_err());
+        assert!(FntFile::parse("cellWidth").is_err());
+    }
+
+    #[test]
+    fn builds_font_layout() {
+        let mut builder = FontBuilder::new(1);
+        builder
+            .add_glyph('A', 5, image(8, 2, &[0xff, 0x00]))
+            .unwrap();
+        builder.add_kerning('A', 'B', -1).unwrap();
+        builder.add_kerning('A', '\u{100}', 2).unwrap();
+        let data = builder.build().unwrap();
+
+        let mut expected = Vec::from([8, 2, 1, 0]);
+        // Only page 0 is used, and it starts right after the page offsets.
+        let mut pages = [0; 32];
+        pages[0] = 1;
+        expected.extend_from_slice(&pages);
+        expected.extend_from_slice(&[0, 0, 0, 0]);
+        // The page: its glyph count, which glyphs it has, and their offsets padded to 4 bytes.
+        expected.extend_from_slice(&[0, 0, 0, 1]);
+        let mut glyphs = [0; 32];
+        glyphs[0x41 / 8] = 1 << (0x41 % 8);
+        expected.extend_from_slice(&glyphs);
+        expected.extend_from_slice(&[0, 0, 0, 0]);
+        // The glyph: advance, kerning counts, short and long kerning, then the image cell.
+        expected.extend_from_slice(&[5, 1, 1, 0]);
+        expected.extend_from_slice(&[0x42, 0xff, 0, 0]);
+        expected.extend_from_slice(&[0x00, 0x01, 0x00, 0x02]);
+        expected.extend_from_slice(&[8, 0, 2, 0, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        expected.extend_from_slice(&[0xff, 0x00, 0, 0]);
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn offsets_later_pages() {
+        let mut builder = FontBuilder::new(0);
+        builder.add_glyph('a', 4, image(1, 1, &[0x80])).unwrap();
+        builder
+            .add_glyph('\u{101}', 4, image(1, 1, &[0x80]))
+            .unwrap();
+        let data = builder.build().unwrap();
+        assert_eq!(data[4], 0b11);
+        let offsets = &data[36..44];
+        // Page 0 is its 40 byte header plus a 24 byte glyph.
+        assert_eq!(offsets, [0, 0, 0, 0, 64, 0, 0, 0]);
+        assert_eq!(data.len(), 44 + 64 * 2);
+    }
+
+    #[test]
+    fn rejects_pages_too_large_for_glyph_offsets() {
+        let mut builder = FontBuilder::new(0);
+        let big = image(255, 255, &[0; 32 * 255]);
+        for code in 'a'..='j' {
+            builder.add_glyph(code, 255, big.clone()).unwrap();
+        }
+        assert!(builder.build().is_err());
+        builder.glyphs.remove(&'j');
+        assert!(builder.build().is_ok());
+    }
+
+    #[test]
+    fn rejects_bad_glyphs() {
+        let mut builder = FontBuilder::new(0);
+        assert!(builder
+            .add_glyph('\u{10000}', 1, image(1, 1, &[0]))
+            .is_err());
+        assert!(builder.add_glyph('a', 256, image(1, 1, &[0])).is_err());
+        assert!(builder.add_glyph('a', 1, image(8, 2, &[0])).is_err());
+        assert!(builder.add_kerning('a', 'b', 1).is_err());
+    }
+}
@@ -0,0 +1,171 @@
+use crate::{pd_func_caller, system::System};
+
+use super::{Bitmap, BitmapSource, Graphics};
+use alloc::{rc::Rc, vec::Vec};
+use anyhow::{ensure, Error, Result};
+use core::ptr;
+
+/// A font, from `Graphics::load_font` or `Graphics::make_font_from_data`.
+// Really a wrapper around an Rc clone of the internal structure; derive Clone so it's easy to get
+// another Rc reference.  We use Rc so we don't free the font while it's set in a drawing context
+// or one of its glyphs is in use.
+#[derive(Clone, Debug)]
+pub struct Font {
+    inner: Rc<FontInner>,
+}
+
+#[derive(Debug)]
+struct FontInner {
+    raw_font: *mut crankstart_sys::LCDFont,
+    // Fonts made from data point into it rather than copying it, so it has to outlive them.
+    _data: Option<Vec<u8>>,
+}
+
+impl Drop for FontInner {
+    fn drop(&mut self) {
+        // The system has no freeFont; fonts are allocated with its realloc.
+        System::get().realloc(self.raw_font as *mut core::ffi::c_void, 0);
+    }
+}
+
+impl Font {
+    /// Takes ownership of a font from the system, which is freed when the last clone is dropped.
+    pub(crate) fn new(font: *mut crankstart_sys::LCDFont) -> Result<Self, Error> {
+        Self::with_data(font, None)
+    }
+
+    pub(crate) fn with_data(
+        font: *mut crankstart_sys::LCDFont,
+        data: Option<Vec<u8>>,
+    ) -> Result<Self, Error> {
+        ensure!(!font.is_null(), "Null pointer passed to Font::new");
+        Ok(Self {
+            inner: Rc::new(FontInner {
+                raw_font: font,
+                _data: data,
+            }),
+        })
+    }
+
+    pub(crate) fn raw_font(&self) -> *mut crankstart_sys::LCDFont {
+        self.inner.raw_font
+    }
+
+    pub fn get_height(&self) -> Result<u8, Error> {
+        Graphics::get().get_font_height(self)
+    }
+
+    /// Returns the page of glyphs containing `c`, or None if the font has no such page.  Each
+    /// page holds 256 consecutive characters.
+    pub fn get_page(&self, c: char) -> Result<Option<FontPage>, Error> {
+        let raw_page = pd_func_caller!(
+            (*Graphics::get_ptr()).getFontPage,
+            self.raw_font(),
+            c as u32
+        )?;
+        if raw_page.is_null() {
+            return Ok(None);
+        }
+        Ok(Some(FontPage {
+            font: self.clone(),
+            raw_page,
+        }))
+    }
+
+    /// Returns the glyph for `c`, or None if the font doesn't have it.
+    pub fn get_glyph(&self, c: char) -> Result<Option<FontGlyph>, Error> {
+        match self.get_page(c)? {
+            Some(page) => page.get_glyph(c),
+            None => Ok(None),
+        }
+    }
+
+    /// Returns the kerning adjustment between `c` and `next`, in pixels, to be added to the
+    /// advance of `c`.
+    pub fn get_kerning(&self, c: char, next: char) -> Result<i32, Error> {
+        match self.get_glyph(c)? {
+            Some(glyph) => glyph.get_kerning(next),
+            None => Ok(0),
+        }
+    }
+}
+
+/// A page of 256 consecutive characters in a `Font`.  Holds the font, so it can't be freed while
+/// the page is in use.
+#[derive(Clone, Debug)]
+pub struct FontPage {
+    font: Font,
+    raw_page: *mut crankstart_sys::LCDFontPage,
+}
+
+impl FontPage {
+    /// Returns the glyph for `c`, or None if the page doesn't have it.
+    pub fn get_glyph(&self, c: char) -> Result<Option<FontGlyph>, Error> {
+        let mut raw_bitmap = ptr::null_mut();
+        let mut advance = 0;
+        let raw_glyph = pd_func_caller!(
+            (*Graphics::get_ptr()).getPageGlyph,
+            self.raw_page,
+            c as u32,
+            &mut raw_bitmap,
+            &mut advance
+        )?;
+        if raw_glyph.is_null() {
+            return Ok(None);
+        }
+        Ok(Some(FontGlyph {
+            _font: self.font.clone(),
+            raw_glyph,
+            // The bitmap belongs to the font, so it holds the font to keep it alive.
+            bitmap: (!raw_bitmap.is_null()).then(|| {
+                Bitmap::with_source(
+                    raw_bitmap,
+                    false,
+                    Some(BitmapSource::Font(self.font.clone())),
+                )
+            }),
+            advance,
+            code: c,
+        }))
+    }
+}
+
+/// A single character of a `Font`: its image, and how far to move before drawing the next one.
+/// Holds the font, so it can't be freed while the glyph is in use.
+#[derive(Clone, Debug)]
+pub struct FontGlyph {
+    _font: Font,
+    raw_glyph: *mut crankstart_sys::LCDFontGlyph,
+    bitmap: Option<Bitmap>,
+    advance: i32,
+    code: char,
+}
+
+impl FontGlyph {
+    /// Returns the glyph's image, or None for glyphs with nothing to draw, like space.  The
+    /// image is part of the font, and keeps the font alive while it's in use.
+    pub fn get_bitmap(&self) -> Option<&Bitmap> {
+        self.bitmap.as_ref()
+    }
+
+    /// Returns how far to move right after drawing the glyph, in pixels, before tracking and
+    /// kerning.
+    pub fn get_advance(&self) -> i32 {
+        self.advance
+    }
+
+    pub fn get_code(&self) -> char {
+        self.code
+    }
+
+    /// Returns the kerning adjustment between this glyph and `next`, in pixels, to be added to
+    /// the advance.
+    pub fn get_kerning(&self, next: char) -> Result<i32, Error> {
+        pd_func_caller!(
+            (*Graphics::get_ptr()).getGlyphKerning,
+            self.raw_glyph,
+            self.code as u32,
+            next as u32
+        )
+    }
+}
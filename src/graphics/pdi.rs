@@ -0,0 +1,494 @@
+//! Reading and writing compiled `.pdi` images and `.pdt` image tables without the system loader.
+//!
+//! Both start with a 12-byte ident and a u32 of flags; if the high flag bit is set, the rest is
+//! a 16-byte header (the decompressed length first) and zlib-compressed data.  A `.pdi` file
+//! then holds one image cell.  A `.pdt` file holds a u16 cell count and a u16 count of cells
+//! per row, a u32 end offset for each cell, then the cells, each padded to 4 bytes.
+//!
+//! A cell is eight u16s: the width and height of the stored pixels, the bytes per row, how many
+//! transparent columns and rows were cropped from the left, right, top and bottom, and flags,
+//! where bit 0 means a mask follows the data.
+
+use crate::geometry::ScreenSize;
+
+use super::{Bitmap, BitmapTable, Graphics, LCDColor, LCDSolidColor};
+use alloc::vec::Vec;
+use anyhow::{anyhow, ensure, Error, Result};
+use core::convert::TryFrom;
+use miniz_oxide::{deflate::compress_to_vec_zlib, inflate::decompress_to_vec_zlib_with_limit};
+
+const PDI_IDENT: &[u8] = b"Playdate IMG";
+const PDT_IDENT: &[u8] = b"Playdate IMT";
+const COMPRESSED: u32 = 0x8000_0000;
+const CELL_HEADER_LEN: usize = 16;
+const CELL_HAS_MASK: u16 = 1;
+// The most bytes a parsed image's pixels may take, so a corrupt cell can't claim a size that
+// runs the device out of memory.
+pub(super) const MAX_PLANE_LEN: usize = 4 * 1024 * 1024;
+
+/// A 1-bit image, rows packed most significant bit first like bitmap data, as stored in a
+/// `.pdi` file or a cell of a `.pdt` file.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PdiImage {
+    pub width: u16,
+    pub height: u16,
+    /// Bytes per row in `data` and `mask`.
+    pub rowbytes: usize,
+    /// Set bits are white.
+    pub data: Vec<u8>,
+    /// Set bits are opaque; None if the whole image is opaque.
+    pub mask: Option<Vec<u8>>,
+}
+
+impl PdiImage {
+    /// Makes an opaque black image.
+    pub fn new(width: u16, height: u16) -> Self {
+        let rowbytes = (width as usize).div_ceil(8);
+        Self {
+            width,
+            height,
+            rowbytes,
+            data: alloc::vec![0; rowbytes * height as usize],
+            mask: None,
+        }
+    }
+
+    /// Parses the contents of a `.pdi` file.
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        let body = read_body(bytes, PDI_IDENT, ".pdi")?;
+        Self::parse_cell(&body)
+    }
+
+    /// Lays out the image as a `.pdi` file, zlib-compressed if `compressed`.
+    pub fn encode(&self, compressed: bool) -> Result<Vec<u8>, Error> {
+        let mut body = Vec::new();
+        self.write_cell(&mut body)?;
+        let header = [self.width as u32, self.height as u32, 0];
+        Ok(write_file(PDI_IDENT, &body, compressed, header))
+    }
+
+    /// Copies the pixels and mask of `bitmap`.
+    pub fn from_bitmap(bitmap: &Bitmap) -> Result<Self, Error> {
+        let pixels = bitmap.pixels()?;
+        let width = u16::try_from(pixels.width()).map_err(Error::msg)?;
+        let height = u16::try_from(pixels.height()).map_err(Error::msg)?;
+        let rowbytes = pixels.width().div_ceil(8);
+        let data = pixels
+            .rows()
+            .flat_map(|row| row[..rowbytes].iter().copied())
+            .collect();
+        let mask = pixels.has_mask().then(|| {
+            pixels
+                .mask_rows()
+                .flat_map(|row| row[..rowbytes].iter().copied())
+                .collect()
+        });
+        Ok(Self {
+            width,
+            height,
+            rowbytes,
+            data,
+            mask,
+        })
+    }
+
+    /// Makes a new bitmap of the image.
+    pub fn to_bitmap(&self) -> Result<Bitmap, Error> {
+        let graphics = Graphics::get();
+        let bitmap =
+            graphics.new_bitmap(self.size(), LCDColor::Solid(LCDSolidColor::kColorBlack))?;
+        self.copy_into(&bitmap)?;
+        Ok(bitmap)
+    }
+
+    pub fn size(&self) -> ScreenSize {
+        ScreenSize::new(self.width as i32, self.height as i32)
+    }
+
+    // Writes the pixels, and the mask if any, into a bitmap of the same size.
+    fn copy_into(&self, bitmap: &Bitmap) -> Result<(), Error> {
+        bitmap.pixels()?.copy_from_packed(&self.data, self.rowbytes);
+        if let Some(mask) = &self.mask {
+            let mask_bitmap = Graphics::get()
+                .new_bitmap(self.size(), LCDColor::Solid(LCDSolidColor::kColorBlack))?;
+            mask_bitmap.pixels()?.copy_from_packed(mask, self.rowbytes);
+            bitmap.set_mask(&mask_bitmap)?;
+        }
+        Ok(())
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        ensure!(
+            self.rowbytes * 8 >= self.width as usize && self.rowbytes <= u16::MAX as usize,
+            "Row bytes {} don't fit image width {}",
+            self.rowbytes,
+            self.width
+        );
+        let plane_len = self.rowbytes * self.height as usize;
+        ensure!(
+            self.data.len() == plane_len,
+            "Expected {} bytes of image data, got {}",
+            plane_len,
+            self.data.len()
+        );
+        if let Some(mask) = &self.mask {
+            ensure!(
+                mask.len() == plane_len,
+                "Expected {} bytes of image mask, got {}",
+                plane_len,
+                mask.len()
+            );
+        }
+        Ok(())
+    }
+
+    // Parses a cell, undoing any cropping.
+    fn parse_cell(bytes: &[u8]) -> Result<Self, Error> {
+        ensure!(
+            bytes.len() >= CELL_HEADER_LEN,
+            "Truncated image cell header"
+        );
+        let [width, height, stride, left, right, top, bottom, flags] =
+            core::array::from_fn(|index| read_u16(bytes, index * 2));
+        let stride = stride as usize;
+        ensure!(
+            stride * 8 >= width as usize,
+            "Image cell row bytes {} don't fit width {}",
+            stride,
+            width
+        );
+        // Checked before anything is computed from it, as it can overflow a 32-bit usize.
+        let plane_len = stride
+            .checked_mul(height as usize)
+            .filter(|&plane_len| plane_len <= MAX_PLANE_LEN);
+        let plane_len = plane_len.ok_or_else(|| {
+            anyhow!(
+                "Image cell of {} row bytes by {} is too large",
+                stride,
+                height
+            )
+        })?;
+        let has_mask = flags & CELL_HAS_MASK != 0;
+        let len = CELL_HEADER_LEN + plane_len * if has_mask { 2 } else { 1 };
+        ensure!(bytes.len() >= len, "Truncated image cell data");
+        let data = &bytes[CELL_HEADER_LEN..CELL_HEADER_LEN + plane_len];
+        let mask = has_mask.then(|| &bytes[CELL_HEADER_LEN + plane_len..len]);
+
+        let full_width = width as u32 + left as u32 + right as u32;
+        let full_height = height as u32 + top as u32 + bottom as u32;
+        let full_width = u16::try_from(full_width).map_err(Error::msg)?;
+        let full_height = u16::try_from(full_height).map_err(Error::msg)?;
+        ensure!(
+            (full_width as usize).div_ceil(8) * full_height as usize <= MAX_PLANE_LEN,
+            "Image cell of {}x{} is too large",
+            full_width,
+            full_height
+        );
+        let mut image = Self::new(full_width, full_height);
+        let cropped = full_width != width || full_height != height;
+        if has_mask || cropped {
+            // Cropped edges are transparent; the stored area is opaque unless masked.
+            image.mask = Some(alloc::vec![0; image.data.len()]);
+        }
+        for y in 0..height as usize {
+            for x in 0..width as usize {
+                let (byte, bit) = (y * stride + x / 8, 0x80 >> (x % 8));
+                let (dest_x, dest_y) = (x + left as usize, y + top as usize);
+                if data[byte] & bit != 0 {
+                    set_bit(&mut image.data, image.rowbytes, dest_x, dest_y);
+                }
+                let opaque = mask.is_none_or(|mask| mask[byte] & bit != 0);
+                if let (Some(image_mask), true) = (&mut image.mask, opaque) {
+                    set_bit(image_mask, image.rowbytes, dest_x, dest_y);
+                }
+            }
+        }
+        Ok(image)
+    }
+
+    // Writes an uncropped cell.
+    fn write_cell(&self, out: &mut Vec<u8>) -> Result<(), Error> {
+        self.check()?;
+        let header = [
+            self.width,
+            self.height,
+            self.rowbytes as u16,
+            0,
+            0,
+            0,
+            0,
+            if self.mask.is_some() {
+                CELL_HAS_MASK
+            } else {
+                0
+            },
+        ];
+        for value in header {
+            out.extend_from_slice(&value.to_le_bytes());
+        }
+        out.extend_from_slice(&self.data);
+        if let Some(mask) = &self.mask {
+            out.extend_from_slice(mask);
+        }
+        Ok(())
+    }
+}
+
+/// The cells of a `.pdt` image table.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct PdtTable {
+    pub cells: Vec<PdiImage>,
+    /// How many cells make up each row of the table's grid; the cell count for a sequence.
+    pub cells_per_row: u16,
+}
+
+impl PdtTable {
+    /// Parses the contents of a `.pdt` file.
+    pub fn parse(bytes: &[u8]) -> Result<Self, Error> {
+        let body = read_body(bytes, PDT_IDENT, ".pdt")?;
+        ensure!(body.len() >= 4, "Truncated .pdt table header");
+        let count = read_u16(&body, 0) as usize;
+        let cells_per_row = read_u16(&body, 2);
+        let table_end = 4 + count * 4;
+        ensure!(body.len() >= table_end, "Truncated .pdt cell table");
+        let cell_data = &body[table_end..];
+        let mut cells = Vec::with_capacity(count);
+        let mut start = 0;
+        for index in 0..count {
+            let end = read_u32(&body, 4 + index * 4) as usize;
+            let cell = cell_data
+                .get(start..end)
+                .ok_or_else(|| anyhow!("Cell {} of .pdt file out of range", index))?;
+            cells.push(PdiImage::parse_cell(cell)?);
+            start = end;
+        }
+        Ok(Self {
+            cells,
+            cells_per_row,
+        })
+    }
+
+    /// Lays out the table as a `.pdt` file, zlib-compressed if `compressed`.
+    pub fn encode(&self, compressed: bool) -> Result<Vec<u8>, Error> {
+        let count = u16::try_from(self.cells.len()).map_err(Error::msg)?;
+        let mut cell_data = Vec::new();
+        let mut ends = Vec::with_capacity(self.cells.len());
+        for cell in &self.cells {
+            cell.write_cell(&mut cell_data)?;
+            while !cell_data.len().is_multiple_of(4) {
+                cell_data.push(0);
+            }
+            ends.push(cell_data.len() as u32);
+        }
+        let mut body = Vec::new();
+        body.extend_from_slice(&count.to_le_bytes());
+        body.extend_from_slice(&self.cells_per_row.to_le_bytes());
+        for end in ends {
+            body.extend_from_slice(&end.to_le_bytes());
+        }
+        body.extend_from_slice(&cell_data);
+        let first = self
+            .cells
+            .first()
+            .map_or(ScreenSize::zero(), |cell| cell.size());
+        let header = [first.width as u32, first.height as u32, count as u32];
+        Ok(write_file(PDT_IDENT, &body, compressed, header))
+    }
+
+    /// Copies the cells of `table`.
+    pub fn from_bitmap_table(table: &BitmapTable) -> Result<Self, Error> {
+        let (count, cells_wide) = table.get_info()?;
+        let cells = (0..count)
+            .map(|index| PdiImage::from_bitmap(&table.get_bitmap(index)?))
+            .collect::<Result<_, Error>>()?;
+        Ok(Self {
+            cells,
+            cells_per_row: u16::try_from(cells_wide).map_err(Error::msg)?,
+        })
+    }
+
+    /// Makes a new bitmap table of the cells, which must all be the same size.
+    pub fn to_bitmap_table(&self) -> Result<BitmapTable, Error> {
+        let size = self
+            .cells
+            .first()
+            .map_or(ScreenSize::zero(), |cell| cell.size());
+        ensure!(
+            self.cells.iter().all(|cell| cell.size() == size),
+            "Cells of a bitmap table must all be the same size"
+        );
+        let table = Graphics::get().new_bitmap_table(self.cells.len(), size)?;
+        for (index, cell) in self.cells.iter().enumerate() {
+            cell.copy_into(&table.get_bitmap(index)?)?;
+        }
+        Ok(table)
+    }
+}
+
+// Checks the ident and returns the data after the flags, decompressed if needed.
+fn read_body(bytes: &[u8], ident: &[u8], kind: &str) -> Result<Vec<u8>, Error> {
+    ensure!(
+        bytes.len() >= ident.len() + 4 && bytes.starts_with(ident),
+        "Not a {} file",
+        kind
+    );
+    let flags = read_u32(bytes, ident.len());
+    let body = &bytes[ident.len() + 4..];
+    if flags & COMPRESSED == 0 {
+        return Ok(body.to_vec());
+    }
+    ensure!(body.len() >= 16, "Truncated {} compression header", kind);
+    let expected = read_u32(body, 0) as usize;
+    // Stops at the expected length, so bad data can't make it allocate more.
+    let data = decompress_to_vec_zlib_with_limit(&body[16..], expected)
+        .map_err(|err| anyhow!("Decompressing {} file: {:?}", kind, err.status))?;
+    ensure!(
+        data.len() == expected,
+        "{} file decompressed to {} bytes, expected {}",
+        kind,
+        data.len(),
+        expected
+    );
+    Ok(data)
+}
+
+// The compression header is the decompressed length followed by `header`.
+fn write_file(ident: &[u8], body: &[u8], compressed: bool, header: [u32; 3]) -> Vec<u8> {
+    let mut out = Vec::from(ident);
+    if !compressed {
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend_from_slice(body);
+        return out;
+    }
+    out.extend_from_slice(&COMPRESSED.to_le_bytes());
+    out.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    for value in header {
+        out.extend_from_slice(&value.to_le_bytes());
+    }
+    out.extend_from_slice(&compress_to_vec_zlib(body, 9));
+    out
+}
+
+fn set_bit(plane: &mut [u8], rowbytes: usize, x: usize, y: usize) {
+    plane[y * rowbytes + x / 8] |= 0x80 >> (x % 8);
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn masked_image() -> PdiImage {
+        PdiImage {
+            width: 10,
+            height: 2,
+            rowbytes: 2,
+            data: Vec::from([0xff, 0xc0, 0x00, 0x40]),
+            mask: Some(Vec::from([0xf0, 0x00, 0x0f, 0xc0])),
+        }
+    }
+
+    // A cell header followed by its planes.
+    fn cell(header: [u16; 8], planes: &[u8]) -> Vec<u8> {
+        let mut out: Vec<u8> = header
+            .iter()
+            .flat_map(|value| value.to_le_bytes())
+            .collect();
+        out.extend_from_slice(planes);
+        out
+    }
+
+    #[test]
+    fn round_trips_images() {
+        for image in [masked_image(), PdiImage::new(3, 5)] {
+            for compressed in [false, true] {
+                let data = image.encode(compressed).unwrap();
+                assert_eq!(data[15] & 0x80 != 0, compressed);
+                assert_eq!(PdiImage::parse(&data).unwrap(), image);
+            }
+        }
+    }
+
+    #[test]
+    fn uncrops_cells() {
+        // A 2x1 white pixel pair cropped 1 from the left, 5 from the right and 1 from the top.
+        let mut data = PDI_IDENT.to_vec();
+        data.extend_from_slice(&0u32.to_le_bytes());
+        data.extend_from_slice(&cell([2, 1, 1, 1, 5, 1, 0, 0], &[0xc0]));
+        let image = PdiImage::parse(&data).unwrap();
+        assert_eq!((image.width, image.height, image.rowbytes), (8, 2, 1));
+        assert_eq!(image.data, [0x00, 0x60]);
+        assert_eq!(image.mask, Some(Vec::from([0x00, 0x60])));
+    }
+
+    #[test]
+    fn round_trips_tables() {
+        let table = PdtTable {
+            cells: Vec::from([masked_image(), PdiImage::new(10, 2), masked_image()]),
+            cells_per_row: 2,
+        };
+        for compressed in [false, true] {
+            let data = table.encode(compressed).unwrap();
+            assert_eq!(PdtTable::parse(&data).unwrap(), table);
+        }
+    }
+
+    #[test]
+    fn rejects_bad_images() {
+        let data = masked_image().encode(false).unwrap();
+        assert!(PdiImage::parse(&data[..data.len() - 1]).is_err());
+        assert!(PdiImage::parse(b"Playdate IMT\0\0\0\0").is_err());
+
+        // Row bytes too small for the width.
+        let mut bad = data.clone();
+        bad[20] = 1;
+        assert!(PdiImage::parse(&bad).is_err());
+
+        // Cropping that adds up to an enormous image, with no pixels to back it.
+        let mut huge = PDI_IDENT.to_vec();
+        huge.extend_from_slice(&0u32.to_le_bytes());
+        huge.extend_from_slice(&cell([0, 0, 0, 0, 60000, 0, 60000, 0], &[]));
+        assert!(PdiImage::parse(&huge).is_err());
+
+        // Stored planes too large to have their length computed on 32-bit targets.
+        let mut huge = PDI_IDENT.to_vec();
+        huge.extend_from_slice(&0u32.to_le_bytes());
+        huge.extend_from_slice(&cell([8, 0xffff, 0xffff, 0, 0, 0, 0, CELL_HAS_MASK], &[]));
+        let err = PdiImage::parse(&huge).unwrap_err();
+        assert!(alloc::format!("{}", err).contains("too large"));
+    }
+
+    #[test]
+    fn rejects_wrong_decompressed_lengths() {
+        let mut data = masked_image().encode(true).unwrap();
+        for expected in [1u32, 1000] {
+            data[16..20].copy_from_slice(&expected.to_le_bytes());
+            assert!(PdiImage::parse(&data).is_err());
+        }
+    }
+
+    #[test]
+    fn rejects_bad_tables() {
+        let table = PdtTable {
+            cells: Vec::from([PdiImage::new(8, 1)]),
+            cells_per_row: 1,
+        };
+        let mut data = table.encode(false).unwrap();
+        // Point the cell's end past the data.
+        data[20..24].copy_from_slice(&100u32.to_le_bytes());
+        assert!(PdtTable::parse(&data).is_err());
+        assert!(PdtTable::parse(&data[..18]).is_err());
+    }
+}
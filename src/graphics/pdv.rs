@@ -0,0 +1,444 @@
+//! Reading `.pdv` video files without the system video player.
+//!
+//! A `.pdv` file is a 28-byte header (the ident `Playdate VID`, the frame count, frame rate and
+//! frame size), a table of frame offsets and types, then each frame's zlib-compressed 1-bit
+//! pixels.  Key frames hold a whole image; delta frames are XORed onto the previous frame.
+
+use crate::geometry::ScreenSize;
+
+use super::{pdi::MAX_PLANE_LEN, Bitmap, Graphics, LCDColor, LCDSolidColor};
+use alloc::vec::Vec;
+use anyhow::{anyhow, bail, ensure, Error, Result};
+use miniz_oxide::inflate::decompress_to_vec_zlib_with_limit;
+
+const PDV_IDENT: &[u8] = b"Playdate VID";
+const PDV_HEADER_LEN: usize = 28;
+
+/// What a frame of a `.pdv` file holds.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PdvFrameType {
+    /// No data; the frame looks like the previous one.
+    Empty,
+    /// A whole image.
+    Key,
+    /// Changes to XOR onto the previous frame.
+    Delta,
+    /// A whole image followed by changes to XOR onto it.
+    Combined,
+}
+
+impl PdvFrameType {
+    fn from_bits(bits: u32) -> Self {
+        match bits & 3 {
+            1 => Self::Key,
+            2 => Self::Delta,
+            3 => Self::Combined,
+            _ => Self::Empty,
+        }
+    }
+
+    /// Returns true if decoding can start at a frame of this type.
+    pub fn is_key(self) -> bool {
+        matches!(self, Self::Key | Self::Combined)
+    }
+}
+
+/// The header and frame table of a `.pdv` file, borrowing the file's bytes.
+#[derive(Clone, Debug)]
+pub struct PdvFile<'a> {
+    frame_data: &'a [u8],
+    frame_rate: f32,
+    width: usize,
+    height: usize,
+    // Offsets into frame_data and frame types; there's one more offset than frames, marking
+    // the end of the last one.
+    frames: Vec<(usize, PdvFrameType)>,
+}
+
+impl<'a> PdvFile<'a> {
+    /// Reads the header and frame table.  Frames are only decompressed when decoded.
+    pub fn parse(data: &'a [u8]) -> Result<Self, Error> {
+        ensure!(
+            data.len() >= PDV_HEADER_LEN && data.starts_with(PDV_IDENT),
+            "Not a .pdv file"
+        );
+        let frame_count = read_u16(data, 16) as usize;
+        let frame_rate = f32::from_le_bytes([data[20], data[21], data[22], data[23]]);
+        let width = read_u16(data, 24) as usize;
+        let height = read_u16(data, 26) as usize;
+        // Decoders allocate a whole frame up front, so don't trust the header with any size.
+        ensure!(
+            width.div_ceil(8) * height <= MAX_PLANE_LEN,
+            "Video frames of {}x{} are too large",
+            width,
+            height
+        );
+
+        let table_len = (frame_count + 1) * 4;
+        ensure!(
+            data.len() >= PDV_HEADER_LEN + table_len,
+            "Truncated .pdv frame table"
+        );
+        let frame_data = &data[PDV_HEADER_LEN + table_len..];
+        let mut frames = Vec::with_capacity(frame_count + 1);
+        for index in 0..=frame_count {
+            let entry = read_u32(data, PDV_HEADER_LEN + index * 4);
+            let offset = (entry >> 2) as usize;
+            ensure!(
+                offset <= frame_data.len(),
+                "Frame {} of .pdv file starts past the end",
+                index
+            );
+            if let Some((previous, _)) = frames.last() {
+                ensure!(
+                    offset >= *previous,
+                    "Frame {} of .pdv file starts before the one before it",
+                    index
+                );
+            }
+            frames.push((offset, PdvFrameType::from_bits(entry)));
+        }
+        Ok(Self {
+            frame_data,
+            frame_rate,
+            width,
+            height,
+            frames,
+        })
+    }
+
+    pub fn frame_count(&self) -> usize {
+        self.frames.len() - 1
+    }
+
+    /// Returns frames per second.
+    pub fn frame_rate(&self) -> f32 {
+        self.frame_rate
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the number of bytes in each row of a decoded frame.
+    pub fn rowbytes(&self) -> usize {
+        self.width.div_ceil(8)
+    }
+
+    pub fn frame_type(&self, index: usize) -> Option<PdvFrameType> {
+        (index < self.frame_count()).then(|| self.frames[index].1)
+    }
+
+    /// Returns the compressed data of a frame.
+    pub fn frame_data(&self, index: usize) -> Option<&'a [u8]> {
+        (index < self.frame_count())
+            .then(|| &self.frame_data[self.frames[index].0..self.frames[index + 1].0])
+    }
+
+    /// Returns a decoder for the frames, starting before the first.
+    pub fn decoder(&self) -> PdvDecoder<'_, 'a> {
+        PdvDecoder {
+            file: self,
+            frame: alloc::vec![0; self.rowbytes() * self.height],
+            current: None,
+        }
+    }
+}
+
+/// Decodes the frames of a `PdvFile` in order or by seeking, keeping the current frame.  Frames
+/// are 1-bit rows, `rowbytes` bytes each, with set bits white like bitmap data.
+#[derive(Clone, Debug)]
+pub struct PdvDecoder<'f, 'a> {
+    file: &'f PdvFile<'a>,
+    frame: Vec<u8>,
+    current: Option<usize>,
+}
+
+impl PdvDecoder<'_, '_> {
+    /// Returns the current frame's pixels.  Before any frame is decoded, they're all black.
+    pub fn frame(&self) -> &[u8] {
+        &self.frame
+    }
+
+    /// Returns the index of the current frame, if any frame has been decoded.
+    pub fn current_frame(&self) -> Option<usize> {
+        self.current
+    }
+
+    /// Decodes the frame after the current one, returning None after the last frame.
+    pub fn next_frame(&mut self) -> Result<Option<&[u8]>, Error> {
+        let next = self.current.map_or(0, |current| current + 1);
+        if next >= self.file.frame_count() {
+            return Ok(None);
+        }
+        self.decode(next)?;
+        Ok(Some(&self.frame))
+    }
+
+    /// Decodes frame `index`, starting from the nearest key frame before it unless the current
+    /// frame is already on the way.
+    pub fn seek(&mut self, index: usize) -> Result<&[u8], Error> {
+        ensure!(
+            index < self.file.frame_count(),
+            "Frame {} out of range for video of {} frames",
+            index,
+            self.file.frame_count()
+        );
+        let key = (0..=index)
+            .rev()
+            .find(|&frame| self.file.frames[frame].1.is_key())
+            .unwrap_or(0);
+        let start = match self.current {
+            Some(current) if current >= key && current <= index => current + 1,
+            _ => key,
+        };
+        if start == key && !self.file.frames[key].1.is_key() {
+            // No key frame to start from, so start from black like the player does.
+            self.frame.fill(0);
+        }
+        for frame in start..=index {
+            self.decode(frame)?;
+        }
+        Ok(&self.frame)
+    }
+
+    /// Makes a bitmap of the current frame.
+    pub fn to_bitmap(&self) -> Result<Bitmap, Error> {
+        let size = ScreenSize::new(self.file.width as i32, self.file.height as i32);
+        let bitmap =
+            Graphics::get().new_bitmap(size, LCDColor::Solid(LCDSolidColor::kColorBlack))?;
+        bitmap
+            .pixels()?
+            .copy_from_packed(&self.frame, self.file.rowbytes());
+        Ok(bitmap)
+    }
+
+    fn decode(&mut self, index: usize) -> Result<(), Error> {
+        let data = self.file.frame_data(index).unwrap_or(&[]);
+        match self.file.frames[index].1 {
+            PdvFrameType::Empty => {}
+            PdvFrameType::Key => self.frame = self.inflate(index, data)?,
+            PdvFrameType::Delta => {
+                let delta = self.inflate(index, data)?;
+                xor_into(&mut self.frame, &delta);
+            }
+            PdvFrameType::Combined => {
+                // The key frame's compressed length comes first.
+                ensure!(data.len() >= 2, "Truncated frame {} of .pdv file", index);
+                let key_len = read_u16(data, 0) as usize;
+                let Some(key) = data.get(2..2 + key_len) else {
+                    bail!("Truncated frame {} of .pdv file", index);
+                };
+                // Decoded aside so a bad delta leaves the current frame as it was.
+                let mut frame = self.inflate(index, key)?;
+                let delta = self.inflate(index, &data[2 + key_len..])?;
+                xor_into(&mut frame, &delta);
+                self.frame = frame;
+            }
+        }
+        self.current = Some(index);
+        Ok(())
+    }
+
+    // Stops at a frame's worth of pixels, so bad data can't make it allocate more.
+    fn inflate(&self, index: usize, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let pixels = decompress_to_vec_zlib_with_limit(data, self.frame.len())
+            .map_err(|err| anyhow!("Frame {} of .pdv file: {:?}", index, err.status))?;
+        ensure!(
+            pixels.len() == self.frame.len(),
+            "Frame {} of .pdv file has {} bytes, expected {}",
+            index,
+            pixels.len(),
+            self.frame.len()
+        );
+        Ok(pixels)
+    }
+}
+
+fn xor_into(frame: &mut [u8], delta: &[u8]) {
+    for (pixel, change) in frame.iter_mut().zip(delta) {
+        *pixel ^= change;
+    }
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([data[offset], data[offset + 1]])
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use miniz_oxide::deflate::compress_to_vec_zlib;
+
+    fn zlib(pixels: &[u8]) -> Vec<u8> {
+        compress_to_vec_zlib(pixels, 6)
+    }
+
+    fn combined(key: &[u8], delta: &[u8]) -> Vec<u8> {
+        let key = zlib(key);
+        let mut data = (key.len() as u16).to_le_bytes().to_vec();
+        data.extend_from_slice(&key);
+        data.extend_from_slice(&zlib(delta));
+        data
+    }
+
+    // An 8x2 video of the given frames.
+    fn pdv(frames: &[(PdvFrameType, Vec<u8>)]) -> Vec<u8> {
+        let mut out = PDV_IDENT.to_vec();
+        out.extend_from_slice(&[0; 8]);
+        out[16..18].copy_from_slice(&(frames.len() as u16).to_le_bytes());
+        out.extend_from_slice(&30.0f32.to_le_bytes());
+        out.extend_from_slice(&8u16.to_le_bytes());
+        out.extend_from_slice(&2u16.to_le_bytes());
+        let mut offset = 0u32;
+        for (frame_type, data) in frames {
+            let bits = match frame_type {
+                PdvFrameType::Empty => 0,
+                PdvFrameType::Key => 1,
+                PdvFrameType::Delta => 2,
+                PdvFrameType::Combined => 3,
+            };
+            out.extend_from_slice(&((offset << 2) | bits).to_le_bytes());
+            offset += data.len() as u32;
+        }
+        out.extend_from_slice(&(offset << 2).to_le_bytes());
+        for (_, data) in frames {
+            out.extend_from_slice(data);
+        }
+        out
+    }
+
+    fn fixture() -> Vec<u8> {
+        pdv(&[
+            (PdvFrameType::Key, zlib(&[0xf0, 0x0f])),
+            (PdvFrameType::Delta, zlib(&[0xff, 0x00])),
+            (PdvFrameType::Empty, Vec::new()),
+            (
+                PdvFrameType::Combined,
+                combined(&[0x01, 0x02], &[0x10, 0x20]),
+            ),
+            (PdvFrameType::Delta, zlib(&[0x01, 0x00])),
+        ])
+    }
+
+    #[test]
+    fn parses_header_and_frame_table() {
+        let data = fixture();
+        let file = PdvFile::parse(&data).unwrap();
+        assert_eq!(file.frame_count(), 5);
+        assert_eq!(file.frame_rate(), 30.0);
+        assert_eq!((file.width(), file.height(), file.rowbytes()), (8, 2, 1));
+        assert_eq!(file.frame_type(2), Some(PdvFrameType::Empty));
+        assert_eq!(file.frame_data(2), Some(&[][..]));
+        assert_eq!(file.frame_type(5), None);
+    }
+
+    #[test]
+    fn decodes_frames_in_order() {
+        let data = fixture();
+        let file = PdvFile::parse(&data).unwrap();
+        let mut decoder = file.decoder();
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.next_frame().unwrap() {
+            frames.push(frame.to_vec());
+        }
+        assert_eq!(
+            frames,
+            [
+                [0xf0, 0x0f],
+                [0x0f, 0x0f],
+                [0x0f, 0x0f],
+                [0x11, 0x22],
+                [0x10, 0x22],
+            ]
+        );
+        assert_eq!(decoder.current_frame(), Some(4));
+    }
+
+    #[test]
+    fn seeks_from_key_frames() {
+        let data = fixture();
+        let file = PdvFile::parse(&data).unwrap();
+        let mut decoder = file.decoder();
+        assert_eq!(decoder.seek(4).unwrap(), [0x10, 0x22]);
+        assert_eq!(decoder.seek(2).unwrap(), [0x0f, 0x0f]);
+        assert_eq!(decoder.seek(3).unwrap(), [0x11, 0x22]);
+        assert!(decoder.seek(5).is_err());
+    }
+
+    // A 12x4 video at 20 fps, assembled by hand following the file layout rather than made by
+    // the SDK's compiler: a checkerboard key frame, its inverse as a delta, an empty frame,
+    // stripes with two corners changed as a combined frame, then another inverting delta.
+    const CHECKER_PDV: &[u8] = include_bytes!("fixtures/checker.pdv");
+
+    #[test]
+    fn decodes_fixture_file() {
+        let file = PdvFile::parse(CHECKER_PDV).unwrap();
+        assert_eq!(file.frame_count(), 5);
+        assert_eq!(file.frame_rate(), 20.0);
+        assert_eq!((file.width(), file.height(), file.rowbytes()), (12, 4, 2));
+        let mut decoder = file.decoder();
+        let mut frames = Vec::new();
+        while let Some(frame) = decoder.next_frame().unwrap() {
+            frames.push(frame.to_vec());
+        }
+        assert_eq!(
+            frames,
+            [
+                [0xaa, 0xa0, 0x55, 0x50, 0xaa, 0xa0, 0x55, 0x50],
+                [0x55, 0x50, 0xaa, 0xa0, 0x55, 0x50, 0xaa, 0xa0],
+                [0x55, 0x50, 0xaa, 0xa0, 0x55, 0x50, 0xaa, 0xa0],
+                [0x7f, 0xf0, 0x00, 0x00, 0xff, 0xf0, 0x00, 0x10],
+                [0x80, 0x00, 0xff, 0xf0, 0x00, 0x00, 0xff, 0xe0],
+            ]
+        );
+        assert_eq!(decoder.seek(1).unwrap(), frames[1]);
+    }
+
+    #[test]
+    fn rejects_frames_of_the_wrong_size() {
+        let data = pdv(&[
+            (PdvFrameType::Key, zlib(&[0xaa, 0x55])),
+            (PdvFrameType::Key, zlib(&[0; 64])),
+            (PdvFrameType::Combined, combined(&[0x01, 0x02], &[0x10])),
+        ]);
+        let file = PdvFile::parse(&data).unwrap();
+        let mut decoder = file.decoder();
+        decoder.next_frame().unwrap();
+        assert!(decoder.seek(1).is_err());
+        assert!(decoder.seek(2).is_err());
+        // Failed frames leave the last good one in place.
+        assert_eq!(decoder.frame(), [0xaa, 0x55]);
+        assert_eq!(decoder.current_frame(), Some(0));
+    }
+
+    #[test]
+    fn rejects_bad_files() {
+        assert!(PdvFile::parse(b"Playdate VIX").is_err());
+        let mut data = fixture();
+        data.truncate(PDV_HEADER_LEN + 8);
+        assert!(PdvFile::parse(&data).is_err());
+        let mut data = fixture();
+        // Make the second frame start past the end of the data.
+        data[PDV_HEADER_LEN + 4..PDV_HEADER_LEN + 8]
+            .copy_from_slice(&(0xffffu32 << 2).to_le_bytes());
+        assert!(PdvFile::parse(&data).is_err());
+        let mut data = fixture();
+        // Frames far too large to allocate.
+        data[24..28].copy_from_slice(&[0xff; 4]);
+        assert!(PdvFile::parse(&data).is_err());
+    }
+}
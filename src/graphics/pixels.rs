@@ -0,0 +1,276 @@
+use crate::{
+    geometry::{ScreenPoint, ScreenRect},
+    pd_func_caller,
+};
+
+use super::{BitmapRefMut, Graphics, LCDSolidColor};
+use anyhow::{anyhow, ensure, Error, Result};
+use core::{ptr, slice};
+
+/// Direct access to the pixels of a `Bitmap`, from `Bitmap::pixels`.  The bitmap stays mutably
+/// borrowed while this is alive, so it can't be drawn or drawn into at the same time.
+///
+/// Pixels are stored one bit each, most significant bit first, with each row padded to
+/// `rowbytes` bytes; a set bit is white in the data plane and opaque in the mask plane.
+pub struct BitmapPixels<'a> {
+    _inner: BitmapRefMut<'a>,
+    width: usize,
+    height: usize,
+    rowbytes: usize,
+    data: *mut u8,
+    // Null if the bitmap has no mask.
+    mask: *mut u8,
+}
+
+impl<'a> BitmapPixels<'a> {
+    pub(crate) fn new(inner: BitmapRefMut<'a>) -> Result<Self> {
+        let mut width = 0;
+        let mut height = 0;
+        let mut rowbytes = 0;
+        let mut mask = ptr::null_mut();
+        let mut data = ptr::null_mut();
+        pd_func_caller!(
+            (*Graphics::get_ptr()).getBitmapData,
+            inner.raw_bitmap,
+            &mut width,
+            &mut height,
+            &mut rowbytes,
+            &mut mask,
+            &mut data,
+        )?;
+        ensure!(!data.is_null(), "Null pointer returned from getBitmapData");
+        Ok(Self {
+            _inner: inner,
+            width: width.max(0) as usize,
+            height: height.max(0) as usize,
+            rowbytes: rowbytes.max(0) as usize,
+            data,
+            mask,
+        })
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Returns the number of bytes in each row, including padding.
+    pub fn rowbytes(&self) -> usize {
+        self.rowbytes
+    }
+
+    pub fn has_mask(&self) -> bool {
+        !self.mask.is_null()
+    }
+
+    /// Returns the whole data plane, `rowbytes * height` bytes.
+    pub fn data(&self) -> &[u8] {
+        unsafe { slice::from_raw_parts(self.data, self.plane_len()) }
+    }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        unsafe { slice::from_raw_parts_mut(self.data, self.plane_len()) }
+    }
+
+    /// Returns the whole mask plane, if the bitmap has a mask.
+    pub fn mask(&self) -> Option<&[u8]> {
+        if self.mask.is_null() {
+            return None;
+        }
+        Some(unsafe { slice::from_raw_parts(self.mask, self.plane_len()) })
+    }
+
+    pub fn mask_mut(&mut self) -> Option<&mut [u8]> {
+        if self.mask.is_null() {
+            return None;
+        }
+        Some(unsafe { slice::from_raw_parts_mut(self.mask, self.plane_len()) })
+    }
+
+    /// Returns the color of the pixel at (x, y), or None if it's outside the bitmap.
+    pub fn get(&self, x: usize, y: usize) -> Option<LCDSolidColor> {
+        let (index, bit) = self.bit_index(x, y)?;
+        Some(if self.data()[index] & bit != 0 {
+            LCDSolidColor::kColorWhite
+        } else {
+            LCDSolidColor::kColorBlack
+        })
+    }
+
+    /// Sets the pixel at (x, y) to black or white.  Returns false, changing nothing, if it's
+    /// outside the bitmap or the color isn't black or white.
+    pub fn set(&mut self, x: usize, y: usize, color: LCDSolidColor) -> bool {
+        let white = match color {
+            LCDSolidColor::kColorWhite => true,
+            LCDSolidColor::kColorBlack => false,
+            _ => return false,
+        };
+        let Some((index, bit)) = self.bit_index(x, y) else {
+            return false;
+        };
+        set_bit(&mut self.data_mut()[index], bit, white);
+        true
+    }
+
+    /// Returns whether the pixel at (x, y) is opaque, or None if it's outside the bitmap or the
+    /// bitmap has no mask.
+    pub fn get_mask(&self, x: usize, y: usize) -> Option<bool> {
+        let (index, bit) = self.bit_index(x, y)?;
+        Some(self.mask()?[index] & bit != 0)
+    }
+
+    /// Sets whether the pixel at (x, y) is opaque.  Returns false, changing nothing, if it's
+    /// outside the bitmap or the bitmap has no mask.
+    pub fn set_mask(&mut self, x: usize, y: usize, opaque: bool) -> bool {
+        let Some((index, bit)) = self.bit_index(x, y) else {
+            return false;
+        };
+        let Some(mask) = self.mask_mut() else {
+            return false;
+        };
+        set_bit(&mut mask[index], bit, opaque);
+        true
+    }
+
+    /// Returns row `y` of the data plane, including padding.
+    pub fn row(&self, y: usize) -> Option<&[u8]> {
+        let rowbytes = self.rowbytes;
+        (y < self.height).then(|| &self.data()[y * rowbytes..(y + 1) * rowbytes])
+    }
+
+    pub fn row_mut(&mut self, y: usize) -> Option<&mut [u8]> {
+        let rowbytes = self.rowbytes;
+        if y >= self.height {
+            return None;
+        }
+        Some(&mut self.data_mut()[y * rowbytes..(y + 1) * rowbytes])
+    }
+
+    /// Returns row `y` of the mask plane, if the bitmap has a mask.
+    pub fn mask_row(&self, y: usize) -> Option<&[u8]> {
+        let rowbytes = self.rowbytes;
+        let height = self.height;
+        self.mask()
+            .filter(|_| y < height)
+            .map(|mask| &mask[y * rowbytes..(y + 1) * rowbytes])
+    }
+
+    pub fn mask_row_mut(&mut self, y: usize) -> Option<&mut [u8]> {
+        let rowbytes = self.rowbytes;
+        let height = self.height;
+        self.mask_mut()
+            .filter(|_| y < height)
+            .map(|mask| &mut mask[y * rowbytes..(y + 1) * rowbytes])
+    }
+
+    /// Iterates over the rows of the data plane, top to bottom.
+    pub fn rows(&self) -> impl Iterator<Item = &[u8]> {
+        self.data().chunks_exact(self.rowbytes.max(1))
+    }
+
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [u8]> {
+        let rowbytes = self.rowbytes.max(1);
+        self.data_mut().chunks_exact_mut(rowbytes)
+    }
+
+    /// Iterates over the rows of the mask plane, top to bottom; empty if there's no mask.
+    pub fn mask_rows(&self) -> impl Iterator<Item = &[u8]> {
+        self.mask()
+            .unwrap_or(&[])
+            .chunks_exact(self.rowbytes.max(1))
+    }
+
+    pub fn mask_rows_mut(&mut self) -> impl Iterator<Item = &mut [u8]> {
+        let rowbytes = self.rowbytes.max(1);
+        self.mask_mut()
+            .unwrap_or(&mut [])
+            .chunks_exact_mut(rowbytes)
+    }
+
+    /// Sets every pixel to black or white, and makes every pixel opaque if there's a mask.
+    pub fn fill(&mut self, color: LCDSolidColor) -> Result<()> {
+        let byte = match color {
+            LCDSolidColor::kColorWhite => 0xff,
+            LCDSolidColor::kColorBlack => 0,
+            _ => return Err(anyhow!("Can only fill bitmap pixels with black or white")),
+        };
+        self.data_mut().fill(byte);
+        if let Some(mask) = self.mask_mut() {
+            mask.fill(0xff);
+        }
+        Ok(())
+    }
+
+    /// Copies the pixels in `source_rect` of `source` so that its top left lands at `dest`,
+    /// clipping to both bitmaps.  If both bitmaps have masks, the mask is copied too; if only
+    /// this one does, copied pixels become opaque.
+    pub fn blit(&mut self, source: &BitmapPixels<'_>, source_rect: ScreenRect, dest: ScreenPoint) {
+        let source_bounds = ScreenRect::new(
+            ScreenPoint::zero(),
+            euclid::size2(source.width as i32, source.height as i32),
+        );
+        let Some(clipped) = source_rect.intersection(&source_bounds) else {
+            return;
+        };
+        // Move the destination along with any part of the source rect that was clipped off.
+        let dest = dest + (clipped.origin - source_rect.origin);
+        let source_rect = clipped;
+        for row in 0..source_rect.size.height {
+            for column in 0..source_rect.size.width {
+                let (dest_x, dest_y) = (dest.x + column, dest.y + row);
+                if dest_x < 0 || dest_y < 0 {
+                    continue;
+                }
+                let (source_x, source_y) = (
+                    (source_rect.origin.x + column) as usize,
+                    (source_rect.origin.y + row) as usize,
+                );
+                let (dest_x, dest_y) = (dest_x as usize, dest_y as usize);
+                let Some(color) = source.get(source_x, source_y) else {
+                    continue;
+                };
+                if !self.set(dest_x, dest_y, color) {
+                    continue;
+                }
+                let opaque = source.get_mask(source_x, source_y).unwrap_or(true);
+                self.set_mask(dest_x, dest_y, opaque);
+            }
+        }
+    }
+
+    /// Copies packed 1-bit rows, `source_rowbytes` bytes each, into the data plane starting at
+    /// the top left.  Rows and columns that don't fit are dropped.
+    pub fn copy_from_packed(&mut self, source: &[u8], source_rowbytes: usize) {
+        if source_rowbytes == 0 {
+            return;
+        }
+        let rowbytes = self.rowbytes;
+        for (dest_row, source_row) in self.rows_mut().zip(source.chunks(source_rowbytes)) {
+            let len = rowbytes.min(source_row.len());
+            dest_row[..len].copy_from_slice(&source_row[..len]);
+        }
+    }
+
+    fn plane_len(&self) -> usize {
+        self.rowbytes * self.height
+    }
+
+    // Returns the byte index and bit mask of (x, y) within a plane.
+    fn bit_index(&self, x: usize, y: usize) -> Option<(usize, u8)> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        Some((y * self.rowbytes + x / 8, 0x80 >> (x % 8)))
+    }
+}
+
+fn set_bit(byte: &mut u8, bit: u8, value: bool) {
+    if value {
+        *byte |= bit;
+    } else {
+        *byte &= !bit;
+    }
+}
@@ -0,0 +1,611 @@
+use crate::geometry::{ScreenPoint, ScreenVector};
+
+use super::{Font, Graphics};
+use alloc::{string::String, vec::Vec};
+use anyhow::{Error, Result};
+
+/// The style of a run of text, set with `*bold*` and `_italic_` markup.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TextStyle {
+    pub bold: bool,
+    pub italic: bool,
+}
+
+/// A run of text in a single style.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TextSpan {
+    pub text: String,
+    pub style: TextStyle,
+}
+
+/// Splits text with markup into spans.  `*` toggles bold and `_` toggles italic, like the Lua
+/// SDK's text drawing; write `**` or `__` for a literal `*` or `_`.
+pub fn parse_markup(text: &str) -> Vec<TextSpan> {
+    let mut spans = Vec::new();
+    let mut current = TextSpan::default();
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != '*' && c != '_' {
+            current.text.push(c);
+            continue;
+        }
+        if chars.peek() == Some(&c) {
+            chars.next();
+            current.text.push(c);
+            continue;
+        }
+        let mut style = current.style;
+        if c == '*' {
+            style.bold = !style.bold;
+        } else {
+            style.italic = !style.italic;
+        }
+        let finished = core::mem::replace(
+            &mut current,
+            TextSpan {
+                text: String::new(),
+                style,
+            },
+        );
+        if !finished.text.is_empty() {
+            spans.push(finished);
+        }
+    }
+    if !current.text.is_empty() {
+        spans.push(current);
+    }
+    spans
+}
+
+/// Measures text for `TextLayout`.  Implemented for `FontFamily` to lay out with real fonts;
+/// anything else, like fixed-width stubs, can be used to lay out text without a device.
+pub trait TextMetrics {
+    /// Returns the width of `text` drawn in `style`, in pixels.
+    fn text_width(&self, text: &str, style: TextStyle) -> Result<i32, Error>;
+
+    /// Returns the height of a line of text, in pixels, not counting line spacing.
+    fn line_height(&self) -> Result<i32, Error>;
+}
+
+/// The fonts to lay out and draw text with, one per style.  Styles without a font of their own
+/// use the regular font.
+#[derive(Clone, Debug)]
+pub struct FontFamily {
+    pub regular: Font,
+    pub bold: Option<Font>,
+    pub italic: Option<Font>,
+    pub bold_italic: Option<Font>,
+    /// Extra space between characters, in pixels.
+    pub tracking: i32,
+}
+
+impl FontFamily {
+    pub fn new(regular: Font) -> Self {
+        Self {
+            regular,
+            bold: None,
+            italic: None,
+            bold_italic: None,
+            tracking: 0,
+        }
+    }
+
+    /// Returns the font to use for `style`.
+    pub fn font(&self, style: TextStyle) -> &Font {
+        let font = match (style.bold, style.italic) {
+            (true, true) => self
+                .bold_italic
+                .as_ref()
+                .or(self.bold.as_ref())
+                .or(self.italic.as_ref()),
+            (true, false) => self.bold.as_ref(),
+            (false, true) => self.italic.as_ref(),
+            (false, false) => None,
+        };
+        font.unwrap_or(&self.regular)
+    }
+}
+
+impl TextMetrics for FontFamily {
+    fn text_width(&self, text: &str, style: TextStyle) -> Result<i32, Error> {
+        Graphics::get().get_text_width(self.font(style), text, self.tracking)
+    }
+
+    fn line_height(&self) -> Result<i32, Error> {
+        Ok(self.regular.get_height()? as i32)
+    }
+}
+
+/// How lines are placed horizontally in a `TextLayout`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TextAlignment {
+    #[default]
+    Left,
+    Center,
+    Right,
+}
+
+/// Options for `TextLayout::new`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TextLayoutOptions {
+    pub alignment: TextAlignment,
+    /// Extra space between lines, in pixels.
+    pub line_spacing: i32,
+    /// If true, lines are wrapped at spaces to fit the width, or between characters for words
+    /// too long for a line; otherwise lines only end at newlines and are cut off.
+    pub wrap: bool,
+    /// If true, `*` and `_` toggle bold and italic; see `parse_markup`.
+    pub markup: bool,
+    /// Text to end the last line with when text is cut off, e.g. "...".
+    pub ellipsis: Option<String>,
+    /// The most lines to lay out, on top of the limit from the height.
+    pub max_lines: Option<usize>,
+}
+
+impl Default for TextLayoutOptions {
+    fn default() -> Self {
+        Self {
+            alignment: TextAlignment::Left,
+            line_spacing: 0,
+            wrap: true,
+            markup: false,
+            ellipsis: None,
+            max_lines: None,
+        }
+    }
+}
+
+/// A run of text in a single style within a line, positioned relative to the layout.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TextRun {
+    pub text: String,
+    pub style: TextStyle,
+    pub x: i32,
+    pub width: i32,
+}
+
+/// A laid out line, positioned relative to the layout.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TextLine {
+    pub runs: Vec<TextRun>,
+    pub y: i32,
+    pub width: i32,
+}
+
+type StyledChar = (char, TextStyle);
+
+/// Text broken into lines and runs to fit a width, and optionally a height.  Laying out only
+/// measures text through `TextMetrics`, so it can be done once and drawn every frame.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TextLayout {
+    lines: Vec<TextLine>,
+    width: i32,
+    line_height: i32,
+    line_spacing: i32,
+    truncated: bool,
+}
+
+impl TextLayout {
+    /// Lays out `text` to fit `width` pixels, and `height` pixels if given.  Lines that don't
+    /// fit are dropped, ending the last line that does with the ellipsis, if any.
+    pub fn new(
+        text: &str,
+        width: i32,
+        height: Option<i32>,
+        options: &TextLayoutOptions,
+        metrics: &impl TextMetrics,
+    ) -> Result<Self, Error> {
+        let line_height = metrics.line_height()?;
+        let spans = if options.markup {
+            parse_markup(text)
+        } else {
+            Vec::from([TextSpan {
+                text: text.into(),
+                style: TextStyle::default(),
+            }])
+        };
+        let chars: Vec<StyledChar> = spans
+            .iter()
+            .flat_map(|span| span.text.chars().map(move |c| (c, span.style)))
+            .collect();
+
+        let mut lines: Vec<Vec<StyledChar>> = Vec::new();
+        // Whether each line already ends with the ellipsis added when it was cut off.
+        let mut ellipsized: Vec<bool> = Vec::new();
+        let mut truncated = false;
+        for paragraph in chars.split(|(c, _)| *c == '\n') {
+            if options.wrap {
+                wrap_paragraph(paragraph, width, metrics, &mut lines)?;
+                ellipsized.resize(lines.len(), false);
+            } else {
+                let fitted = fit_chars(paragraph, width, metrics)?;
+                let mut line = paragraph[..fitted].to_vec();
+                let mut line_ellipsized = false;
+                if fitted < paragraph.len() {
+                    truncated = true;
+                    if let Some(ellipsis) = &options.ellipsis {
+                        add_ellipsis(&mut line, ellipsis, width, metrics)?;
+                        line_ellipsized = true;
+                    }
+                }
+                lines.push(line);
+                ellipsized.push(line_ellipsized);
+            }
+        }
+
+        let mut max_lines = options.max_lines.unwrap_or(usize::MAX);
+        if let Some(height) = height {
+            let pitch = (line_height + options.line_spacing).max(1);
+            let fit = ((height + options.line_spacing) / pitch).max(0) as usize;
+            max_lines = max_lines.min(fit);
+        }
+        if lines.len() > max_lines {
+            lines.truncate(max_lines);
+            truncated = true;
+            if let (Some(ellipsis), Some(last)) = (&options.ellipsis, lines.last_mut()) {
+                if !ellipsized[max_lines - 1] {
+                    add_ellipsis(last, ellipsis, width, metrics)?;
+                }
+            }
+        }
+
+        let mut layout = Self {
+            lines: Vec::with_capacity(lines.len()),
+            width,
+            line_height,
+            line_spacing: options.line_spacing,
+            truncated,
+        };
+        for (index, line) in lines.iter().enumerate() {
+            let y = index as i32 * (line_height + options.line_spacing);
+            layout
+                .lines
+                .push(make_line(line, y, width, options.alignment, metrics)?);
+        }
+        Ok(layout)
+    }
+
+    pub fn lines(&self) -> &[TextLine] {
+        &self.lines
+    }
+
+    /// Returns the width the text was laid out to fit.
+    pub fn width(&self) -> i32 {
+        self.width
+    }
+
+    /// Returns the height of the laid out lines, in pixels.
+    pub fn height(&self) -> i32 {
+        let count = self.lines.len() as i32;
+        if count == 0 {
+            return 0;
+        }
+        count * self.line_height + (count - 1) * self.line_spacing
+    }
+
+    /// Returns true if some of the text didn't fit.
+    pub fn is_truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// Draws the laid out text with its top left at `origin`, using the fonts the layout was
+    /// measured with.  The current font and tracking are restored afterwards, even if it's the
+    /// system font.
+    pub fn draw(
+        &self,
+        graphics: &Graphics,
+        origin: ScreenPoint,
+        fonts: &FontFamily,
+    ) -> Result<(), Error> {
+        // Dropping the context restores the font and tracking.
+        let context = graphics.push_nested_context()?;
+        context.set_text_tracking(fonts.tracking)?;
+        for line in &self.lines {
+            for run in &line.runs {
+                context.set_font(fonts.font(run.style))?;
+                context.draw_text(&run.text, origin + ScreenVector::new(run.x, line.y))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Measures styled characters, one run of each style at a time.
+fn measure(chars: &[StyledChar], metrics: &impl TextMetrics) -> Result<i32, Error> {
+    let mut width = 0;
+    for run in chars.chunk_by(|a, b| a.1 == b.1) {
+        let text: String = run.iter().map(|(c, _)| c).collect();
+        width += metrics.text_width(&text, run[0].1)?;
+    }
+    Ok(width)
+}
+
+// Returns how many of the characters fit in `width`.  Measuring a prefix at a time would be
+// quadratic, so this searches for the longest one that fits.
+fn fit_chars(chars: &[StyledChar], width: i32, metrics: &impl TextMetrics) -> Result<usize, Error> {
+    if measure(chars, metrics)? <= width {
+        return Ok(chars.len());
+    }
+    // chars[..low] fits and chars[..high] doesn't.
+    let (mut low, mut high) = (0, chars.len());
+    while high - low > 1 {
+        let middle = low + (high - low) / 2;
+        if measure(&chars[..middle], metrics)? <= width {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+    Ok(low)
+}
+
+fn wrap_paragraph(
+    paragraph: &[StyledChar],
+    width: i32,
+    metrics: &impl TextMetrics,
+    lines: &mut Vec<Vec<StyledChar>>,
+) -> Result<(), Error> {
+    let mut line: Vec<StyledChar> = Vec::new();
+    let mut line_width = 0;
+    let mut start = 0;
+    loop {
+        let end = paragraph[start..]
+            .iter()
+            .position(|(c, _)| *c == ' ')
+            .map_or(paragraph.len(), |position| start + position);
+        let mut word = &paragraph[start..end];
+        let mut word_width = measure(word, metrics)?;
+        // The space in front of the word, unless it starts the paragraph or a line.
+        let space = (start > 0 && !line.is_empty()).then(|| paragraph[start - 1]);
+        let space_width = match space {
+            Some(space) => measure(&[space], metrics)?,
+            None => 0,
+        };
+        if line.is_empty() || line_width + space_width + word_width <= width {
+            line.extend(space);
+            line_width += space_width;
+        } else {
+            lines.push(core::mem::take(&mut line));
+            line_width = 0;
+        }
+        // Break words too long for a line of their own between characters.
+        while line.is_empty() && word_width > width && !word.is_empty() {
+            let fitted = fit_chars(word, width, metrics)?.max(1);
+            lines.push(word[..fitted].to_vec());
+            word = &word[fitted..];
+            word_width = measure(word, metrics)?;
+        }
+        line.extend_from_slice(word);
+        line_width += word_width;
+
+        if end == paragraph.len() {
+            break;
+        }
+        start = end + 1;
+    }
+    lines.push(line);
+    Ok(())
+}
+
+// Ends the line with the ellipsis, dropping characters until it fits.
+fn add_ellipsis(
+    line: &mut Vec<StyledChar>,
+    ellipsis: &str,
+    width: i32,
+    metrics: &impl TextMetrics,
+) -> Result<(), Error> {
+    let style = line
+        .last()
+        .map_or(TextStyle::default(), |(_, style)| *style);
+    let ellipsis: Vec<StyledChar> = ellipsis.chars().map(|c| (c, style)).collect();
+    let ellipsis_width = measure(&ellipsis, metrics)?;
+    let fitted = fit_chars(line, width - ellipsis_width, metrics)?;
+    line.truncate(fitted);
+    while line.last().is_some_and(|(c, _)| *c == ' ') {
+        line.pop();
+    }
+    line.extend(ellipsis);
+    Ok(())
+}
+
+fn make_line(
+    chars: &[StyledChar],
+    y: i32,
+    width: i32,
+    alignment: TextAlignment,
+    metrics: &impl TextMetrics,
+) -> Result<TextLine, Error> {
+    let mut runs = Vec::new();
+    let mut x = 0;
+    for run in chars.chunk_by(|a, b| a.1 == b.1) {
+        let text: String = run.iter().map(|(c, _)| c).collect();
+        let run_width = metrics.text_width(&text, run[0].1)?;
+        runs.push(TextRun {
+            text,
+            style: run[0].1,
+            x,
+            width: run_width,
+        });
+        x += run_width;
+    }
+    let offset = match alignment {
+        TextAlignment::Left => 0,
+        TextAlignment::Center => (width - x) / 2,
+        TextAlignment::Right => width - x,
+    };
+    for run in &mut runs {
+        run.x += offset;
+    }
+    Ok(TextLine { runs, y, width: x })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Regular characters are 6 pixels wide and bold ones 8, with 10 pixel lines.
+    struct FixedMetrics;
+
+    impl TextMetrics for FixedMetrics {
+        fn text_width(&self, text: &str, style: TextStyle) -> Result<i32, Error> {
+            let per_char = if style.bold { 8 } else { 6 };
+            Ok(text.chars().count() as i32 * per_char)
+        }
+
+        fn line_height(&self) -> Result<i32, Error> {
+            Ok(10)
+        }
+    }
+
+    const BOLD: TextStyle = TextStyle {
+        bold: true,
+        italic: false,
+    };
+
+    fn layout(
+        text: &str,
+        width: i32,
+        height: Option<i32>,
+        options: &TextLayoutOptions,
+    ) -> TextLayout {
+        TextLayout::new(text, width, height, options, &FixedMetrics).unwrap()
+    }
+
+    fn texts(layout: &TextLayout) -> Vec<String> {
+        layout
+            .lines()
+            .iter()
+            .map(|line| line.runs.iter().map(|run| run.text.as_str()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn wraps_at_spaces() {
+        let layout = layout("one two three", 48, None, &TextLayoutOptions::default());
+        assert_eq!(texts(&layout), ["one two", "three"]);
+        assert_eq!(layout.lines()[1].y, 10);
+        assert_eq!(layout.height(), 20);
+        assert!(!layout.is_truncated());
+    }
+
+    #[test]
+    fn breaks_long_words() {
+        let layout = layout("abcdefghij", 24, None, &TextLayoutOptions::default());
+        assert_eq!(texts(&layout), ["abcd", "efgh", "ij"]);
+    }
+
+    #[test]
+    fn keeps_newlines_and_line_spacing() {
+        let options = TextLayoutOptions {
+            line_spacing: 2,
+            ..TextLayoutOptions::default()
+        };
+        let layout = layout("a\n\nb", 60, None, &options);
+        assert_eq!(texts(&layout), ["a", "", "b"]);
+        assert_eq!(layout.lines()[2].y, 24);
+        assert_eq!(layout.height(), 34);
+    }
+
+    #[test]
+    fn aligns_lines() {
+        let mut options = TextLayoutOptions {
+            alignment: TextAlignment::Center,
+            ..TextLayoutOptions::default()
+        };
+        let centered = layout("ab", 20, None, &options);
+        assert_eq!(centered.lines()[0].runs[0].x, 4);
+        assert_eq!(centered.lines()[0].width, 12);
+
+        options.alignment = TextAlignment::Right;
+        let right = layout("ab", 20, None, &options);
+        assert_eq!(right.lines()[0].runs[0].x, 8);
+    }
+
+    #[test]
+    fn cuts_off_unwrapped_lines_with_ellipsis() {
+        let options = TextLayoutOptions {
+            wrap: false,
+            ellipsis: Some("..".into()),
+            ..TextLayoutOptions::default()
+        };
+        let layout = layout("hello world", 42, None, &options);
+        assert_eq!(texts(&layout), ["hello.."]);
+        assert!(layout.is_truncated());
+    }
+
+    #[test]
+    fn ellipsis_drops_trailing_spaces() {
+        let options = TextLayoutOptions {
+            wrap: false,
+            ellipsis: Some("..".into()),
+            ..TextLayoutOptions::default()
+        };
+        let layout = layout("ab cdef", 30, None, &options);
+        assert_eq!(texts(&layout), ["ab.."]);
+    }
+
+    #[test]
+    fn ellipsizes_text_that_already_ends_in_dots() {
+        let options = TextLayoutOptions {
+            wrap: false,
+            max_lines: Some(1),
+            ellipsis: Some("..".into()),
+            ..TextLayoutOptions::default()
+        };
+        // The first line fits as it is, so only the line limit cuts it off.
+        let layout = layout("ab...\ncd", 60, None, &options);
+        assert_eq!(texts(&layout), ["ab....."]);
+        assert!(layout.is_truncated());
+    }
+
+    #[test]
+    fn limits_lines_by_height_and_max_lines() {
+        let options = TextLayoutOptions {
+            ellipsis: Some("..".into()),
+            ..TextLayoutOptions::default()
+        };
+        let by_height = layout("one two three four", 30, Some(25), &options);
+        assert_eq!(texts(&by_height), ["one", "two.."]);
+        assert!(by_height.is_truncated());
+
+        let options = TextLayoutOptions {
+            max_lines: Some(1),
+            ..options
+        };
+        let by_max_lines = layout("one two three four", 30, None, &options);
+        assert_eq!(texts(&by_max_lines), ["one.."]);
+    }
+
+    #[test]
+    fn measures_markup_runs_by_style() {
+        let options = TextLayoutOptions {
+            markup: true,
+            ..TextLayoutOptions::default()
+        };
+        let layout = layout("a *bc* d", 100, None, &options);
+        let runs = &layout.lines()[0].runs;
+        assert_eq!(runs.len(), 3);
+        assert_eq!((runs[1].text.as_str(), runs[1].style), ("bc", BOLD));
+        assert_eq!((runs[1].x, runs[1].width), (12, 16));
+        assert_eq!(runs[2].x, 28);
+        assert_eq!(layout.lines()[0].width, 40);
+    }
+
+    #[test]
+    fn parses_markup() {
+        let spans = parse_markup("x *b _bi_* __y**");
+        let parsed: Vec<(&str, bool, bool)> = spans
+            .iter()
+            .map(|span| (span.text.as_str(), span.style.bold, span.style.italic))
+            .collect();
+        assert_eq!(
+            parsed,
+            [
+                ("x ", false, false),
+                ("b ", true, false),
+                ("bi", true, true),
+                (" _y*", false, false),
+            ]
+        );
+    }
+}
@@ -0,0 +1,345 @@
+use crate::{pd_func_caller, pd_func_caller_log, sound::FilePlayer};
+
+use super::{Bitmap, BitmapSource};
+use alloc::{rc::Rc, string::String};
+use anyhow::{ensure, Error, Result};
+use cstr_core::CStr;
+
+use crankstart_sys::LCDVideoPlayer;
+
+/// The size and timing of a video, and the last frame rendered.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VideoInfo {
+    pub width: i32,
+    pub height: i32,
+    /// Frames per second.
+    pub frame_rate: f32,
+    pub frame_count: i32,
+    pub current_frame: i32,
+}
+
+impl VideoInfo {
+    /// Returns the length of the video, in seconds.
+    pub fn duration(&self) -> f32 {
+        if self.frame_rate > 0.0 {
+            self.frame_count as f32 / self.frame_rate
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Plays a `.pdv` video, from `Graphics::load_video`.  Frames can be rendered one at a time with
+/// `render_frame`, or timed by `play` and `update`, following an audio track if one is set so
+/// cutscenes stay in sync with their sound.
+#[derive(Debug)]
+pub struct VideoPlayer {
+    inner: Rc<VideoPlayerInner>,
+    info: VideoInfo,
+    // Held so the bitmap being rendered into isn't freed while the player uses it.
+    context: Option<Bitmap>,
+    // The player's own context once `get_context` has wrapped it, kept so every caller shares
+    // one bitmap, and so one borrow, rather than each getting their own.
+    own_context: Option<Bitmap>,
+    // True after `use_screen_context`, until a context is set again.
+    screen_context: bool,
+    audio: Option<FilePlayer>,
+    playing: bool,
+    // The last frame rendered, if any, so `update` only renders when the frame changes.
+    rendered_frame: Option<i32>,
+    // Playback position in seconds, used when there's no audio track to follow.
+    position: f32,
+}
+
+// Shared with the bitmap from `get_context`, which the player frees along with itself.
+#[derive(Debug)]
+pub(crate) struct VideoPlayerInner {
+    raw_subsystem: *const crankstart_sys::playdate_video,
+    raw_player: *mut LCDVideoPlayer,
+}
+
+impl Drop for VideoPlayerInner {
+    fn drop(&mut self) {
+        // Use _log to leak rather than fail
+        pd_func_caller_log!((*self.raw_subsystem).freePlayer, self.raw_player);
+    }
+}
+
+impl VideoPlayer {
+    pub(crate) fn new(
+        raw_subsystem: *const crankstart_sys::playdate_video,
+        raw_player: *mut LCDVideoPlayer,
+    ) -> Result<Self> {
+        ensure!(
+            !raw_subsystem.is_null(),
+            "Null pointer given as subsystem to VideoPlayer::new"
+        );
+        ensure!(
+            !raw_player.is_null(),
+            "Null pointer given as player to VideoPlayer::new"
+        );
+        // Wrapped first so the player is freed if getting its info fails.
+        let mut player = Self {
+            inner: Rc::new(VideoPlayerInner {
+                raw_subsystem,
+                raw_player,
+            }),
+            info: VideoInfo {
+                width: 0,
+                height: 0,
+                frame_rate: 0.0,
+                frame_count: 0,
+                current_frame: 0,
+            },
+            context: None,
+            own_context: None,
+            screen_context: false,
+            audio: None,
+            playing: false,
+            rendered_frame: None,
+            position: 0.0,
+        };
+        player.info = player.get_info()?;
+        Ok(player)
+    }
+
+    /// Returns the video's size, frame rate and frame count, and the last frame rendered.
+    pub fn get_info(&self) -> Result<VideoInfo> {
+        let mut info = self.info;
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).getInfo,
+            self.inner.raw_player,
+            &mut info.width,
+            &mut info.height,
+            &mut info.frame_rate,
+            &mut info.frame_count,
+            &mut info.current_frame
+        )?;
+        Ok(info)
+    }
+
+    pub fn frame_count(&self) -> i32 {
+        self.info.frame_count
+    }
+
+    /// Returns frames per second.
+    pub fn frame_rate(&self) -> f32 {
+        self.info.frame_rate
+    }
+
+    /// Returns the width and height of each frame.
+    pub fn size(&self) -> (i32, i32) {
+        (self.info.width, self.info.height)
+    }
+
+    /// Renders into `bitmap` from now on.  The bitmap is kept alive until the context changes
+    /// or the player is dropped.
+    pub fn set_context(&mut self, bitmap: &Bitmap) -> Result<()> {
+        let result = pd_func_caller!(
+            (*self.inner.raw_subsystem).setContext,
+            self.inner.raw_player,
+            bitmap.inner.borrow().raw_bitmap
+        )?;
+        ensure!(result != 0, "Error setting video context: {}", self.error());
+        self.context = Some(bitmap.clone());
+        self.screen_context = false;
+        Ok(())
+    }
+
+    /// Renders into the framebuffer from now on.
+    pub fn use_screen_context(&mut self) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).useScreenContext,
+            self.inner.raw_player
+        )?;
+        self.context = None;
+        self.screen_context = true;
+        Ok(())
+    }
+
+    /// Returns the bitmap frames are rendered into.  If no context was set, the player makes
+    /// one the size of the video, and the returned bitmap keeps the player's data alive.
+    pub fn get_context(&mut self) -> Result<Bitmap> {
+        if let Some(context) = &self.context {
+            return Ok(context.clone());
+        }
+        let raw_bitmap = pd_func_caller!(
+            (*self.inner.raw_subsystem).getContext,
+            self.inner.raw_player
+        )?;
+        ensure!(
+            !raw_bitmap.is_null(),
+            "Null pointer returned from getContext"
+        );
+        if let Some(context) = &self.own_context {
+            if context.inner.borrow().raw_bitmap == raw_bitmap {
+                return Ok(context.clone());
+            }
+        }
+        let context = Bitmap::with_source(
+            raw_bitmap,
+            false,
+            Some(BitmapSource::Video(self.inner.clone())),
+        );
+        self.own_context = Some(context.clone());
+        Ok(context)
+    }
+
+    /// Renders frame `frame` into the context.  Fails if the context bitmap is in use, e.g.
+    /// while its pixels are held.
+    pub fn render_frame(&mut self, frame: i32) -> Result<()> {
+        ensure!(
+            (0..self.info.frame_count).contains(&frame),
+            "Frame {} out of range for video of {} frames",
+            frame,
+            self.info.frame_count
+        );
+        let target = if self.screen_context {
+            None
+        } else {
+            self.context.as_ref().or(self.own_context.as_ref())
+        };
+        let target = target.map(|bitmap| bitmap.borrow_mut()).transpose()?;
+        let result = pd_func_caller!(
+            (*self.inner.raw_subsystem).renderFrame,
+            self.inner.raw_player,
+            frame
+        )?;
+        drop(target);
+        ensure!(result != 0, "Error rendering video frame: {}", self.error());
+        self.info.current_frame = frame;
+        self.rendered_frame = Some(frame);
+        Ok(())
+    }
+
+    /// Returns the last frame rendered.
+    pub fn current_frame(&self) -> i32 {
+        self.info.current_frame
+    }
+
+    /// Returns the player's last error, if any.
+    pub fn get_error(&self) -> Result<Option<String>> {
+        let message = pd_func_caller!((*self.inner.raw_subsystem).getError, self.inner.raw_player)?;
+        if message.is_null() {
+            return Ok(None);
+        }
+        Ok(Some(unsafe {
+            CStr::from_ptr(message).to_string_lossy().into_owned()
+        }))
+    }
+
+    // For error messages; getting the error can fail too.
+    fn error(&self) -> String {
+        match self.get_error() {
+            Ok(Some(message)) => message,
+            Ok(None) => "unknown error".into(),
+            Err(err) => alloc::format!("{:#}", err),
+        }
+    }
+
+    /// Sets the audio track to play along with the video, replacing any previous one.  While
+    /// it's playing, `update` shows the frame matching the audio's position.  The player should
+    /// already be loaded, e.g. with `FilePlayer::load_into_player`.
+    pub fn set_audio(&mut self, audio: Option<FilePlayer>) -> Result<()> {
+        if let Some(previous) = &self.audio {
+            previous.stop()?;
+        }
+        self.audio = audio;
+        if let Some(audio) = &self.audio {
+            audio.set_offset(self.position)?;
+            if self.playing {
+                audio.play(1)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn get_audio(&self) -> Option<&FilePlayer> {
+        self.audio.as_ref()
+    }
+
+    /// Starts or resumes timed playback; call `update` every frame to render.
+    pub fn play(&mut self) -> Result<()> {
+        if self.playing {
+            return Ok(());
+        }
+        if let Some(audio) = &self.audio {
+            audio.set_offset(self.position)?;
+            audio.play(1)?;
+        }
+        self.playing = true;
+        Ok(())
+    }
+
+    /// Pauses timed playback, keeping the position.
+    pub fn pause(&mut self) -> Result<()> {
+        if let Some(audio) = &self.audio {
+            self.position = audio.get_offset()?;
+            audio.pause()?;
+        }
+        self.playing = false;
+        Ok(())
+    }
+
+    /// Stops timed playback and goes back to the start.
+    pub fn stop(&mut self) -> Result<()> {
+        if let Some(audio) = &self.audio {
+            audio.stop()?;
+        }
+        self.playing = false;
+        self.position = 0.0;
+        Ok(())
+    }
+
+    pub fn is_playing(&self) -> bool {
+        self.playing
+    }
+
+    /// Returns the playback position, in seconds.
+    pub fn get_position(&self) -> Result<f32> {
+        match &self.audio {
+            Some(audio) if self.playing => audio.get_offset(),
+            _ => Ok(self.position),
+        }
+    }
+
+    /// Moves playback to `seconds` into the video, and the audio with it.
+    pub fn seek(&mut self, seconds: f32) -> Result<()> {
+        self.position = seconds.clamp(0.0, self.info.duration());
+        if let Some(audio) = &self.audio {
+            audio.set_offset(self.position)?;
+        }
+        Ok(())
+    }
+
+    /// Advances timed playback by `dt` seconds, or to the audio's position if there's an audio
+    /// track, and renders the matching frame if it changed.  Returns false once the end of the
+    /// video has been reached, after which playback stops.
+    pub fn update(&mut self, dt: f32) -> Result<bool> {
+        if !self.playing {
+            return Ok(true);
+        }
+        let audio_playing = match &self.audio {
+            Some(audio) => audio.is_playing()?,
+            None => false,
+        };
+        self.position = match &self.audio {
+            Some(audio) if audio_playing => audio.get_offset()?,
+            _ => self.position + dt,
+        };
+        let frame = (self.position * self.info.frame_rate) as i32;
+        if frame >= self.info.frame_count {
+            let last = self.info.frame_count - 1;
+            if last >= 0 && self.rendered_frame != Some(last) {
+                self.render_frame(last)?;
+            }
+            self.stop()?;
+            return Ok(false);
+        }
+        let frame = frame.max(0);
+        if self.rendered_frame != Some(frame) {
+            self.render_frame(frame)?;
+        }
+        Ok(true)
+    }
+}
@@ -20,7 +20,7 @@ use {
         file::FileSystem,
         graphics::{Graphics, PDRect},
         lua::Lua,
-        sound::Sound,
+        sound::{HeadphoneState, Sound},
         sprite::{
             Sprite, SpriteCollideFunction, SpriteDrawFunction, SpriteManager, SpriteUpdateFunction,
         },
@@ -118,6 +118,33 @@ pub trait Game {
 
     fn update(&mut self, playdate: &mut Playdate) -> Result<(), Error>;
 
+    /// Called when the system menu opens, e.g. to pause a `MusicPlayer`.
+    fn pause(&mut self, _playdate: &mut Playdate) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Called when the system menu closes.
+    fn resume(&mut self, _playdate: &mut Playdate) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Return true to have `headphones_changed` called when headphones are plugged in or
+    /// unplugged.  Note that the system then no longer switches outputs by itself.
+    fn handles_headphone_changes(&self) -> bool {
+        false
+    }
+
+    /// Called when headphones are plugged in or unplugged, if `handles_headphone_changes` returns
+    /// true.  By default, switches the output the way the system would; override it to also
+    /// e.g. pause the game.
+    fn headphones_changed(
+        &mut self,
+        state: HeadphoneState,
+        _playdate: &mut Playdate,
+    ) -> Result<(), Error> {
+        Sound::get().set_outputs_active(state.headphones, !state.headphones)
+    }
+
     fn draw_fps(&self) -> bool {
         false
     }
@@ -169,6 +196,48 @@ impl<T: 'static + Game> GameRunner<T> {
         }
     }
 
+    pub fn pause(&mut self) {
+        if let Some(game) = self.game.as_mut() {
+            if let Err(err) = game.pause(&mut self.playdate) {
+                log_to_console!("Error in pause: {err:#}")
+            }
+        }
+    }
+
+    pub fn resume(&mut self) {
+        if let Some(game) = self.game.as_mut() {
+            if let Err(err) = game.resume(&mut self.playdate) {
+                log_to_console!("Error in resume: {err:#}")
+            }
+        }
+    }
+
+    pub fn init_headphone_events(
+        &mut self,
+        callback: unsafe extern "C" fn(
+            crankstart_sys::ctypes::c_int,
+            crankstart_sys::ctypes::c_int,
+        ),
+    ) {
+        if self
+            .game
+            .as_ref()
+            .is_some_and(|game| game.handles_headphone_changes())
+        {
+            if let Err(err) = Sound::get().set_headphone_change_callback(Some(callback)) {
+                log_to_console!("Error from sound().set_headphone_change_callback: {err:#}")
+            }
+        }
+    }
+
+    pub fn headphones_changed(&mut self, state: HeadphoneState) {
+        if let Some(game) = self.game.as_mut() {
+            if let Err(err) = game.headphones_changed(state, &mut self.playdate) {
+                log_to_console!("Error in headphones_changed: {err:#}")
+            }
+        }
+    }
+
     pub fn update_sprite(&mut self, sprite: *mut LCDSprite) {
         if let Some(game) = self.game.as_mut() {
             if let Some(mut sprite) = SpriteManager::get_mut().get_sprite(sprite) {
@@ -214,8 +283,8 @@ macro_rules! crankstart_game {
             use {
                 alloc::{boxed::Box, format},
                 crankstart::{
-                    graphics::PDRect, log_to_console, sprite::SpriteManager, system::System,
-                    GameRunner, Playdate,
+                    graphics::PDRect, log_to_console, sound::HeadphoneState, sprite::SpriteManager,
+                    system::System, GameRunner, Playdate,
                 },
                 crankstart_sys::{
                     LCDRect, LCDSprite, PDSystemEvent, PlaydateAPI, SpriteCollisionResponseType,
@@ -234,6 +303,14 @@ macro_rules! crankstart_game {
                 game_runner.draw_sprite(sprite, bounds, drawrect);
             }
 
+            extern "C" fn headphones_changed(
+                headphones: crankstart_sys::ctypes::c_int,
+                microphone: crankstart_sys::ctypes::c_int,
+            ) {
+                let game_runner = unsafe { GAME_RUNNER.as_mut().expect("GAME_RUNNER") };
+                game_runner.headphones_changed(HeadphoneState::from_raw(headphones, microphone));
+            }
+
             extern "C" fn update(_user_data: *mut core::ffi::c_void) -> i32 {
                 let game_runner = unsafe { GAME_RUNNER.as_mut().expect("GAME_RUNNER") };
 
@@ -272,6 +349,17 @@ macro_rules! crankstart_game {
 
                     unsafe {
                         GAME_RUNNER = Some(GameRunner::new(game, playdate));
+                        if let Some(game_runner) = GAME_RUNNER.as_mut() {
+                            game_runner.init_headphone_events(headphones_changed);
+                        }
+                    }
+                } else if event == PDSystemEvent::kEventPause {
+                    if let Some(game_runner) = unsafe { GAME_RUNNER.as_mut() } {
+                        game_runner.pause();
+                    }
+                } else if event == PDSystemEvent::kEventResume {
+                    if let Some(game_runner) = unsafe { GAME_RUNNER.as_mut() } {
+                        game_runner.resume();
                     }
                 }
                 0
@@ -288,6 +376,7 @@ fn abort_with_addr(addr: usize) -> ! {
     core::intrinsics::abort()
 }
 
+#[cfg(not(test))]
 #[panic_handler]
 fn panic(#[allow(unused)] panic_info: &PanicInfo) -> ! {
     use arrayvec::ArrayString;
@@ -346,10 +435,12 @@ unsafe impl GlobalAlloc for PlaydateAllocator {
     }
 }
 
+#[cfg(not(test))]
 #[global_allocator]
 pub(crate) static mut A: PlaydateAllocator = PlaydateAllocator;
 
 // define what happens in an Out Of Memory (OOM) condition
+#[cfg(not(test))]
 #[alloc_error_handler]
 fn alloc_error(_layout: Layout) -> ! {
     System::log_to_console("Out of Memory\0");
@@ -431,60 +522,70 @@ pub unsafe extern "C" fn __bzero(s: *mut u8, n: usize) {
     memset_internal(s, 0, n);
 }
 
+// Stubs for C library functions the device build links against; host tests use the real ones.
+#[cfg(not(test))]
 #[no_mangle]
 pub extern "C" fn _sbrk() {}
 
-#[cfg(not(target_os = "windows"))]
+#[cfg(not(any(test, target_os = "windows")))]
 #[no_mangle]
 pub extern "C" fn _write() {}
 
-#[cfg(not(target_os = "windows"))]
+#[cfg(not(any(test, target_os = "windows")))]
 #[no_mangle]
 pub extern "C" fn _close() {}
 
-#[cfg(not(target_os = "windows"))]
+#[cfg(not(any(test, target_os = "windows")))]
 #[no_mangle]
 pub extern "C" fn _lseek() {}
 
-#[cfg(not(target_os = "windows"))]
+#[cfg(not(any(test, target_os = "windows")))]
 #[no_mangle]
 pub extern "C" fn _read() {}
 
+#[cfg(not(test))]
 #[no_mangle]
 pub extern "C" fn _fstat() {}
 
+#[cfg(not(test))]
 #[no_mangle]
 pub extern "C" fn _isatty() {}
 
-#[cfg(not(target_os = "windows"))]
+#[cfg(not(any(test, target_os = "windows")))]
 #[no_mangle]
 pub extern "C" fn _exit() {}
 
+#[cfg(not(test))]
 #[no_mangle]
 pub extern "C" fn _open() {}
 
+#[cfg(not(test))]
 #[no_mangle]
 pub extern "C" fn _kill() {}
 
+#[cfg(not(test))]
 #[no_mangle]
 pub extern "C" fn _getpid() {}
 
+#[cfg(not(test))]
 #[no_mangle]
 pub extern "C" fn rust_eh_personality() {
     unimplemented!();
 }
 
-#[cfg(target_os = "macos")]
+#[cfg(all(not(test), target_os = "macos"))]
 #[no_mangle]
 extern "C" fn _Unwind_Resume() {
     unimplemented!();
 }
 
+#[cfg(not(test))]
 #[no_mangle]
 extern "C" fn __exidx_start() {
     unimplemented!();
 }
 
+#[cfg(not(test))]
 #[no_mangle]
 extern "C" fn __exidx_end() {
     unimplemented!();
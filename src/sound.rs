@@ -17,23 +17,95 @@
 //! music.load_into_player("music.pda")?;
 //! music.play(0)?;
 //! ```
+//!
+//! To play a note on a synthesizer:
+//! ```rust
+//! let mut synth = Sound::get().get_synth()?;
+//! synth.set_waveform(SoundWaveform::kWaveformSquare)?;
+//! synth.set_adsr(0.0, 0.1, 0.5, 0.2)?;
+//! synth.play_midi_note(60.0, 1.0, Some(0.25), 0)?;
+//! ```
 
 use crate::{pd_func_caller, pd_func_caller_log};
 use crankstart_sys::ctypes;
 
+use alloc::{boxed::Box, vec::Vec};
 use anyhow::{anyhow, ensure, Error, Result};
 use core::ptr;
 use cstr_core::CString;
 
 pub mod sampleplayer;
-pub use sampleplayer::{AudioSample, SamplePlayer};
+use sampleplayer::bytes_per_frame;
+pub use sampleplayer::{AudioSample, SampleData, SamplePlayer, SoundFormat};
 pub mod fileplayer;
 pub use fileplayer::FilePlayer;
+pub mod synth;
+pub use synth::{MIDINote, SoundWaveform, Synth, SynthGenerator};
+pub mod source;
+pub use source::{CustomSource, SourceGenerator};
+pub mod signal;
+pub use signal::{CustomSignal, Modulator, Signal, SignalGenerator};
+pub mod control;
+pub use control::ControlSignal;
+pub mod lfo;
+pub use lfo::{LFOType, Lfo};
+pub mod envelope;
+pub use envelope::Envelope;
+pub mod instrument;
+pub use instrument::Instrument;
+pub mod track;
+pub use track::{NoteEvent, Track};
+pub mod sequence;
+pub use sequence::Sequence;
+pub mod midi;
+pub use midi::MidiFile;
+pub mod channel;
+pub use channel::{Channel, LevelSignal, Source, SourceId};
+pub mod microphone;
+pub use microphone::{MicSource, Microphone, Recorder, RecordingBuffer, MIC_SAMPLE_RATE};
+pub mod music;
+pub use music::{LoopPoints, MusicDeck, MusicPlayer, MusicTrack};
+pub mod sfx;
+pub use sfx::{SfxBank, SfxOptions};
+pub mod effect;
+pub use effect::{
+    BitCrusher, DelayLine, DelayLineTap, Effect, OnePoleFilter, Overdrive, RingModulator,
+    TwoPoleFilter, TwoPoleFilterType,
+};
 
 // When the Playdate system struct is created, it passes the given playdate_sound to Sound::new,
 // which then replaces this.
 static mut SOUND: Sound = Sound::null();
 
+// The wrapper for the system's default channel, made on first use.  Sources added to a channel
+// are removed when its wrapper is dropped, so every caller shares this one.
+static mut DEFAULT_CHANNEL: Option<Channel> = None;
+
+type HeadphoneCallback = unsafe extern "C" fn(ctypes::c_int, ctypes::c_int);
+
+// The callback given to set_headphone_change_callback.  getHeadphoneState replaces the
+// callback with the one it's passed, so queries pass this one back to keep it registered.
+static mut HEADPHONE_CALLBACK: Option<HeadphoneCallback> = None;
+
+/// What's plugged into the headphone jack; see `Sound::get_headphone_state`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct HeadphoneState {
+    /// Headphones are plugged in.
+    pub headphones: bool,
+    /// The headphones have a microphone.
+    pub microphone: bool,
+}
+
+impl HeadphoneState {
+    /// Builds a state from the flags the system passes to headphone callbacks.
+    pub fn from_raw(headphones: ctypes::c_int, microphone: ctypes::c_int) -> Self {
+        Self {
+            headphones: headphones != 0,
+            microphone: microphone != 0,
+        }
+    }
+}
+
 /// `Sound` is the main interface to the Playdate audio subsystems.
 #[derive(Clone, Debug)]
 pub struct Sound {
@@ -44,10 +116,19 @@ pub struct Sound {
     raw_file_player: *const crankstart_sys::playdate_sound_fileplayer,
     raw_sample: *const crankstart_sys::playdate_sound_sample,
     raw_sample_player: *const crankstart_sys::playdate_sound_sampleplayer,
+    raw_synth: *const crankstart_sys::playdate_sound_synth,
+    raw_lfo: *const crankstart_sys::playdate_sound_lfo,
+    raw_envelope: *const crankstart_sys::playdate_sound_envelope,
+    raw_instrument: *const crankstart_sys::playdate_sound_instrument,
+    raw_track: *const crankstart_sys::playdate_sound_track,
+    raw_sequence: *const crankstart_sys::playdate_sound_sequence,
+    raw_control_signal: *const crankstart_sys::playdate_control_signal,
+    raw_effect: *const crankstart_sys::playdate_sound_effect,
+    raw_channel: *const crankstart_sys::playdate_sound_channel,
+    raw_signal: *const crankstart_sys::playdate_sound_signal,
 }
 
-// Not implemented: addSource, removeSource, setMicCallback, and getHeadphoneState (waiting on
-// crankstart callback strategy), getDefaultChannel, addChannel, removeChannel.
+// Not implemented: removeChannel (drop the Channel instead).
 impl Sound {
     const fn null() -> Self {
         Self {
@@ -55,6 +136,16 @@ impl Sound {
             raw_file_player: ptr::null(),
             raw_sample: ptr::null(),
             raw_sample_player: ptr::null(),
+            raw_synth: ptr::null(),
+            raw_lfo: ptr::null(),
+            raw_envelope: ptr::null(),
+            raw_instrument: ptr::null(),
+            raw_track: ptr::null(),
+            raw_sequence: ptr::null(),
+            raw_control_signal: ptr::null(),
+            raw_effect: ptr::null(),
+            raw_channel: ptr::null(),
+            raw_signal: ptr::null(),
         }
     }
 
@@ -70,12 +161,42 @@ impl Sound {
         ensure!(!raw_sample.is_null(), "Null sound.sample");
         let raw_sample_player = unsafe { (*raw_sound).sampleplayer };
         ensure!(!raw_sample_player.is_null(), "Null sound.sampleplayer");
+        let raw_synth = unsafe { (*raw_sound).synth };
+        ensure!(!raw_synth.is_null(), "Null sound.synth");
+        let raw_lfo = unsafe { (*raw_sound).lfo };
+        ensure!(!raw_lfo.is_null(), "Null sound.lfo");
+        let raw_envelope = unsafe { (*raw_sound).envelope };
+        ensure!(!raw_envelope.is_null(), "Null sound.envelope");
+        let raw_instrument = unsafe { (*raw_sound).instrument };
+        ensure!(!raw_instrument.is_null(), "Null sound.instrument");
+        let raw_track = unsafe { (*raw_sound).track };
+        ensure!(!raw_track.is_null(), "Null sound.track");
+        let raw_sequence = unsafe { (*raw_sound).sequence };
+        ensure!(!raw_sequence.is_null(), "Null sound.sequence");
+        let raw_control_signal = unsafe { (*raw_sound).controlsignal };
+        ensure!(!raw_control_signal.is_null(), "Null sound.controlsignal");
+        let raw_effect = unsafe { (*raw_sound).effect };
+        ensure!(!raw_effect.is_null(), "Null sound.effect");
+        let raw_channel = unsafe { (*raw_sound).channel };
+        ensure!(!raw_channel.is_null(), "Null sound.channel");
+        let raw_signal = unsafe { (*raw_sound).signal };
+        ensure!(!raw_signal.is_null(), "Null sound.signal");
 
         let sound = Self {
             raw_sound,
             raw_file_player,
             raw_sample,
             raw_sample_player,
+            raw_synth,
+            raw_lfo,
+            raw_envelope,
+            raw_instrument,
+            raw_track,
+            raw_sequence,
+            raw_control_signal,
+            raw_effect,
+            raw_channel,
+            raw_signal,
         };
         unsafe { SOUND = sound };
         Ok(())
@@ -96,6 +217,22 @@ impl Sound {
         FilePlayer::new(self.raw_file_player, raw_player)
     }
 
+    /// Get a `MusicPlayer` for playing background music, backed by two `FilePlayer`s.
+    pub fn get_music_player(&self) -> Result<MusicPlayer> {
+        Ok(MusicPlayer::with_decks([
+            self.get_file_player()?,
+            self.get_file_player()?,
+        ]))
+    }
+
+    /// Get an empty `SfxBank` with a pool of `voices` sample players.
+    pub fn get_sfx_bank(&self, voices: usize) -> Result<SfxBank> {
+        let players = (0..voices)
+            .map(|_| self.get_sample_player())
+            .collect::<Result<Vec<_>>>()?;
+        Ok(SfxBank::new(players))
+    }
+
     /// Get a `SamplePlayer` that can be used to play sound effects.
     pub fn get_sample_player(&self) -> Result<SamplePlayer> {
         let raw_player = pd_func_caller!((*self.raw_sample_player).newPlayer)?;
@@ -106,6 +243,276 @@ impl Sound {
         SamplePlayer::new(self.raw_sample_player, raw_player)
     }
 
+    /// Get a `Synth` that can be used to play notes, e.g. for chiptune sound effects.
+    pub fn get_synth(&self) -> Result<Synth> {
+        let raw_synth = pd_func_caller!((*self.raw_synth).newSynth)?;
+        ensure!(!raw_synth.is_null(), "Null returned from synth.newSynth");
+        Synth::new(self.raw_synth, raw_synth)
+    }
+
+    /// Get an `Lfo` of the given type, e.g. to add vibrato to a `Synth`.
+    pub fn get_lfo(&self, lfo_type: LFOType) -> Result<Lfo> {
+        let raw_lfo = pd_func_caller!((*self.raw_lfo).newLFO, lfo_type)?;
+        ensure!(!raw_lfo.is_null(), "Null returned from lfo.newLFO");
+        Lfo::new(self.raw_lfo, raw_lfo)
+    }
+
+    /// Get an `Envelope` with the given attack, decay and release times, in seconds, and
+    /// sustain level, from 0 to 1.
+    pub fn get_envelope(
+        &self,
+        attack: f32,
+        decay: f32,
+        sustain: f32,
+        release: f32,
+    ) -> Result<Envelope> {
+        let raw_envelope = pd_func_caller!(
+            (*self.raw_envelope).newEnvelope,
+            attack,
+            decay,
+            sustain,
+            release
+        )?;
+        ensure!(
+            !raw_envelope.is_null(),
+            "Null returned from envelope.newEnvelope"
+        );
+        Envelope::new(self.raw_envelope, raw_envelope)
+    }
+
+    /// Get an empty `ControlSignal`; add values to it with `ControlSignal::add_event`.
+    pub fn get_control_signal(&self) -> Result<ControlSignal> {
+        let raw_signal = pd_func_caller!((*self.raw_control_signal).newSignal)?;
+        ensure!(
+            !raw_signal.is_null(),
+            "Null returned from controlsignal.newSignal"
+        );
+        ControlSignal::new(self.raw_control_signal, raw_signal, None)
+    }
+
+    /// Get a `CustomSignal` driven by the given generator.
+    pub fn get_custom_signal<G: SignalGenerator>(&self, generator: G) -> Result<CustomSignal> {
+        CustomSignal::new(self.raw_signal, generator)
+    }
+
+    /// Get an empty `Instrument`; add `Synth` voices to it with `Instrument::add_voice`.
+    pub fn get_instrument(&self) -> Result<Instrument> {
+        let raw_instrument = pd_func_caller!((*self.raw_instrument).newInstrument)?;
+        ensure!(
+            !raw_instrument.is_null(),
+            "Null returned from instrument.newInstrument"
+        );
+        Instrument::new(self.raw_instrument, raw_instrument)
+    }
+
+    /// Get an empty `Track`, which can be added to a `Sequence` with
+    /// `Sequence::set_track_at_index`.
+    pub fn get_track(&self) -> Result<Track> {
+        let raw_track = pd_func_caller!((*self.raw_track).newTrack)?;
+        ensure!(!raw_track.is_null(), "Null returned from track.newTrack");
+        Track::new(self.raw_track, self.raw_control_signal, raw_track, None)
+    }
+
+    /// Get an empty `Sequence`; load a MIDI file into it with `Sequence::load_midi_file` or
+    /// build it up from `Track`s.
+    pub fn get_sequence(&self) -> Result<Sequence> {
+        let raw_sequence = pd_func_caller!((*self.raw_sequence).newSequence)?;
+        ensure!(
+            !raw_sequence.is_null(),
+            "Null returned from sequence.newSequence"
+        );
+        Sequence::new(
+            self.raw_sequence,
+            self.raw_track,
+            self.raw_control_signal,
+            raw_sequence,
+        )
+    }
+
+    /// Get the default `Channel`, where sources play unless they're added to another channel.
+    /// Every call returns a handle to the same channel, so sources added through one stay in the
+    /// channel after it's dropped.
+    pub fn get_default_channel(&self) -> Result<Channel> {
+        #[allow(static_mut_refs)]
+        let default_channel = unsafe { &mut DEFAULT_CHANNEL };
+        if let Some(channel) = default_channel {
+            return Ok(channel.clone());
+        }
+        let raw_channel = pd_func_caller!((*self.raw_sound).getDefaultChannel)?;
+        ensure!(
+            !raw_channel.is_null(),
+            "Null returned from sound.getDefaultChannel"
+        );
+        let channel = Channel::new(self.raw_sound, self.raw_channel, raw_channel, false)?;
+        *default_channel = Some(channel.clone());
+        Ok(channel)
+    }
+
+    /// Creates a new `Channel` and adds it to the mixer.  Dropping the last reference to the
+    /// channel removes it from the mixer and frees it, along with the sources it owns.
+    pub fn add_channel(&self) -> Result<Channel> {
+        let raw_channel = pd_func_caller!((*self.raw_channel).newChannel)?;
+        ensure!(
+            !raw_channel.is_null(),
+            "Null returned from channel.newChannel"
+        );
+        // Wrap it first so it's freed if adding fails.
+        let channel = Channel::new(self.raw_sound, self.raw_channel, raw_channel, true)?;
+        let result = pd_func_caller!((*self.raw_sound).addChannel, raw_channel)?;
+        ensure!(
+            result == 1,
+            "sound.addChannel should return 1; returned {}",
+            result
+        );
+        Ok(channel)
+    }
+
+    /// Get a `TwoPoleFilter` effect of the given type.
+    pub fn get_two_pole_filter(&self, filter_type: TwoPoleFilterType) -> Result<TwoPoleFilter> {
+        let raw_filter = pd_func_caller!((*(*self.raw_effect).twopolefilter).newFilter)?;
+        ensure!(
+            !raw_filter.is_null(),
+            "Null returned from twopolefilter.newFilter"
+        );
+        let filter = TwoPoleFilter::new(self.raw_effect, raw_filter)?;
+        filter.set_type(filter_type)?;
+        Ok(filter)
+    }
+
+    /// Get a `OnePoleFilter` effect.
+    pub fn get_one_pole_filter(&self) -> Result<OnePoleFilter> {
+        let raw_filter = pd_func_caller!((*(*self.raw_effect).onepolefilter).newFilter)?;
+        ensure!(
+            !raw_filter.is_null(),
+            "Null returned from onepolefilter.newFilter"
+        );
+        OnePoleFilter::new(self.raw_effect, raw_filter)
+    }
+
+    /// Get a `BitCrusher` effect.
+    pub fn get_bit_crusher(&self) -> Result<BitCrusher> {
+        let raw_bit_crusher = pd_func_caller!((*(*self.raw_effect).bitcrusher).newBitCrusher)?;
+        ensure!(
+            !raw_bit_crusher.is_null(),
+            "Null returned from bitcrusher.newBitCrusher"
+        );
+        BitCrusher::new(self.raw_effect, raw_bit_crusher)
+    }
+
+    /// Get a `RingModulator` effect.
+    pub fn get_ring_modulator(&self) -> Result<RingModulator> {
+        let raw_ring_modulator = pd_func_caller!((*(*self.raw_effect).ringmodulator).newRingmod)?;
+        ensure!(
+            !raw_ring_modulator.is_null(),
+            "Null returned from ringmodulator.newRingmod"
+        );
+        RingModulator::new(self.raw_effect, raw_ring_modulator)
+    }
+
+    /// Get a `DelayLine` effect with the given length, in frames (44.1k per second).
+    pub fn get_delay_line(&self, length: i32, stereo: bool) -> Result<DelayLine> {
+        let raw_delay_line = pd_func_caller!(
+            (*(*self.raw_effect).delayline).newDelayLine,
+            length,
+            stereo as ctypes::c_int
+        )?;
+        ensure!(
+            !raw_delay_line.is_null(),
+            "Null returned from delayline.newDelayLine"
+        );
+        DelayLine::new(self.raw_effect, raw_delay_line)
+    }
+
+    /// Get an `Overdrive` effect.
+    pub fn get_overdrive(&self) -> Result<Overdrive> {
+        let raw_overdrive = pd_func_caller!((*(*self.raw_effect).overdrive).newOverdrive)?;
+        ensure!(
+            !raw_overdrive.is_null(),
+            "Null returned from overdrive.newOverdrive"
+        );
+        Overdrive::new(self.raw_effect, raw_overdrive)
+    }
+
+    /// Starts capturing the microphone, calling `callback` with each block of 16-bit mono samples
+    /// at 44.1kHz.  Return false from the callback to stop capture.  Capture also stops when the
+    /// returned `Microphone` is dropped.
+    pub fn start_microphone(
+        &self,
+        source: MicSource,
+        callback: impl FnMut(&[i16]) -> bool + 'static,
+    ) -> Result<Microphone> {
+        Microphone::new(self.raw_sound, source, Box::new(callback))
+    }
+
+    /// Creates an `AudioSample` from raw audio data in the given format, e.g. for sounds
+    /// synthesized procedurally or decoded from your own formats.  The sample takes ownership of
+    /// the data.
+    pub fn new_audio_sample_from_data(
+        &self,
+        data: Vec<u8>,
+        format: SoundFormat,
+        sample_rate: u32,
+    ) -> Result<AudioSample> {
+        ensure!(!data.is_empty(), "Can't create an AudioSample with no data");
+        if let Some(bytes_per_frame) = bytes_per_frame(format) {
+            ensure!(
+                data.len().is_multiple_of(bytes_per_frame),
+                "Data length {} isn't a whole number of {:?} frames",
+                data.len(),
+                format
+            );
+        }
+        let byte_count = data.len() as ctypes::c_int;
+        // The system frees the data along with the sample, and our allocator is the system's, so
+        // hand over ownership.
+        let data = Box::into_raw(data.into_boxed_slice());
+        let raw_audio_sample = pd_func_caller!(
+            (*self.raw_sample).newSampleFromData,
+            data as *mut u8,
+            format,
+            sample_rate,
+            byte_count,
+            1
+        )
+        .and_then(|raw_audio_sample| {
+            ensure!(
+                !raw_audio_sample.is_null(),
+                "Null returned from sample.newSampleFromData"
+            );
+            Ok(raw_audio_sample)
+        });
+        let raw_audio_sample = match raw_audio_sample {
+            Ok(raw_audio_sample) => raw_audio_sample,
+            Err(err) => {
+                // No sample took the data, so it's still ours to free.
+                unsafe {
+                    let _ = Box::from_raw(data);
+                }
+                return Err(err);
+            }
+        };
+        AudioSample::new(self.raw_sample, raw_audio_sample)
+    }
+
+    /// Creates a 16-bit `AudioSample` from samples; stereo samples are interleaved left, right.
+    pub fn new_audio_sample_from_pcm(
+        &self,
+        samples: &[i16],
+        stereo: bool,
+        sample_rate: u32,
+    ) -> Result<AudioSample> {
+        let format = if stereo {
+            SoundFormat::kSound16bitStereo
+        } else {
+            SoundFormat::kSound16bitMono
+        };
+        let data = samples
+            .iter()
+            .flat_map(|sample| sample.to_le_bytes())
+            .collect();
+        self.new_audio_sample_from_data(data, format, sample_rate)
+    }
+
     /// Loads an `AudioSample` sound effect.  Assign it to a `SamplePlayer` with
     /// `SamplePlayer.set_sample`.
     pub fn load_audio_sample(&self, sample_path: &str) -> Result<AudioSample> {
@@ -119,11 +526,52 @@ impl Sound {
         AudioSample::new(self.raw_sample, raw_audio_sample)
     }
 
+    /// Registers a `SourceGenerator` implemented in Rust as an audio source on the default
+    /// channel.  It plays until the returned `CustomSource` is dropped or removed.
+    pub fn add_source<T: SourceGenerator>(
+        &self,
+        generator: T,
+        stereo: bool,
+    ) -> Result<CustomSource<T>> {
+        CustomSource::new(self.raw_sound, generator, stereo)
+    }
+
     /// Returns the sound engine's current time, in frames, 44.1k per second.
     pub fn get_current_time(&self) -> Result<ctypes::c_uint> {
         pd_func_caller!((*self.raw_sound).getCurrentTime)
     }
 
+    /// Returns whether headphones, and a headset microphone, are plugged in.  Any callback set
+    /// with `set_headphone_change_callback` stays registered.
+    pub fn get_headphone_state(&self) -> Result<HeadphoneState> {
+        self.headphone_state(unsafe { HEADPHONE_CALLBACK })
+    }
+
+    /// Registers the function the system calls when headphones are plugged in or unplugged;
+    /// None goes back to the default.  While a callback is set, the system no longer switches
+    /// between speaker and headphones by itself, so the callback should call
+    /// `set_outputs_active`.  Games normally use `Game::headphones_changed` instead.
+    pub fn set_headphone_change_callback(
+        &self,
+        callback: Option<HeadphoneCallback>,
+    ) -> Result<HeadphoneState> {
+        let state = self.headphone_state(callback)?;
+        unsafe { HEADPHONE_CALLBACK = callback };
+        Ok(state)
+    }
+
+    fn headphone_state(&self, callback: Option<HeadphoneCallback>) -> Result<HeadphoneState> {
+        let mut headphones = 0;
+        let mut microphone = 0;
+        pd_func_caller!(
+            (*self.raw_sound).getHeadphoneState,
+            &mut headphones,
+            &mut microphone,
+            callback
+        )?;
+        Ok(HeadphoneState::from_raw(headphones, microphone))
+    }
+
     /// Sets which audio outputs should be active.  Note: if you disable headphones and enable
     /// speaker, sound will be played through the speaker even if headphones are plugged in.
     pub fn set_outputs_active(&self, headphone: bool, speaker: bool) -> Result<()> {
@@ -134,3 +582,45 @@ impl Sound {
         )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The callback the fake getHeadphoneState was last given.
+    static mut GIVEN_CALLBACK: Option<HeadphoneCallback> = None;
+
+    unsafe extern "C" fn get_headphone_state(
+        headphone: *mut ctypes::c_int,
+        headsetmic: *mut ctypes::c_int,
+        callback: Option<HeadphoneCallback>,
+    ) {
+        *headphone = 1;
+        *headsetmic = 0;
+        GIVEN_CALLBACK = callback;
+    }
+
+    unsafe extern "C" fn headphones_changed(headphone: ctypes::c_int, mic: ctypes::c_int) {}
+
+    #[test]
+    fn queries_keep_the_headphone_callback() {
+        let mut raw_sound: crankstart_sys::playdate_sound = unsafe { core::mem::zeroed() };
+        raw_sound.getHeadphoneState = Some(get_headphone_state);
+        let sound = Sound {
+            raw_sound: &raw_sound,
+            ..Sound::null()
+        };
+
+        sound
+            .set_headphone_change_callback(Some(headphones_changed))
+            .unwrap();
+        let state = sound.get_headphone_state().unwrap();
+        assert!(state.headphones && !state.microphone);
+        let given = unsafe { GIVEN_CALLBACK };
+        assert_eq!(given.map(|f| f as usize), Some(headphones_changed as usize));
+
+        sound.set_headphone_change_callback(None).unwrap();
+        sound.get_headphone_state().unwrap();
+        assert!(unsafe { GIVEN_CALLBACK }.is_none());
+    }
+}
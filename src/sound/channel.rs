@@ -0,0 +1,381 @@
+use crate::{pd_func_caller, pd_func_caller_log};
+
+use super::effect::{Effect, EffectHandle};
+use super::signal::{raw_modulator, Modulator, Signal};
+use super::{DelayLineTap, FilePlayer, Instrument, SamplePlayer, Synth};
+use alloc::{boxed::Box, collections::BTreeMap, rc::Rc, vec::Vec};
+use anyhow::{anyhow, ensure, Error, Result};
+use core::{any::Any, cell::RefCell, fmt::Debug};
+
+/// Something that produces audio and can be added to a `Channel`.
+pub trait Source: Any + Debug {
+    /// Internal: returns the raw sound source.
+    fn raw_source(&self) -> *mut crankstart_sys::SoundSource;
+}
+
+impl Source for FilePlayer {
+    fn raw_source(&self) -> *mut crankstart_sys::SoundSource {
+        self.raw_player as *mut crankstart_sys::SoundSource
+    }
+}
+
+impl Source for SamplePlayer {
+    fn raw_source(&self) -> *mut crankstart_sys::SoundSource {
+        self.raw_player as *mut crankstart_sys::SoundSource
+    }
+}
+
+impl Source for Synth {
+    fn raw_source(&self) -> *mut crankstart_sys::SoundSource {
+        self.raw_synth as *mut crankstart_sys::SoundSource
+    }
+}
+
+impl Source for Instrument {
+    fn raw_source(&self) -> *mut crankstart_sys::SoundSource {
+        self.inner.raw_instrument as *mut crankstart_sys::SoundSource
+    }
+}
+
+impl Source for DelayLineTap {
+    fn raw_source(&self) -> *mut crankstart_sys::SoundSource {
+        self.inner.raw_tap as *mut crankstart_sys::SoundSource
+    }
+}
+
+/// Identifies a source owned by a `Channel`; returned by `Channel::add_source`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SourceId(u32);
+
+/// A mixer bus.  Sources added to a channel are mixed together, run through the channel's
+/// effects in order, and then panned and scaled by the channel's volume.  Sources that aren't
+/// added to any channel play on the default channel.
+// Really a wrapper around an Rc clone of the internal structure; derive Clone so it's easy to get
+// another Rc reference.  We use Rc so the level signals can keep the channel alive.
+#[derive(Clone, Debug)]
+pub struct Channel {
+    inner: Rc<ChannelInner>,
+}
+
+#[derive(Debug)]
+struct ChannelInner {
+    raw_sound: *const crankstart_sys::playdate_sound,
+    raw_subsystem: *const crankstart_sys::playdate_sound_channel,
+    raw_channel: *mut crankstart_sys::SoundChannel,
+    // The default channel belongs to the system, so we don't remove or free it.
+    owned: bool,
+
+    sources: RefCell<BTreeMap<SourceId, Box<dyn Source>>>,
+    next_source_id: RefCell<u32>,
+    // In processing order.
+    effects: RefCell<Vec<EffectHandle>>,
+    volume_modulator: RefCell<Option<Modulator>>,
+    pan_modulator: RefCell<Option<Modulator>>,
+}
+
+impl Drop for ChannelInner {
+    fn drop(&mut self) {
+        // Take everything out of the channel before the sources and effects are freed along
+        // with our fields.  Use _log to leak rather than fail.
+        if self.owned {
+            pd_func_caller_log!((*self.raw_sound).removeChannel, self.raw_channel);
+        }
+        for source in self.sources.borrow().values() {
+            pd_func_caller_log!(
+                (*self.raw_subsystem).removeSource,
+                self.raw_channel,
+                source.raw_source()
+            );
+        }
+        for effect in self.effects.borrow().iter() {
+            pd_func_caller_log!(
+                (*self.raw_subsystem).removeEffect,
+                self.raw_channel,
+                effect.raw_effect
+            );
+        }
+        if self.owned {
+            pd_func_caller_log!((*self.raw_subsystem).freeChannel, self.raw_channel);
+        }
+    }
+}
+
+// Not implemented: newChannel and freeChannel (use Sound::add_channel and drop the Channel),
+// addCallbackSource (use Sound::add_source), and getVolumeModulator and getPanModulator (the
+// modulators we set are held here instead).
+impl Channel {
+    pub(crate) fn new(
+        raw_sound: *const crankstart_sys::playdate_sound,
+        raw_subsystem: *const crankstart_sys::playdate_sound_channel,
+        raw_channel: *mut crankstart_sys::SoundChannel,
+        owned: bool,
+    ) -> Result<Self> {
+        ensure!(
+            !raw_sound.is_null(),
+            "Null pointer given as sound to Channel::new"
+        );
+        ensure!(
+            !raw_subsystem.is_null(),
+            "Null pointer given as subsystem to Channel::new"
+        );
+        ensure!(
+            !raw_channel.is_null(),
+            "Null pointer given as channel to Channel::new"
+        );
+        Ok(Self {
+            inner: Rc::new(ChannelInner {
+                raw_sound,
+                raw_subsystem,
+                raw_channel,
+                owned,
+                sources: RefCell::new(BTreeMap::new()),
+                next_source_id: RefCell::new(0),
+                effects: RefCell::new(Vec::new()),
+                volume_modulator: RefCell::new(None),
+                pan_modulator: RefCell::new(None),
+            }),
+        })
+    }
+
+    /// Moves a source into the channel, which owns it from then on.  Use the returned id with
+    /// `with_source` to control it, or with `remove_source` to get it back.
+    pub fn add_source<S: Source>(&self, source: S) -> Result<SourceId> {
+        let result = pd_func_caller!(
+            (*self.inner.raw_subsystem).addSource,
+            self.inner.raw_channel,
+            source.raw_source()
+        )?;
+        ensure!(
+            result == 1,
+            "channel.addSource should return 1; returned {}",
+            result
+        );
+        let mut next_source_id = self.inner.next_source_id.borrow_mut();
+        let id = SourceId(*next_source_id);
+        *next_source_id += 1;
+        self.inner.sources.borrow_mut().insert(id, Box::new(source));
+        Ok(id)
+    }
+
+    /// Calls `f` with the source with the given id, if it's in this channel and is an `S`.
+    pub fn with_source<S: Source, R>(
+        &self,
+        id: SourceId,
+        f: impl FnOnce(&mut S) -> R,
+    ) -> Option<R> {
+        let mut sources = self.inner.sources.borrow_mut();
+        let source: &mut dyn Any = sources.get_mut(&id)?.as_mut();
+        source.downcast_mut::<S>().map(f)
+    }
+
+    /// Takes the source with the given id out of the channel and gives it back.  Fails if it's
+    /// not in this channel or isn't an `S`, in which case the channel keeps it.
+    pub fn remove_source<S: Source>(&self, id: SourceId) -> Result<S> {
+        let mut sources = self.inner.sources.borrow_mut();
+        let source = sources
+            .get(&id)
+            .ok_or_else(|| anyhow!("No source {:?} in channel", id))?;
+        ensure!(
+            (source.as_ref() as &dyn Any).is::<S>(),
+            "Source {:?} has a different type",
+            id
+        );
+        let result = pd_func_caller!(
+            (*self.inner.raw_subsystem).removeSource,
+            self.inner.raw_channel,
+            source.raw_source()
+        )?;
+        ensure!(
+            result == 1,
+            "channel.removeSource should return 1; returned {}",
+            result
+        );
+        let source: Box<dyn Any> = sources.remove(&id).expect("source checked above");
+        Ok(*source.downcast::<S>().expect("type checked above"))
+    }
+
+    /// Returns the ids of the sources in this channel.
+    pub fn source_ids(&self) -> Vec<SourceId> {
+        self.inner.sources.borrow().keys().copied().collect()
+    }
+
+    /// Adds an effect to the end of the channel's effect chain.
+    pub fn add_effect(&self, effect: &dyn Effect) -> Result<()> {
+        let handle = effect.handle();
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).addEffect,
+            self.inner.raw_channel,
+            handle.raw_effect
+        )?;
+        self.inner.effects.borrow_mut().push(handle);
+        Ok(())
+    }
+
+    /// Inserts an effect at the given position in the effect chain; 0 processes it first.
+    pub fn insert_effect(&self, index: usize, effect: &dyn Effect) -> Result<()> {
+        let mut effects = self.inner.effects.borrow_mut();
+        ensure!(
+            index <= effects.len(),
+            "Effect index {} out of range; channel has {} effects",
+            index,
+            effects.len()
+        );
+        // The system only appends, so take off everything after the insertion point and add it
+        // back afterward.
+        for handle in &effects[index..] {
+            pd_func_caller!(
+                (*self.inner.raw_subsystem).removeEffect,
+                self.inner.raw_channel,
+                handle.raw_effect
+            )?;
+        }
+        effects.insert(index, effect.handle());
+        for handle in &effects[index..] {
+            pd_func_caller!(
+                (*self.inner.raw_subsystem).addEffect,
+                self.inner.raw_channel,
+                handle.raw_effect
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Removes an effect from the channel.  Returns false if it wasn't in the channel.
+    pub fn remove_effect(&self, effect: &dyn Effect) -> Result<bool> {
+        let raw_effect = effect.handle().raw_effect;
+        let mut effects = self.inner.effects.borrow_mut();
+        let position = effects
+            .iter()
+            .position(|handle| handle.raw_effect == raw_effect);
+        if let Some(position) = position {
+            pd_func_caller!(
+                (*self.inner.raw_subsystem).removeEffect,
+                self.inner.raw_channel,
+                raw_effect
+            )?;
+            effects.remove(position);
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    /// Returns how many effects are in the channel's effect chain.
+    pub fn effect_count(&self) -> usize {
+        self.inner.effects.borrow().len()
+    }
+
+    /// Gets the channel's volume, out of 1.
+    pub fn get_volume(&self) -> Result<f32> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).getVolume,
+            self.inner.raw_channel
+        )
+    }
+
+    /// Sets the channel's volume, out of 1.
+    pub fn set_volume(&self, volume: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setVolume,
+            self.inner.raw_channel,
+            volume
+        )
+    }
+
+    /// Sets a signal to modulate the volume; None removes it.  The volume is scaled by the
+    /// signal's value, so it rises and falls with it.  To duck music under other sounds, use
+    /// `MusicPlayer::duck`.
+    pub fn set_volume_modulator(&self, signal: Option<&dyn Signal>) -> Result<()> {
+        let modulator = self.modulator(signal)?;
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setVolumeModulator,
+            self.inner.raw_channel,
+            raw_modulator(modulator.as_ref())
+        )?;
+        *self.inner.volume_modulator.borrow_mut() = modulator;
+        Ok(())
+    }
+
+    /// Sets the channel's pan, from -1 (left) to 1 (right).
+    pub fn set_pan(&self, pan: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setPan,
+            self.inner.raw_channel,
+            pan
+        )
+    }
+
+    /// Sets a signal to modulate the pan; None removes it.
+    pub fn set_pan_modulator(&self, signal: Option<&dyn Signal>) -> Result<()> {
+        let modulator = self.modulator(signal)?;
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setPanModulator,
+            self.inner.raw_channel,
+            raw_modulator(modulator.as_ref())
+        )?;
+        *self.inner.pan_modulator.borrow_mut() = modulator;
+        Ok(())
+    }
+
+    /// Returns a signal that follows the level of the channel's input, before effects.
+    pub fn get_dry_level_signal(&self) -> Result<LevelSignal> {
+        let raw_signal = pd_func_caller!(
+            (*self.inner.raw_subsystem).getDryLevelSignal,
+            self.inner.raw_channel
+        )?;
+        self.level_signal(raw_signal, "getDryLevelSignal")
+    }
+
+    /// Returns a signal that follows the level of the channel's output, after effects.
+    pub fn get_wet_level_signal(&self) -> Result<LevelSignal> {
+        let raw_signal = pd_func_caller!(
+            (*self.inner.raw_subsystem).getWetLevelSignal,
+            self.inner.raw_channel
+        )?;
+        self.level_signal(raw_signal, "getWetLevelSignal")
+    }
+
+    // The channel holds its modulators, so one of its own level signals would keep it alive
+    // forever.
+    fn modulator(&self, signal: Option<&dyn Signal>) -> Result<Option<Modulator>> {
+        let modulator = signal.map(|signal| signal.modulator());
+        ensure!(
+            !modulator
+                .as_ref()
+                .is_some_and(|modulator| modulator.is_owned_by(&self.inner)),
+            "A channel can't be modulated by its own level signal"
+        );
+        Ok(modulator)
+    }
+
+    fn level_signal(
+        &self,
+        raw_signal: *mut crankstart_sys::PDSynthSignalValue,
+        function: &str,
+    ) -> Result<LevelSignal> {
+        ensure!(
+            !raw_signal.is_null(),
+            "Null returned from channel.{}",
+            function
+        );
+        Ok(LevelSignal {
+            raw_signal,
+            channel: self.inner.clone(),
+        })
+    }
+}
+
+/// The input or output level of a `Channel`, usable as a modulator; see
+/// `Channel::get_dry_level_signal`.  Keeps the channel alive while in use, so it can only
+/// modulate other channels and sources.
+#[derive(Clone, Debug)]
+pub struct LevelSignal {
+    raw_signal: *mut crankstart_sys::PDSynthSignalValue,
+    channel: Rc<ChannelInner>,
+}
+
+impl Signal for LevelSignal {
+    fn modulator(&self) -> Modulator {
+        Modulator::new(self.raw_signal, self.channel.clone())
+    }
+}
@@ -0,0 +1,106 @@
+use crate::{pd_func_caller, pd_func_caller_log};
+use crankstart_sys::ctypes;
+
+use super::signal::{Modulator, Signal};
+use alloc::rc::Rc;
+use anyhow::{ensure, Error, Result};
+use core::any::Any;
+
+/// A signal made of values at sequence steps, like MIDI controller automation.  Use it as a
+/// modulator anywhere a `Signal` is accepted.
+// Really a wrapper around an Rc clone of the internal structure; derive Clone so it's easy to get
+// another Rc reference.  We use Rc so we don't free the signal while it's still a modulator.
+#[derive(Clone, Debug)]
+pub struct ControlSignal {
+    inner: Rc<ControlSignalInner>,
+}
+
+#[derive(Debug)]
+struct ControlSignalInner {
+    raw_subsystem: *const crankstart_sys::playdate_control_signal,
+    raw_signal: *mut crankstart_sys::ControlSignal,
+    // Signals that belong to a track are freed with the track, so we hold the track instead of
+    // freeing them ourselves.
+    track: Option<Rc<dyn Any>>,
+}
+
+impl Drop for ControlSignalInner {
+    fn drop(&mut self) {
+        if self.track.is_none() {
+            // Use _log to leak rather than fail
+            pd_func_caller_log!((*self.raw_subsystem).freeSignal, self.raw_signal);
+        }
+    }
+}
+
+// Not implemented: newSignal (use Sound::get_control_signal).
+impl ControlSignal {
+    pub(crate) fn new(
+        raw_subsystem: *const crankstart_sys::playdate_control_signal,
+        raw_signal: *mut crankstart_sys::ControlSignal,
+        track: Option<Rc<dyn Any>>,
+    ) -> Result<Self> {
+        ensure!(
+            !raw_subsystem.is_null(),
+            "Null pointer given as subsystem to ControlSignal::new"
+        );
+        ensure!(
+            !raw_signal.is_null(),
+            "Null pointer given as signal to ControlSignal::new"
+        );
+        Ok(Self {
+            inner: Rc::new(ControlSignalInner {
+                raw_subsystem,
+                raw_signal,
+                track,
+            }),
+        })
+    }
+
+    /// Adds a value at the given step.  If `interpolate` is true, the signal ramps to it from
+    /// the previous event instead of jumping.
+    pub fn add_event(&self, step: i32, value: f32, interpolate: bool) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).addEvent,
+            self.inner.raw_signal,
+            step,
+            value,
+            interpolate as ctypes::c_int
+        )
+    }
+
+    /// Removes the event at the given step.
+    pub fn remove_event(&self, step: i32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).removeEvent,
+            self.inner.raw_signal,
+            step
+        )
+    }
+
+    /// Removes all events.
+    pub fn clear_events(&self) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).clearEvents,
+            self.inner.raw_signal
+        )
+    }
+
+    /// Returns the MIDI controller number the signal was loaded from, for signals on a track
+    /// loaded from a MIDI file.
+    pub fn get_midi_controller_number(&self) -> Result<i32> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).getMIDIControllerNumber,
+            self.inner.raw_signal
+        )
+    }
+}
+
+impl Signal for ControlSignal {
+    fn modulator(&self) -> Modulator {
+        Modulator::new(
+            self.inner.raw_signal as *mut crankstart_sys::PDSynthSignalValue,
+            self.inner.clone(),
+        )
+    }
+}
@@ -0,0 +1,762 @@
+use crate::{pd_func_caller, pd_func_caller_log};
+use crankstart_sys::ctypes;
+
+use super::signal::{raw_modulator, Modulator, Signal};
+use alloc::rc::Rc;
+use anyhow::{ensure, Error, Result};
+use core::{cell::RefCell, fmt::Debug};
+
+pub use crankstart_sys::TwoPoleFilterType;
+
+/// An audio effect that can be added to a `Channel`.  Each effect has a mix level, from 0 (dry)
+/// to 1 (fully processed), which can also be driven by a `Signal`.
+pub trait Effect: Debug {
+    /// Internal: returns the raw effect along with a handle that keeps it alive.
+    fn handle(&self) -> EffectHandle;
+
+    /// Sets the mix level, from 0 (only the unprocessed signal) to 1 (only the effect output).
+    fn set_mix(&self, level: f32) -> Result<()> {
+        let handle = self.handle();
+        pd_func_caller!((*handle.raw_subsystem).setMix, handle.raw_effect, level)
+    }
+
+    /// Sets a signal to modulate the mix level; None removes it.
+    fn set_mix_modulator(&self, signal: Option<&dyn Signal>) -> Result<()> {
+        let handle = self.handle();
+        let modulator = signal.map(|signal| signal.modulator());
+        pd_func_caller!(
+            (*handle.raw_subsystem).setMixModulator,
+            handle.raw_effect,
+            raw_modulator(modulator.as_ref())
+        )?;
+        *handle.state.mix_modulator().borrow_mut() = modulator;
+        Ok(())
+    }
+}
+
+// Implemented by the inner structure of each effect, so the Effect methods can reach the state
+// every effect has.
+pub(crate) trait EffectState: Debug {
+    fn mix_modulator(&self) -> &RefCell<Option<Modulator>>;
+}
+
+/// An effect attached to some other part of the audio system.  Holding one keeps the underlying
+/// effect from being freed while the sound engine is still using it.
+#[derive(Clone, Debug)]
+pub struct EffectHandle {
+    raw_subsystem: *const crankstart_sys::playdate_sound_effect,
+    pub(crate) raw_effect: *mut crankstart_sys::SoundEffect,
+    state: Rc<dyn EffectState>,
+}
+
+/// A resonant two-pole filter, e.g. a low pass filter for an underwater effect.
+// Really a wrapper around an Rc clone of the internal structure; derive Clone so it's easy to get
+// another Rc reference.  We use Rc so we don't free the effect while a channel still uses it.
+#[derive(Clone, Debug)]
+pub struct TwoPoleFilter {
+    inner: Rc<TwoPoleFilterInner>,
+}
+
+#[derive(Debug)]
+struct TwoPoleFilterInner {
+    raw_effect_subsystem: *const crankstart_sys::playdate_sound_effect,
+    raw_subsystem: *const crankstart_sys::playdate_sound_effect_twopolefilter,
+    raw_filter: *mut crankstart_sys::TwoPoleFilter,
+
+    // Modulators currently attached to the effect, held so they aren't freed while in use.
+    mix_modulator: RefCell<Option<Modulator>>,
+    frequency_modulator: RefCell<Option<Modulator>>,
+    resonance_modulator: RefCell<Option<Modulator>>,
+}
+
+impl Drop for TwoPoleFilterInner {
+    fn drop(&mut self) {
+        // Use _log to leak rather than fail
+        pd_func_caller_log!((*self.raw_subsystem).freeFilter, self.raw_filter);
+    }
+}
+
+impl EffectState for TwoPoleFilterInner {
+    fn mix_modulator(&self) -> &RefCell<Option<Modulator>> {
+        &self.mix_modulator
+    }
+}
+
+impl Effect for TwoPoleFilter {
+    fn handle(&self) -> EffectHandle {
+        EffectHandle {
+            raw_subsystem: self.inner.raw_effect_subsystem,
+            raw_effect: self.inner.raw_filter as *mut crankstart_sys::SoundEffect,
+            state: self.inner.clone(),
+        }
+    }
+}
+
+// Not implemented: newFilter (use Sound::get_two_pole_filter).
+impl TwoPoleFilter {
+    pub(crate) fn new(
+        raw_effect_subsystem: *const crankstart_sys::playdate_sound_effect,
+        raw_filter: *mut crankstart_sys::TwoPoleFilter,
+    ) -> Result<Self> {
+        ensure!(
+            !raw_effect_subsystem.is_null(),
+            "Null pointer given as subsystem to TwoPoleFilter::new"
+        );
+        ensure!(
+            !raw_filter.is_null(),
+            "Null pointer given as filter to TwoPoleFilter::new"
+        );
+        let raw_subsystem = unsafe { (*raw_effect_subsystem).twopolefilter };
+        Ok(Self {
+            inner: Rc::new(TwoPoleFilterInner {
+                raw_effect_subsystem,
+                raw_subsystem,
+                raw_filter,
+                mix_modulator: RefCell::new(None),
+                frequency_modulator: RefCell::new(None),
+                resonance_modulator: RefCell::new(None),
+            }),
+        })
+    }
+
+    /// Sets the kind of filter, e.g. low pass or high pass.
+    pub fn set_type(&self, filter_type: TwoPoleFilterType) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setType,
+            self.inner.raw_filter,
+            filter_type
+        )
+    }
+
+    /// Sets the center or corner frequency of the filter, in Hz.
+    pub fn set_frequency(&self, frequency: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setFrequency,
+            self.inner.raw_filter,
+            frequency
+        )
+    }
+
+    /// Sets a signal to modulate the frequency; None removes it.
+    pub fn set_frequency_modulator(&self, signal: Option<&dyn Signal>) -> Result<()> {
+        let modulator = signal.map(|signal| signal.modulator());
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setFrequencyModulator,
+            self.inner.raw_filter,
+            raw_modulator(modulator.as_ref())
+        )?;
+        *self.inner.frequency_modulator.borrow_mut() = modulator;
+        Ok(())
+    }
+
+    /// Sets the gain, for the peaking and shelf filter types.
+    pub fn set_gain(&self, gain: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setGain,
+            self.inner.raw_filter,
+            gain
+        )
+    }
+
+    /// Sets the resonance, from 0 to 1.
+    pub fn set_resonance(&self, resonance: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setResonance,
+            self.inner.raw_filter,
+            resonance
+        )
+    }
+
+    /// Sets a signal to modulate the resonance; None removes it.
+    pub fn set_resonance_modulator(&self, signal: Option<&dyn Signal>) -> Result<()> {
+        let modulator = signal.map(|signal| signal.modulator());
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setResonanceModulator,
+            self.inner.raw_filter,
+            raw_modulator(modulator.as_ref())
+        )?;
+        *self.inner.resonance_modulator.borrow_mut() = modulator;
+        Ok(())
+    }
+}
+
+/// A simple one-pole filter that acts as a low pass or high pass filter depending on its
+/// parameter.
+// Really a wrapper around an Rc clone of the internal structure; derive Clone so it's easy to get
+// another Rc reference.  We use Rc so we don't free the effect while a channel still uses it.
+#[derive(Clone, Debug)]
+pub struct OnePoleFilter {
+    inner: Rc<OnePoleFilterInner>,
+}
+
+#[derive(Debug)]
+struct OnePoleFilterInner {
+    raw_effect_subsystem: *const crankstart_sys::playdate_sound_effect,
+    raw_subsystem: *const crankstart_sys::playdate_sound_effect_onepolefilter,
+    raw_filter: *mut crankstart_sys::OnePoleFilter,
+
+    // Modulators currently attached to the effect, held so they aren't freed while in use.
+    mix_modulator: RefCell<Option<Modulator>>,
+    parameter_modulator: RefCell<Option<Modulator>>,
+}
+
+impl Drop for OnePoleFilterInner {
+    fn drop(&mut self) {
+        // Use _log to leak rather than fail
+        pd_func_caller_log!((*self.raw_subsystem).freeFilter, self.raw_filter);
+    }
+}
+
+impl EffectState for OnePoleFilterInner {
+    fn mix_modulator(&self) -> &RefCell<Option<Modulator>> {
+        &self.mix_modulator
+    }
+}
+
+impl Effect for OnePoleFilter {
+    fn handle(&self) -> EffectHandle {
+        EffectHandle {
+            raw_subsystem: self.inner.raw_effect_subsystem,
+            raw_effect: self.inner.raw_filter as *mut crankstart_sys::SoundEffect,
+            state: self.inner.clone(),
+        }
+    }
+}
+
+// Not implemented: newFilter (use Sound::get_one_pole_filter).
+impl OnePoleFilter {
+    pub(crate) fn new(
+        raw_effect_subsystem: *const crankstart_sys::playdate_sound_effect,
+        raw_filter: *mut crankstart_sys::OnePoleFilter,
+    ) -> Result<Self> {
+        ensure!(
+            !raw_effect_subsystem.is_null(),
+            "Null pointer given as subsystem to OnePoleFilter::new"
+        );
+        ensure!(
+            !raw_filter.is_null(),
+            "Null pointer given as filter to OnePoleFilter::new"
+        );
+        let raw_subsystem = unsafe { (*raw_effect_subsystem).onepolefilter };
+        Ok(Self {
+            inner: Rc::new(OnePoleFilterInner {
+                raw_effect_subsystem,
+                raw_subsystem,
+                raw_filter,
+                mix_modulator: RefCell::new(None),
+                parameter_modulator: RefCell::new(None),
+            }),
+        })
+    }
+
+    /// Sets the filter's parameter, from -1 to 1: negative values are a low pass filter,
+    /// positive values a high pass filter, and 0 passes everything.
+    pub fn set_parameter(&self, parameter: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setParameter,
+            self.inner.raw_filter,
+            parameter
+        )
+    }
+
+    /// Sets a signal to modulate the parameter; None removes it.
+    pub fn set_parameter_modulator(&self, signal: Option<&dyn Signal>) -> Result<()> {
+        let modulator = signal.map(|signal| signal.modulator());
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setParameterModulator,
+            self.inner.raw_filter,
+            raw_modulator(modulator.as_ref())
+        )?;
+        *self.inner.parameter_modulator.borrow_mut() = modulator;
+        Ok(())
+    }
+}
+
+/// Reduces bit depth and sample rate, for a lo-fi sound.
+// Really a wrapper around an Rc clone of the internal structure; derive Clone so it's easy to get
+// another Rc reference.  We use Rc so we don't free the effect while a channel still uses it.
+#[derive(Clone, Debug)]
+pub struct BitCrusher {
+    inner: Rc<BitCrusherInner>,
+}
+
+#[derive(Debug)]
+struct BitCrusherInner {
+    raw_effect_subsystem: *const crankstart_sys::playdate_sound_effect,
+    raw_subsystem: *const crankstart_sys::playdate_sound_effect_bitcrusher,
+    raw_bit_crusher: *mut crankstart_sys::BitCrusher,
+
+    // Modulators currently attached to the effect, held so they aren't freed while in use.
+    mix_modulator: RefCell<Option<Modulator>>,
+    amount_modulator: RefCell<Option<Modulator>>,
+    undersample_modulator: RefCell<Option<Modulator>>,
+}
+
+impl Drop for BitCrusherInner {
+    fn drop(&mut self) {
+        // Use _log to leak rather than fail
+        pd_func_caller_log!((*self.raw_subsystem).freeBitCrusher, self.raw_bit_crusher);
+    }
+}
+
+impl EffectState for BitCrusherInner {
+    fn mix_modulator(&self) -> &RefCell<Option<Modulator>> {
+        &self.mix_modulator
+    }
+}
+
+impl Effect for BitCrusher {
+    fn handle(&self) -> EffectHandle {
+        EffectHandle {
+            raw_subsystem: self.inner.raw_effect_subsystem,
+            raw_effect: self.inner.raw_bit_crusher as *mut crankstart_sys::SoundEffect,
+            state: self.inner.clone(),
+        }
+    }
+}
+
+// Not implemented: newBitCrusher (use Sound::get_bit_crusher).
+impl BitCrusher {
+    pub(crate) fn new(
+        raw_effect_subsystem: *const crankstart_sys::playdate_sound_effect,
+        raw_bit_crusher: *mut crankstart_sys::BitCrusher,
+    ) -> Result<Self> {
+        ensure!(
+            !raw_effect_subsystem.is_null(),
+            "Null pointer given as subsystem to BitCrusher::new"
+        );
+        ensure!(
+            !raw_bit_crusher.is_null(),
+            "Null pointer given as bit crusher to BitCrusher::new"
+        );
+        let raw_subsystem = unsafe { (*raw_effect_subsystem).bitcrusher };
+        Ok(Self {
+            inner: Rc::new(BitCrusherInner {
+                raw_effect_subsystem,
+                raw_subsystem,
+                raw_bit_crusher,
+                mix_modulator: RefCell::new(None),
+                amount_modulator: RefCell::new(None),
+                undersample_modulator: RefCell::new(None),
+            }),
+        })
+    }
+
+    /// Sets how much to reduce the bit depth, from 0 (none) to 1.
+    pub fn set_amount(&self, amount: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setAmount,
+            self.inner.raw_bit_crusher,
+            amount
+        )
+    }
+
+    /// Sets a signal to modulate the amount; None removes it.
+    pub fn set_amount_modulator(&self, signal: Option<&dyn Signal>) -> Result<()> {
+        let modulator = signal.map(|signal| signal.modulator());
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setAmountModulator,
+            self.inner.raw_bit_crusher,
+            raw_modulator(modulator.as_ref())
+        )?;
+        *self.inner.amount_modulator.borrow_mut() = modulator;
+        Ok(())
+    }
+
+    /// Sets how much to reduce the sample rate, from 0 (none) to 1.
+    pub fn set_undersampling(&self, undersampling: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setUndersampling,
+            self.inner.raw_bit_crusher,
+            undersampling
+        )
+    }
+
+    /// Sets a signal to modulate the undersampling; None removes it.
+    pub fn set_undersample_modulator(&self, signal: Option<&dyn Signal>) -> Result<()> {
+        let modulator = signal.map(|signal| signal.modulator());
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setUndersampleModulator,
+            self.inner.raw_bit_crusher,
+            raw_modulator(modulator.as_ref())
+        )?;
+        *self.inner.undersample_modulator.borrow_mut() = modulator;
+        Ok(())
+    }
+}
+
+/// Multiplies the signal by a sine wave, for a metallic or radio-like sound.
+// Really a wrapper around an Rc clone of the internal structure; derive Clone so it's easy to get
+// another Rc reference.  We use Rc so we don't free the effect while a channel still uses it.
+#[derive(Clone, Debug)]
+pub struct RingModulator {
+    inner: Rc<RingModulatorInner>,
+}
+
+#[derive(Debug)]
+struct RingModulatorInner {
+    raw_effect_subsystem: *const crankstart_sys::playdate_sound_effect,
+    raw_subsystem: *const crankstart_sys::playdate_sound_effect_ringmodulator,
+    raw_ring_modulator: *mut crankstart_sys::RingModulator,
+
+    // Modulators currently attached to the effect, held so they aren't freed while in use.
+    mix_modulator: RefCell<Option<Modulator>>,
+    frequency_modulator: RefCell<Option<Modulator>>,
+}
+
+impl Drop for RingModulatorInner {
+    fn drop(&mut self) {
+        // Use _log to leak rather than fail
+        pd_func_caller_log!((*self.raw_subsystem).freeRingmod, self.raw_ring_modulator);
+    }
+}
+
+impl EffectState for RingModulatorInner {
+    fn mix_modulator(&self) -> &RefCell<Option<Modulator>> {
+        &self.mix_modulator
+    }
+}
+
+impl Effect for RingModulator {
+    fn handle(&self) -> EffectHandle {
+        EffectHandle {
+            raw_subsystem: self.inner.raw_effect_subsystem,
+            raw_effect: self.inner.raw_ring_modulator as *mut crankstart_sys::SoundEffect,
+            state: self.inner.clone(),
+        }
+    }
+}
+
+// Not implemented: newRingmod (use Sound::get_ring_modulator).
+impl RingModulator {
+    pub(crate) fn new(
+        raw_effect_subsystem: *const crankstart_sys::playdate_sound_effect,
+        raw_ring_modulator: *mut crankstart_sys::RingModulator,
+    ) -> Result<Self> {
+        ensure!(
+            !raw_effect_subsystem.is_null(),
+            "Null pointer given as subsystem to RingModulator::new"
+        );
+        ensure!(
+            !raw_ring_modulator.is_null(),
+            "Null pointer given as ring modulator to RingModulator::new"
+        );
+        let raw_subsystem = unsafe { (*raw_effect_subsystem).ringmodulator };
+        Ok(Self {
+            inner: Rc::new(RingModulatorInner {
+                raw_effect_subsystem,
+                raw_subsystem,
+                raw_ring_modulator,
+                mix_modulator: RefCell::new(None),
+                frequency_modulator: RefCell::new(None),
+            }),
+        })
+    }
+
+    /// Sets the frequency of the modulating sine wave, in Hz.
+    pub fn set_frequency(&self, frequency: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setFrequency,
+            self.inner.raw_ring_modulator,
+            frequency
+        )
+    }
+
+    /// Sets a signal to modulate the frequency; None removes it.
+    pub fn set_frequency_modulator(&self, signal: Option<&dyn Signal>) -> Result<()> {
+        let modulator = signal.map(|signal| signal.modulator());
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setFrequencyModulator,
+            self.inner.raw_ring_modulator,
+            raw_modulator(modulator.as_ref())
+        )?;
+        *self.inner.frequency_modulator.borrow_mut() = modulator;
+        Ok(())
+    }
+}
+
+/// A delay line, for echoes.  The effect itself plays the delayed signal at its full length;
+/// use `add_tap` to get additional outputs at shorter delays.
+// Really a wrapper around an Rc clone of the internal structure; derive Clone so it's easy to get
+// another Rc reference.  We use Rc so we don't free the effect while a channel or tap still uses
+// it.
+#[derive(Clone, Debug)]
+pub struct DelayLine {
+    inner: Rc<DelayLineInner>,
+}
+
+#[derive(Debug)]
+struct DelayLineInner {
+    raw_effect_subsystem: *const crankstart_sys::playdate_sound_effect,
+    raw_subsystem: *const crankstart_sys::playdate_sound_effect_delayline,
+    raw_delay_line: *mut crankstart_sys::DelayLine,
+
+    // Modulators currently attached to the effect, held so they aren't freed while in use.
+    mix_modulator: RefCell<Option<Modulator>>,
+}
+
+impl Drop for DelayLineInner {
+    fn drop(&mut self) {
+        // Use _log to leak rather than fail
+        pd_func_caller_log!((*self.raw_subsystem).freeDelayLine, self.raw_delay_line);
+    }
+}
+
+impl EffectState for DelayLineInner {
+    fn mix_modulator(&self) -> &RefCell<Option<Modulator>> {
+        &self.mix_modulator
+    }
+}
+
+impl Effect for DelayLine {
+    fn handle(&self) -> EffectHandle {
+        EffectHandle {
+            raw_subsystem: self.inner.raw_effect_subsystem,
+            raw_effect: self.inner.raw_delay_line as *mut crankstart_sys::SoundEffect,
+            state: self.inner.clone(),
+        }
+    }
+}
+
+// Not implemented: newDelayLine (use Sound::get_delay_line).
+impl DelayLine {
+    pub(crate) fn new(
+        raw_effect_subsystem: *const crankstart_sys::playdate_sound_effect,
+        raw_delay_line: *mut crankstart_sys::DelayLine,
+    ) -> Result<Self> {
+        ensure!(
+            !raw_effect_subsystem.is_null(),
+            "Null pointer given as subsystem to DelayLine::new"
+        );
+        ensure!(
+            !raw_delay_line.is_null(),
+            "Null pointer given as delay line to DelayLine::new"
+        );
+        let raw_subsystem = unsafe { (*raw_effect_subsystem).delayline };
+        Ok(Self {
+            inner: Rc::new(DelayLineInner {
+                raw_effect_subsystem,
+                raw_subsystem,
+                raw_delay_line,
+                mix_modulator: RefCell::new(None),
+            }),
+        })
+    }
+
+    /// Changes the length of the delay line, in frames (44.1k per second).  This clears the
+    /// buffer.
+    pub fn set_length(&self, frames: i32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setLength,
+            self.inner.raw_delay_line,
+            frames
+        )
+    }
+
+    /// Sets how much of the delayed signal is fed back into the delay line, from 0 to 1.
+    pub fn set_feedback(&self, feedback: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setFeedback,
+            self.inner.raw_delay_line,
+            feedback
+        )
+    }
+
+    /// Adds a tap that outputs the signal delayed by the given number of frames, which must be
+    /// less than the length of the delay line.  Taps are sound sources, so add them to a channel
+    /// to hear them.
+    pub fn add_tap(&self, delay: i32) -> Result<DelayLineTap> {
+        let raw_tap = pd_func_caller!(
+            (*self.inner.raw_subsystem).addTap,
+            self.inner.raw_delay_line,
+            delay
+        )?;
+        ensure!(!raw_tap.is_null(), "Null returned from delayline.addTap");
+        Ok(DelayLineTap {
+            inner: Rc::new(DelayLineTapInner {
+                delay_line: self.inner.clone(),
+                raw_tap,
+                delay_modulator: RefCell::new(None),
+            }),
+        })
+    }
+}
+
+/// An extra output from a `DelayLine`; see `DelayLine::add_tap`.
+// Really a wrapper around an Rc clone of the internal structure; derive Clone so it's easy to get
+// another Rc reference.  We use Rc so we don't free the tap while a channel still plays it.
+#[derive(Clone, Debug)]
+pub struct DelayLineTap {
+    pub(crate) inner: Rc<DelayLineTapInner>,
+}
+
+#[derive(Debug)]
+pub(crate) struct DelayLineTapInner {
+    // The tap reads from the delay line's buffer, so keep it alive.
+    delay_line: Rc<DelayLineInner>,
+    pub(crate) raw_tap: *mut crankstart_sys::DelayLineTap,
+
+    // The modulator attached to the tap, held so it isn't freed while in use.
+    delay_modulator: RefCell<Option<Modulator>>,
+}
+
+impl Drop for DelayLineTapInner {
+    fn drop(&mut self) {
+        // Use _log to leak rather than fail
+        pd_func_caller_log!((*self.delay_line.raw_subsystem).freeTap, self.raw_tap);
+    }
+}
+
+impl DelayLineTap {
+    /// Sets how far behind the input the tap plays, in frames.
+    pub fn set_delay(&self, frames: i32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.delay_line.raw_subsystem).setTapDelay,
+            self.inner.raw_tap,
+            frames
+        )
+    }
+
+    /// Sets a signal to modulate the delay; None removes it.
+    pub fn set_delay_modulator(&self, signal: Option<&dyn Signal>) -> Result<()> {
+        let modulator = signal.map(|signal| signal.modulator());
+        pd_func_caller!(
+            (*self.inner.delay_line.raw_subsystem).setTapDelayModulator,
+            self.inner.raw_tap,
+            raw_modulator(modulator.as_ref())
+        )?;
+        *self.inner.delay_modulator.borrow_mut() = modulator;
+        Ok(())
+    }
+
+    /// If true, and the delay line is stereo, the tap swaps the left and right channels.
+    pub fn set_channels_flipped(&self, flipped: bool) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.delay_line.raw_subsystem).setTapChannelsFlipped,
+            self.inner.raw_tap,
+            flipped as ctypes::c_int
+        )
+    }
+}
+
+/// Amplifies and clips the signal, for distortion.
+// Really a wrapper around an Rc clone of the internal structure; derive Clone so it's easy to get
+// another Rc reference.  We use Rc so we don't free the effect while a channel still uses it.
+#[derive(Clone, Debug)]
+pub struct Overdrive {
+    inner: Rc<OverdriveInner>,
+}
+
+#[derive(Debug)]
+struct OverdriveInner {
+    raw_effect_subsystem: *const crankstart_sys::playdate_sound_effect,
+    raw_subsystem: *const crankstart_sys::playdate_sound_effect_overdrive,
+    raw_overdrive: *mut crankstart_sys::Overdrive,
+
+    // Modulators currently attached to the effect, held so they aren't freed while in use.
+    mix_modulator: RefCell<Option<Modulator>>,
+    limit_modulator: RefCell<Option<Modulator>>,
+    offset_modulator: RefCell<Option<Modulator>>,
+}
+
+impl Drop for OverdriveInner {
+    fn drop(&mut self) {
+        // Use _log to leak rather than fail
+        pd_func_caller_log!((*self.raw_subsystem).freeOverdrive, self.raw_overdrive);
+    }
+}
+
+impl EffectState for OverdriveInner {
+    fn mix_modulator(&self) -> &RefCell<Option<Modulator>> {
+        &self.mix_modulator
+    }
+}
+
+impl Effect for Overdrive {
+    fn handle(&self) -> EffectHandle {
+        EffectHandle {
+            raw_subsystem: self.inner.raw_effect_subsystem,
+            raw_effect: self.inner.raw_overdrive as *mut crankstart_sys::SoundEffect,
+            state: self.inner.clone(),
+        }
+    }
+}
+
+// Not implemented: newOverdrive (use Sound::get_overdrive).
+impl Overdrive {
+    pub(crate) fn new(
+        raw_effect_subsystem: *const crankstart_sys::playdate_sound_effect,
+        raw_overdrive: *mut crankstart_sys::Overdrive,
+    ) -> Result<Self> {
+        ensure!(
+            !raw_effect_subsystem.is_null(),
+            "Null pointer given as subsystem to Overdrive::new"
+        );
+        ensure!(
+            !raw_overdrive.is_null(),
+            "Null pointer given as overdrive to Overdrive::new"
+        );
+        let raw_subsystem = unsafe { (*raw_effect_subsystem).overdrive };
+        Ok(Self {
+            inner: Rc::new(OverdriveInner {
+                raw_effect_subsystem,
+                raw_subsystem,
+                raw_overdrive,
+                mix_modulator: RefCell::new(None),
+                limit_modulator: RefCell::new(None),
+                offset_modulator: RefCell::new(None),
+            }),
+        })
+    }
+
+    /// Sets how much the signal is amplified before clipping.
+    pub fn set_gain(&self, gain: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setGain,
+            self.inner.raw_overdrive,
+            gain
+        )
+    }
+
+    /// Sets the level at which the amplified signal clips.
+    pub fn set_limit(&self, limit: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setLimit,
+            self.inner.raw_overdrive,
+            limit
+        )
+    }
+
+    /// Sets a signal to modulate the limit; None removes it.
+    pub fn set_limit_modulator(&self, signal: Option<&dyn Signal>) -> Result<()> {
+        let modulator = signal.map(|signal| signal.modulator());
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setLimitModulator,
+            self.inner.raw_overdrive,
+            raw_modulator(modulator.as_ref())
+        )?;
+        *self.inner.limit_modulator.borrow_mut() = modulator;
+        Ok(())
+    }
+
+    /// Adds an offset to the signal before clipping, for asymmetric distortion.
+    pub fn set_offset(&self, offset: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setOffset,
+            self.inner.raw_overdrive,
+            offset
+        )
+    }
+
+    /// Sets a signal to modulate the offset; None removes it.
+    pub fn set_offset_modulator(&self, signal: Option<&dyn Signal>) -> Result<()> {
+        let modulator = signal.map(|signal| signal.modulator());
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setOffsetModulator,
+            self.inner.raw_overdrive,
+            raw_modulator(modulator.as_ref())
+        )?;
+        *self.inner.offset_modulator.borrow_mut() = modulator;
+        Ok(())
+    }
+}
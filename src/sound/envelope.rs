@@ -0,0 +1,157 @@
+use crate::{pd_func_caller, pd_func_caller_log};
+use crankstart_sys::ctypes;
+
+use super::{
+    signal::{Modulator, Signal},
+    synth::MIDINote,
+};
+use alloc::rc::Rc;
+use anyhow::{ensure, Error, Result};
+
+/// An ADSR envelope, for modulating things like a synth's amplitude or a filter's frequency.
+/// The envelope is triggered by the notes of the synth it's attached to.
+// Really a wrapper around an Rc clone of the internal structure; derive Clone so it's easy to get
+// another Rc reference.  We use Rc so we don't free the envelope while it's still a modulator.
+#[derive(Clone, Debug)]
+pub struct Envelope {
+    inner: Rc<EnvelopeInner>,
+}
+
+#[derive(Debug)]
+struct EnvelopeInner {
+    raw_subsystem: *const crankstart_sys::playdate_sound_envelope,
+    raw_envelope: *mut crankstart_sys::PDSynthEnvelope,
+}
+
+impl Drop for EnvelopeInner {
+    fn drop(&mut self) {
+        // Use _log to leak rather than fail
+        pd_func_caller_log!((*self.raw_subsystem).freeEnvelope, self.raw_envelope);
+    }
+}
+
+// Not implemented: newEnvelope (use Sound::get_envelope).
+impl Envelope {
+    pub(crate) fn new(
+        raw_subsystem: *const crankstart_sys::playdate_sound_envelope,
+        raw_envelope: *mut crankstart_sys::PDSynthEnvelope,
+    ) -> Result<Self> {
+        ensure!(
+            !raw_subsystem.is_null(),
+            "Null pointer given as subsystem to Envelope::new"
+        );
+        ensure!(
+            !raw_envelope.is_null(),
+            "Null pointer given as envelope to Envelope::new"
+        );
+        Ok(Self {
+            inner: Rc::new(EnvelopeInner {
+                raw_subsystem,
+                raw_envelope,
+            }),
+        })
+    }
+
+    /// Sets the attack time, in seconds.
+    pub fn set_attack(&self, attack: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setAttack,
+            self.inner.raw_envelope,
+            attack
+        )
+    }
+
+    /// Sets the decay time, in seconds.
+    pub fn set_decay(&self, decay: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setDecay,
+            self.inner.raw_envelope,
+            decay
+        )
+    }
+
+    /// Sets the sustain level, from 0 to 1.
+    pub fn set_sustain(&self, sustain: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setSustain,
+            self.inner.raw_envelope,
+            sustain
+        )
+    }
+
+    /// Sets the release time, in seconds.
+    pub fn set_release(&self, release: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setRelease,
+            self.inner.raw_envelope,
+            release
+        )
+    }
+
+    /// If true, a note that starts while another is held continues from the sustain phase
+    /// instead of restarting the attack.
+    pub fn set_legato(&self, legato: bool) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setLegato,
+            self.inner.raw_envelope,
+            legato as ctypes::c_int
+        )
+    }
+
+    /// If true, the envelope restarts from zero on each note instead of from its current value.
+    pub fn set_retrigger(&self, retrigger: bool) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setRetrigger,
+            self.inner.raw_envelope,
+            retrigger as ctypes::c_int
+        )
+    }
+
+    /// Sets the shape of the envelope's segments, from 0 (linear) to 1 (exponential).
+    pub fn set_curvature(&self, amount: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setCurvature,
+            self.inner.raw_envelope,
+            amount
+        )
+    }
+
+    /// Sets how much note velocity scales the envelope's output, from 0 (not at all) to 1.
+    pub fn set_velocity_sensitivity(&self, sensitivity: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setVelocitySensitivity,
+            self.inner.raw_envelope,
+            sensitivity
+        )
+    }
+
+    /// Scales the envelope's rates by the played note: notes below `start` use the rates as
+    /// set, notes above `end` have them scaled by `scaling`, and notes in between are
+    /// interpolated.
+    pub fn set_rate_scaling(&self, scaling: f32, start: MIDINote, end: MIDINote) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setRateScaling,
+            self.inner.raw_envelope,
+            scaling,
+            start,
+            end
+        )
+    }
+
+    /// Returns the envelope's current output value.
+    pub fn get_value(&self) -> Result<f32> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).getValue,
+            self.inner.raw_envelope
+        )
+    }
+}
+
+impl Signal for Envelope {
+    fn modulator(&self) -> Modulator {
+        Modulator::new(
+            self.inner.raw_envelope as *mut crankstart_sys::PDSynthSignalValue,
+            self.inner.clone(),
+        )
+    }
+}
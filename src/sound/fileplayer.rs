@@ -1,7 +1,10 @@
 use crate::{pd_func_caller, pd_func_caller_log};
 use crankstart_sys::ctypes;
 
+use super::source::{free_callback, sound_source_callback};
+use alloc::boxed::Box;
 use anyhow::{anyhow, ensure, Error, Result};
+use core::ptr;
 use cstr_core::CString;
 
 /// Note: Make sure you hold on to a FilePlayer until the file has played as much as you want,
@@ -9,18 +12,25 @@ use cstr_core::CString;
 #[derive(Debug)]
 pub struct FilePlayer {
     raw_subsystem: *const crankstart_sys::playdate_sound_fileplayer,
-    raw_player: *mut crankstart_sys::FilePlayer,
+    pub(crate) raw_player: *mut crankstart_sys::FilePlayer,
+
+    // Callbacks handed to the sound engine, freed once they're replaced or the player is freed.
+    finish_callback: Option<*mut Box<dyn Fn()>>,
+    loop_callback: Option<*mut Box<dyn Fn()>>,
+    fade_callback: Option<*mut Box<dyn Fn()>>,
 }
 
 impl Drop for FilePlayer {
     fn drop(&mut self) {
         // Use _log to leak rather than fail
         pd_func_caller_log!((*self.raw_subsystem).freePlayer, self.raw_player);
+        free_callback(self.finish_callback.take());
+        free_callback(self.loop_callback.take());
+        free_callback(self.fade_callback.take());
     }
 }
 
-// Not implemented: newPlayer (use Sound::get_file_player), setFinishCallback and fadeVolume
-// (waiting on crankstart callback strategy), and setLoopRange (does not seem to do anything).
+// Not implemented: newPlayer (use Sound::get_file_player).
 impl FilePlayer {
     pub(crate) fn new(
         raw_subsystem: *const crankstart_sys::playdate_sound_fileplayer,
@@ -37,6 +47,9 @@ impl FilePlayer {
         Ok(Self {
             raw_subsystem,
             raw_player,
+            finish_callback: None,
+            loop_callback: None,
+            fade_callback: None,
         })
     }
 
@@ -177,4 +190,84 @@ impl FilePlayer {
     pub fn get_length(&self) -> Result<f32> {
         pd_func_caller!((*self.raw_subsystem).getLength, self.raw_player)
     }
+
+    /// Sets the part of the file that repeats when `play` is given a repeat count other than 1,
+    /// in seconds; None for `end` repeats up to the end of the file.  Set it after loading a
+    /// file.
+    pub fn set_loop_range(&self, start: f32, end: Option<f32>) -> Result<()> {
+        let end = match end {
+            Some(end) => end,
+            None => self.get_length()?,
+        };
+        pd_func_caller!(
+            (*self.raw_subsystem).setLoopRange,
+            self.raw_player,
+            start,
+            end
+        )
+    }
+
+    /// Sets a callback to be called when playback finishes, whether it reaches the end or is
+    /// stopped; None removes it.
+    pub fn set_finish_callback(&mut self, callback: Option<Box<dyn Fn()>>) -> Result<()> {
+        let raw_callback_ptr = callback.map(|callback| Box::into_raw(Box::new(callback)));
+        pd_func_caller!(
+            (*self.raw_subsystem).setFinishCallback,
+            self.raw_player,
+            raw_callback_ptr.map(|_| sound_source_callback as _),
+            raw_callback_ptr.map_or(ptr::null_mut(), |ptr| ptr as *mut ctypes::c_void)
+        )?;
+        // The old callback can't be called any more, so it's safe to free.
+        free_callback(core::mem::replace(
+            &mut self.finish_callback,
+            raw_callback_ptr,
+        ));
+        Ok(())
+    }
+
+    /// Sets a callback to be called each time playback loops back to the start; None removes
+    /// it.
+    pub fn set_loop_callback(&mut self, callback: Option<Box<dyn Fn()>>) -> Result<()> {
+        let raw_callback_ptr = callback.map(|callback| Box::into_raw(Box::new(callback)));
+        pd_func_caller!(
+            (*self.raw_subsystem).setLoopCallback,
+            self.raw_player,
+            raw_callback_ptr.map(|_| sound_source_callback as _),
+            raw_callback_ptr.map_or(ptr::null_mut(), |ptr| ptr as *mut ctypes::c_void)
+        )?;
+        // The old callback can't be called any more, so it's safe to free.
+        free_callback(core::mem::replace(
+            &mut self.loop_callback,
+            raw_callback_ptr,
+        ));
+        Ok(())
+    }
+
+    /// Fades the volume of the left and right channels to the given levels, out of 1, over
+    /// `length` frames (44.1k per second).  If given, `finish_callback` is called when the fade
+    /// completes.
+    pub fn fade_volume(
+        &mut self,
+        left: f32,
+        right: f32,
+        length: i32,
+        finish_callback: Option<Box<dyn Fn()>>,
+    ) -> Result<()> {
+        let raw_callback_ptr = finish_callback.map(|callback| Box::into_raw(Box::new(callback)));
+        pd_func_caller!(
+            (*self.raw_subsystem).fadeVolume,
+            self.raw_player,
+            left,
+            right,
+            length,
+            raw_callback_ptr.map(|_| sound_source_callback as _),
+            raw_callback_ptr.map_or(ptr::null_mut(), |ptr| ptr as *mut ctypes::c_void)
+        )?;
+        // Starting a new fade replaces the old one, so its callback can't be called any more.
+        free_callback(core::mem::replace(
+            &mut self.fade_callback,
+            raw_callback_ptr,
+        ));
+        Ok(())
+    }
 }
@@ -0,0 +1,200 @@
+use crate::{pd_func_caller, pd_func_caller_log};
+use crankstart_sys::ctypes;
+
+use super::synth::{MIDINote, Synth};
+use alloc::{rc::Rc, vec::Vec};
+use anyhow::{ensure, Error, Result};
+use core::cell::RefCell;
+
+/// A collection of `Synth` voices, each covering a range of notes, that can be played like a
+/// single polyphonic synth or assigned to a `Track`.
+// Really a wrapper around an Rc clone of the internal structure; derive Clone so it's easy to get
+// another Rc reference.  We use Rc so we don't free the instrument while a track still plays it.
+#[derive(Clone, Debug)]
+pub struct Instrument {
+    pub(crate) inner: Rc<InstrumentInner>,
+}
+
+#[derive(Debug)]
+pub(crate) struct InstrumentInner {
+    raw_subsystem: *const crankstart_sys::playdate_sound_instrument,
+    pub(crate) raw_instrument: *mut crankstart_sys::PDSynthInstrument,
+
+    // The voices given to add_voice.  Freeing the instrument frees them too, so they only hold
+    // on to what the synths use, like samples and modulators, until it's gone.
+    voices: RefCell<Vec<Synth>>,
+}
+
+impl Drop for InstrumentInner {
+    fn drop(&mut self) {
+        // Use _log to leak rather than fail
+        pd_func_caller_log!((*self.raw_subsystem).freeInstrument, self.raw_instrument);
+    }
+}
+
+// Not implemented: newInstrument (use Sound::get_instrument).
+impl Instrument {
+    pub(crate) fn new(
+        raw_subsystem: *const crankstart_sys::playdate_sound_instrument,
+        raw_instrument: *mut crankstart_sys::PDSynthInstrument,
+    ) -> Result<Self> {
+        ensure!(
+            !raw_subsystem.is_null(),
+            "Null pointer given as subsystem to Instrument::new"
+        );
+        ensure!(
+            !raw_instrument.is_null(),
+            "Null pointer given as instrument to Instrument::new"
+        );
+        Ok(Self {
+            inner: Rc::new(InstrumentInner {
+                raw_subsystem,
+                raw_instrument,
+                voices: RefCell::new(Vec::new()),
+            }),
+        })
+    }
+
+    /// Adds a voice that plays notes from `range_start` to `range_end`, transposed by
+    /// `transpose` half steps.  The instrument takes ownership of the synth and frees it along
+    /// with itself.
+    pub fn add_voice(
+        &self,
+        mut synth: Synth,
+        range_start: MIDINote,
+        range_end: MIDINote,
+        transpose: f32,
+    ) -> Result<()> {
+        let result = pd_func_caller!(
+            (*self.inner.raw_subsystem).addVoice,
+            self.inner.raw_instrument,
+            synth.raw_synth,
+            range_start,
+            range_end,
+            transpose
+        )?;
+        ensure!(
+            result == 1,
+            "instrument.addVoice should return 1; returned {}",
+            result
+        );
+        synth.owned = false;
+        self.inner.voices.borrow_mut().push(synth);
+        Ok(())
+    }
+
+    /// Plays a note at the given frequency on the first free voice; see `Synth::play_note`.
+    pub fn play_note(
+        &self,
+        frequency: f32,
+        velocity: f32,
+        length: Option<f32>,
+        when: ctypes::c_uint,
+    ) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).playNote,
+            self.inner.raw_instrument,
+            frequency,
+            velocity,
+            length.unwrap_or(-1.0),
+            when
+        )?;
+        Ok(())
+    }
+
+    /// Plays a MIDI note on the first free voice; see `Synth::play_midi_note`.
+    pub fn play_midi_note(
+        &self,
+        note: MIDINote,
+        velocity: f32,
+        length: Option<f32>,
+        when: ctypes::c_uint,
+    ) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).playMIDINote,
+            self.inner.raw_instrument,
+            note,
+            velocity,
+            length.unwrap_or(-1.0),
+            when
+        )?;
+        Ok(())
+    }
+
+    /// Releases the voice playing the given note at the given sound engine time.
+    pub fn note_off(&self, note: MIDINote, when: ctypes::c_uint) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).noteOff,
+            self.inner.raw_instrument,
+            note,
+            when
+        )
+    }
+
+    /// Releases all playing voices at the given sound engine time.
+    pub fn all_notes_off(&self, when: ctypes::c_uint) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).allNotesOff,
+            self.inner.raw_instrument,
+            when
+        )
+    }
+
+    /// Sets the pitch bend, from -1 to 1, scaled by the pitch bend range.
+    pub fn set_pitch_bend(&self, bend: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setPitchBend,
+            self.inner.raw_instrument,
+            bend
+        )
+    }
+
+    /// Sets the pitch bend range, in half steps.
+    pub fn set_pitch_bend_range(&self, half_steps: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setPitchBendRange,
+            self.inner.raw_instrument,
+            half_steps
+        )
+    }
+
+    /// Transposes all of the instrument's voices by the given number of half steps.
+    pub fn set_transpose(&self, half_steps: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setTranspose,
+            self.inner.raw_instrument,
+            half_steps
+        )
+    }
+
+    /// Gets the current volume of the left and right audio channels, out of 1.
+    pub fn get_volume(&self) -> Result<(f32, f32)> {
+        let mut left = 0.0;
+        let mut right = 0.0;
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).getVolume,
+            self.inner.raw_instrument,
+            &mut left,
+            &mut right,
+        )?;
+        Ok((left, right))
+    }
+
+    /// Sets the volume of the left and right audio channels, out of 1.
+    pub fn set_volume(&self, left: f32, right: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setVolume,
+            self.inner.raw_instrument,
+            left,
+            right
+        )
+    }
+
+    /// Returns the number of voices currently playing a note.
+    pub fn active_voice_count(&self) -> Result<i32> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).activeVoiceCount,
+            self.inner.raw_instrument
+        )
+    }
+}
@@ -0,0 +1,166 @@
+use crate::{pd_func_caller, pd_func_caller_log};
+use crankstart_sys::ctypes;
+
+use super::signal::{Modulator, Signal};
+use alloc::{rc::Rc, vec::Vec};
+use anyhow::{ensure, Error, Result};
+use core::cell::RefCell;
+
+pub use crankstart_sys::LFOType;
+
+/// A low frequency oscillator, for modulating things like a synth's frequency or a channel's
+/// pan.
+// Really a wrapper around an Rc clone of the internal structure; derive Clone so it's easy to get
+// another Rc reference.  We use Rc so we don't free the LFO while it's still a modulator.
+#[derive(Clone, Debug)]
+pub struct Lfo {
+    inner: Rc<LfoInner>,
+}
+
+#[derive(Debug)]
+struct LfoInner {
+    raw_subsystem: *const crankstart_sys::playdate_sound_lfo,
+    raw_lfo: *mut crankstart_sys::PDSynthLFO,
+
+    // The steps given to set_arpeggiation, kept in case the engine reads them as it plays.
+    steps: RefCell<Vec<f32>>,
+}
+
+impl Drop for LfoInner {
+    fn drop(&mut self) {
+        // Use _log to leak rather than fail
+        pd_func_caller_log!((*self.raw_subsystem).freeLFO, self.raw_lfo);
+    }
+}
+
+// Not implemented: newLFO (use Sound::get_lfo) and setFunction.
+impl Lfo {
+    pub(crate) fn new(
+        raw_subsystem: *const crankstart_sys::playdate_sound_lfo,
+        raw_lfo: *mut crankstart_sys::PDSynthLFO,
+    ) -> Result<Self> {
+        ensure!(
+            !raw_subsystem.is_null(),
+            "Null pointer given as subsystem to Lfo::new"
+        );
+        ensure!(!raw_lfo.is_null(), "Null pointer given as lfo to Lfo::new");
+        Ok(Self {
+            inner: Rc::new(LfoInner {
+                raw_subsystem,
+                raw_lfo,
+                steps: RefCell::new(Vec::new()),
+            }),
+        })
+    }
+
+    /// Sets the shape of the LFO's waveform.
+    pub fn set_type(&self, lfo_type: LFOType) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setType,
+            self.inner.raw_lfo,
+            lfo_type
+        )
+    }
+
+    /// Sets the LFO's rate, in cycles per second.
+    pub fn set_rate(&self, rate: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setRate,
+            self.inner.raw_lfo,
+            rate
+        )
+    }
+
+    /// Sets the LFO's current phase, from 0 to 1.
+    pub fn set_phase(&self, phase: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setPhase,
+            self.inner.raw_lfo,
+            phase
+        )
+    }
+
+    /// Sets the phase the LFO restarts at when retriggered, from 0 to 1.
+    pub fn set_start_phase(&self, phase: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setStartPhase,
+            self.inner.raw_lfo,
+            phase
+        )
+    }
+
+    /// Sets the value the LFO oscillates around.
+    pub fn set_center(&self, center: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setCenter,
+            self.inner.raw_lfo,
+            center
+        )
+    }
+
+    /// Sets how far the LFO swings above and below its center.
+    pub fn set_depth(&self, depth: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setDepth,
+            self.inner.raw_lfo,
+            depth
+        )
+    }
+
+    /// Makes the LFO step through the given offsets, in half steps, as an arpeggiator.  The
+    /// LFO type should be `LFOType::kLFOTypeArpeggiator`.
+    pub fn set_arpeggiation(&self, steps: &[f32]) -> Result<()> {
+        let mut stored = self.inner.steps.borrow_mut();
+        *stored = steps.to_vec();
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setArpeggiation,
+            self.inner.raw_lfo,
+            stored.len() as ctypes::c_int,
+            stored.as_mut_ptr()
+        )
+    }
+
+    /// Sets an initial delay, in seconds, before the LFO starts, and a time over which it then
+    /// ramps up to full depth.
+    pub fn set_delay(&self, holdoff: f32, ramp_time: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setDelay,
+            self.inner.raw_lfo,
+            holdoff,
+            ramp_time
+        )
+    }
+
+    /// If true, the LFO restarts at its start phase (and delay) whenever a note starts.
+    pub fn set_retrigger(&self, retrigger: bool) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setRetrigger,
+            self.inner.raw_lfo,
+            retrigger as ctypes::c_int
+        )
+    }
+
+    /// If true, the LFO keeps running even when no note is playing on the synth it modulates,
+    /// so it can be shared between several synths in sync.
+    pub fn set_global(&self, global: bool) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setGlobal,
+            self.inner.raw_lfo,
+            global as ctypes::c_int
+        )
+    }
+
+    /// Returns the LFO's current output value.
+    pub fn get_value(&self) -> Result<f32> {
+        pd_func_caller!((*self.inner.raw_subsystem).getValue, self.inner.raw_lfo)
+    }
+}
+
+impl Signal for Lfo {
+    fn modulator(&self) -> Modulator {
+        Modulator::new(
+            self.inner.raw_lfo as *mut crankstart_sys::PDSynthSignalValue,
+            self.inner.clone(),
+        )
+    }
+}
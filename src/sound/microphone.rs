@@ -0,0 +1,383 @@
+use crate::{pd_func_caller, pd_func_caller_log};
+use crankstart_sys::ctypes;
+
+use super::{AudioSample, Sound};
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use anyhow::{ensure, Error, Result};
+use core::{
+    cell::UnsafeCell,
+    ptr, slice,
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicUsize, Ordering},
+};
+
+pub use crankstart_sys::MicSource;
+
+/// The rate the microphone records at, in frames per second.
+pub const MIC_SAMPLE_RATE: u32 = 44100;
+
+type MicCallback = Box<dyn FnMut(&[i16]) -> bool>;
+
+// The system only has one microphone callback, so remember whose it is; a Microphone that's been
+// replaced by a newer one must not clear the newer one's callback when it's dropped.
+static ACTIVE_CALLBACK: AtomicPtr<MicCallback> = AtomicPtr::new(ptr::null_mut());
+
+extern "C" fn mic_callback(
+    context: *mut ctypes::c_void,
+    buffer: *mut i16,
+    length: ctypes::c_int,
+) -> ctypes::c_int {
+    let callback = context as *mut MicCallback;
+    let block = unsafe { slice::from_raw_parts(buffer, length.max(0) as usize) };
+    unsafe { (*callback)(block) as ctypes::c_int }
+}
+
+/// Captured microphone input.  While this is held, the closure given to
+/// `Sound::start_microphone` is called with each block of 16-bit mono samples; dropping it stops
+/// capture.  Only one microphone can be active at a time, and starting another replaces this one.
+#[derive(Debug)]
+pub struct Microphone {
+    raw_sound: *const crankstart_sys::playdate_sound,
+    raw_callback_ptr: *mut MicCallback,
+    source: MicSource,
+}
+
+impl Drop for Microphone {
+    fn drop(&mut self) {
+        let ours = ACTIVE_CALLBACK
+            .compare_exchange(
+                self.raw_callback_ptr,
+                ptr::null_mut(),
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            )
+            .is_ok();
+        if ours {
+            // Use _log to leak rather than fail
+            pd_func_caller_log!(
+                (*self.raw_sound).setMicCallback,
+                None,
+                ptr::null_mut(),
+                MicSource::kMicInputAutodetect
+            );
+        }
+        unsafe {
+            // Recast into box to let Box deal with freeing the right memory
+            let _ = Box::from_raw(self.raw_callback_ptr);
+        }
+    }
+}
+
+impl Microphone {
+    pub(crate) fn new(
+        raw_sound: *const crankstart_sys::playdate_sound,
+        source: MicSource,
+        callback: MicCallback,
+    ) -> Result<Self> {
+        ensure!(
+            !raw_sound.is_null(),
+            "Null pointer given as sound to Microphone::new"
+        );
+        let raw_callback_ptr = Box::into_raw(Box::new(callback));
+        // Build it first so the callback is freed if starting fails.
+        let mut microphone = Self {
+            raw_sound,
+            raw_callback_ptr,
+            source,
+        };
+        ACTIVE_CALLBACK.store(raw_callback_ptr, Ordering::Release);
+        let result = pd_func_caller!(
+            (*raw_sound).setMicCallback,
+            Some(mic_callback),
+            raw_callback_ptr as *mut ctypes::c_void,
+            source
+        )?;
+        // The system reports which input it actually picked.
+        if result == MicSource::kMicInputHeadset as ctypes::c_int {
+            microphone.source = MicSource::kMicInputHeadset;
+        } else if result == MicSource::kMicInputInternal as ctypes::c_int {
+            microphone.source = MicSource::kMicInputInternal;
+        }
+        Ok(microphone)
+    }
+
+    /// Returns the input being recorded; if autodetect was requested, this is the input the
+    /// system chose.
+    pub fn source(&self) -> MicSource {
+        self.source
+    }
+
+    /// Returns true if this is still the active microphone, i.e. no other microphone has been
+    /// started since.
+    pub fn is_active(&self) -> bool {
+        ACTIVE_CALLBACK.load(Ordering::Acquire) == self.raw_callback_ptr
+    }
+}
+
+/// Returns the average absolute level of a block of samples, from 0 (silence) to 1 (full
+/// scale).  Useful for blow-into-the-mic mechanics.
+pub fn block_level(block: &[i16]) -> f32 {
+    if block.is_empty() {
+        return 0.0;
+    }
+    let sum: u64 = block
+        .iter()
+        .map(|sample| sample.unsigned_abs() as u64)
+        .sum();
+    (sum as f32 / block.len() as f32 / i16::MAX as f32).min(1.0)
+}
+
+/// Collects blocks of microphone samples into a growable buffer, optionally stopping at a maximum
+/// length.  This holds no system resources, so it can be fed synthetic input on the host.
+#[derive(Clone, Debug, Default)]
+pub struct RecordingBuffer {
+    samples: Vec<i16>,
+    max_frames: Option<usize>,
+    level: f32,
+    peak: i16,
+}
+
+impl RecordingBuffer {
+    /// Creates an empty buffer that stops accepting samples after `max_frames`, if given.  The
+    /// buffer is allocated up front when there's a maximum, so `push` never allocates; without
+    /// one it grows as samples arrive.
+    pub fn new(max_frames: Option<usize>) -> Self {
+        Self {
+            samples: Vec::with_capacity(max_frames.unwrap_or(0)),
+            max_frames,
+            ..Self::default()
+        }
+    }
+
+    /// Appends a block of samples, truncating it if the buffer would go past its maximum
+    /// length.  Returns false once the buffer is full, which as the return value of a
+    /// microphone callback stops recording.
+    pub fn push(&mut self, block: &[i16]) -> bool {
+        let room = self
+            .max_frames
+            .map_or(block.len(), |max| max.saturating_sub(self.samples.len()));
+        let block = &block[..block.len().min(room)];
+        self.samples.extend_from_slice(block);
+        self.level = block_level(block);
+        if let Some(peak) = block.iter().map(|sample| sample.saturating_abs()).max() {
+            self.peak = self.peak.max(peak);
+        }
+        !self.is_full()
+    }
+
+    /// Returns the samples recorded so far.
+    pub fn samples(&self) -> &[i16] {
+        &self.samples
+    }
+
+    /// Returns how many frames have been recorded.
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    /// Returns true if nothing has been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    /// Returns true if the buffer has reached its maximum length.
+    pub fn is_full(&self) -> bool {
+        self.max_frames.is_some_and(|max| self.samples.len() >= max)
+    }
+
+    /// Returns the length of the recording, in seconds.
+    pub fn duration(&self) -> f32 {
+        self.samples.len() as f32 / MIC_SAMPLE_RATE as f32
+    }
+
+    /// Returns the average level of the most recent block; see `block_level`.
+    pub fn level(&self) -> f32 {
+        self.level
+    }
+
+    /// Returns the largest absolute sample value recorded so far.
+    pub fn peak(&self) -> i16 {
+        self.peak
+    }
+
+    /// Discards everything recorded, keeping the maximum length.
+    pub fn clear(&mut self) {
+        self.samples.clear();
+        self.level = 0.0;
+        self.peak = 0;
+    }
+
+    /// Takes the recorded samples, leaving the buffer empty.
+    pub fn take_samples(&mut self) -> Vec<i16> {
+        self.level = 0.0;
+        self.peak = 0;
+        core::mem::take(&mut self.samples)
+    }
+}
+
+// The buffer a `Recorder` fills from the audio thread.  Only the microphone callback touches
+// the buffer while it's recording; the main thread reads the atomics it publishes after each
+// block, and takes the buffer only once the callback has been removed.
+#[derive(Debug)]
+struct SharedRecording {
+    buffer: UnsafeCell<RecordingBuffer>,
+    frames: AtomicUsize,
+    // The bits of the f32 level.
+    level: AtomicU32,
+    full: AtomicBool,
+}
+
+// The buffer is only accessed from one thread at a time, as described above.
+unsafe impl Sync for SharedRecording {}
+unsafe impl Send for SharedRecording {}
+
+impl SharedRecording {
+    // Called only from the microphone callback.
+    fn push(&self, block: &[i16]) -> bool {
+        let buffer = unsafe { &mut *self.buffer.get() };
+        let more = buffer.push(block);
+        self.frames.store(buffer.len(), Ordering::Release);
+        self.level
+            .store(buffer.level().to_bits(), Ordering::Release);
+        self.full.store(buffer.is_full(), Ordering::Release);
+        more
+    }
+}
+
+/// Records the microphone into a `RecordingBuffer` until it's full or `finish` is called, then
+/// turns the recording into an `AudioSample`.
+#[derive(Debug)]
+pub struct Recorder {
+    microphone: Microphone,
+    recording: Arc<SharedRecording>,
+}
+
+impl Recorder {
+    /// Starts recording from the given input, stopping automatically after `max_seconds` if
+    /// given.  Without a maximum, the buffer grows from the audio callback, which allocates on
+    /// the audio thread; give one to keep the callback allocation-free.
+    pub fn start(source: MicSource, max_seconds: Option<f32>) -> Result<Self> {
+        let max_frames = max_seconds.map(|seconds| (seconds * MIC_SAMPLE_RATE as f32) as usize);
+        let recording = Arc::new(SharedRecording {
+            buffer: UnsafeCell::new(RecordingBuffer::new(max_frames)),
+            frames: AtomicUsize::new(0),
+            level: AtomicU32::new(0),
+            full: AtomicBool::new(false),
+        });
+        let callback_recording = recording.clone();
+        let microphone =
+            Sound::get().start_microphone(source, move |block| callback_recording.push(block))?;
+        Ok(Self {
+            microphone,
+            recording,
+        })
+    }
+
+    /// Returns true while samples are still being recorded.
+    pub fn is_recording(&self) -> bool {
+        self.microphone.is_active() && !self.recording.full.load(Ordering::Acquire)
+    }
+
+    /// Returns the length recorded so far, in seconds.
+    pub fn duration(&self) -> f32 {
+        self.recording.frames.load(Ordering::Acquire) as f32 / MIC_SAMPLE_RATE as f32
+    }
+
+    /// Returns the average level of the most recent block; see `block_level`.
+    pub fn level(&self) -> f32 {
+        f32::from_bits(self.recording.level.load(Ordering::Acquire))
+    }
+
+    /// Returns the input being recorded.
+    pub fn source(&self) -> MicSource {
+        self.microphone.source()
+    }
+
+    /// Stops recording and returns what was recorded as a 16-bit mono `AudioSample`.
+    pub fn finish(self) -> Result<AudioSample> {
+        let Self {
+            microphone,
+            recording,
+        } = self;
+        // Dropping the microphone removes the callback, so the buffer is ours from here on.
+        drop(microphone);
+        let samples = unsafe { (*recording.buffer.get()).take_samples() };
+        ensure!(!samples.is_empty(), "Nothing was recorded");
+        Sound::get().new_audio_sample_from_pcm(&samples, false, MIC_SAMPLE_RATE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncates_at_max_frames() {
+        let mut buffer = RecordingBuffer::new(Some(5));
+        assert!(buffer.push(&[1, 2, 3]));
+        assert!(!buffer.is_full());
+        // Only two of these fit, and the buffer reports it's full.
+        assert!(!buffer.push(&[4, 5, 6, 7]));
+        assert!(buffer.is_full());
+        assert_eq!(buffer.samples(), [1, 2, 3, 4, 5]);
+        assert!(!buffer.push(&[8]));
+        assert_eq!(buffer.len(), 5);
+    }
+
+    #[test]
+    fn preallocates_bounded_buffers() {
+        let mut buffer = RecordingBuffer::new(Some(100));
+        let capacity = buffer.samples.capacity();
+        assert!(capacity >= 100);
+        for _ in 0..10 {
+            buffer.push(&[0; 10]);
+        }
+        assert_eq!(buffer.samples.capacity(), capacity);
+    }
+
+    #[test]
+    fn grows_without_max_frames() {
+        let mut buffer = RecordingBuffer::new(None);
+        for _ in 0..100 {
+            assert!(buffer.push(&[1; 64]));
+        }
+        assert_eq!(buffer.len(), 6400);
+        assert!(!buffer.is_full());
+        assert_eq!(buffer.duration(), 6400.0 / MIC_SAMPLE_RATE as f32);
+    }
+
+    #[test]
+    fn tracks_peak_and_level() {
+        let mut buffer = RecordingBuffer::new(None);
+        buffer.push(&[100, -3000, 20]);
+        buffer.push(&[i16::MIN, 0]);
+        assert_eq!(buffer.peak(), i16::MAX);
+        buffer.push(&[i16::MAX, -i16::MAX]);
+        assert_eq!(buffer.level(), 1.0);
+        buffer.push(&[0, 0]);
+        // The level is the latest block's; the peak is the whole recording's.
+        assert_eq!(buffer.level(), 0.0);
+        assert_eq!(buffer.peak(), i16::MAX);
+        assert_eq!(block_level(&[]), 0.0);
+        assert_eq!(block_level(&[i16::MAX / 2, -(i16::MAX / 2)]), 0.49998474);
+    }
+
+    #[test]
+    fn clear_keeps_max_frames() {
+        let mut buffer = RecordingBuffer::new(Some(3));
+        buffer.push(&[1, 2, 3]);
+        buffer.clear();
+        assert!(buffer.is_empty());
+        assert_eq!((buffer.peak(), buffer.level()), (0, 0.0));
+        assert!(!buffer.push(&[4, 5, 6, 7]));
+        assert_eq!(buffer.samples(), [4, 5, 6]);
+    }
+
+    #[test]
+    fn take_samples_empties_buffer() {
+        let mut buffer = RecordingBuffer::new(None);
+        buffer.push(&[5, -5]);
+        assert_eq!(buffer.take_samples(), [5, -5]);
+        assert!(buffer.is_empty());
+        assert_eq!((buffer.peak(), buffer.level()), (0, 0.0));
+    }
+}
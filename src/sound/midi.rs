@@ -0,0 +1,878 @@
+//! A small Standard MIDI File (format 0 and 1) reader and writer, for building `Sequence`s from
+//! music data generated or modified in Rust rather than loaded with `Sequence::load_midi_file`.
+//!
+//! Event times are kept in absolute ticks; when converted to `NoteEvent`s and `ControlEvent`s,
+//! one tick becomes one sequence step, so the tempo to play at is `MidiFile::steps_per_second`.
+//!
+//! This module doesn't touch the Playdate API (apart from `apply_to` and `Sequence::load_midi`),
+//! so it can be used on the host.
+
+use super::{sequence::Sequence, synth::MIDINote, track::NoteEvent, track::Track};
+use alloc::{vec, vec::Vec};
+use anyhow::{anyhow, ensure, Result};
+
+/// The tempo MIDI files play at when they don't say otherwise: 120 beats per minute.
+pub const DEFAULT_MICROSECONDS_PER_QUARTER: u32 = 500_000;
+
+/// A parsed or generated Standard MIDI File.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MidiFile {
+    /// 0 for a single track, 1 for several tracks played together.
+    pub format: u16,
+    /// Ticks per quarter note.  SMPTE time divisions aren't supported.
+    pub ticks_per_quarter: u16,
+    pub tracks: Vec<MidiTrack>,
+}
+
+/// One track of a `MidiFile`, with its events in tick order.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MidiTrack {
+    pub events: Vec<MidiEvent>,
+}
+
+/// A MIDI event at an absolute time, in ticks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MidiEvent {
+    pub tick: u32,
+    pub kind: MidiEventKind,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MidiEventKind {
+    NoteOff {
+        channel: u8,
+        key: u8,
+        velocity: u8,
+    },
+    NoteOn {
+        channel: u8,
+        key: u8,
+        velocity: u8,
+    },
+    Aftertouch {
+        channel: u8,
+        key: u8,
+        pressure: u8,
+    },
+    ControlChange {
+        channel: u8,
+        controller: u8,
+        value: u8,
+    },
+    ProgramChange {
+        channel: u8,
+        program: u8,
+    },
+    ChannelPressure {
+        channel: u8,
+        pressure: u8,
+    },
+    /// The bend amount, from 0 to 16383; 8192 is centered.
+    PitchBend {
+        channel: u8,
+        value: u16,
+    },
+    /// A tempo change, in microseconds per quarter note.
+    Tempo(u32),
+    EndOfTrack,
+    /// Any other meta event, with its type byte and data.
+    Meta(u8, Vec<u8>),
+    /// A system exclusive message, with its status byte (0xF0 or 0xF7) and data.
+    SysEx(u8, Vec<u8>),
+}
+
+/// A value for a MIDI controller at a sequence step, as added by `Track::add_control_event`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ControlEvent {
+    pub controller: i32,
+    pub step: u32,
+    /// The controller value scaled from 0..=127 to 0..=1.
+    pub value: f32,
+}
+
+/// A change of tempo at the given tick.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TempoChange {
+    pub tick: u32,
+    pub microseconds_per_quarter: u32,
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        let byte = *self
+            .data
+            .get(self.pos)
+            .ok_or_else(|| anyhow!("Unexpected end of MIDI data at byte {}", self.pos))?;
+        self.pos += 1;
+        Ok(byte)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos.saturating_add(len);
+        ensure!(
+            end <= self.data.len(),
+            "Unexpected end of MIDI data: wanted {} bytes at byte {}",
+            len,
+            self.pos
+        );
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Reads a data byte of a channel message, which must have its high bit clear.
+    fn data_byte(&mut self) -> Result<u8> {
+        let byte = self.u8()?;
+        ensure!(
+            byte < 0x80,
+            "Expected a MIDI data byte at byte {}, found {:#x}",
+            self.pos - 1,
+            byte
+        );
+        Ok(byte)
+    }
+
+    /// Reads a variable-length quantity: seven bits per byte, high bit set on all but the last.
+    fn varlen(&mut self) -> Result<u32> {
+        let mut value = 0u32;
+        for _ in 0..4 {
+            let byte = self.u8()?;
+            value = (value << 7) | (byte & 0x7f) as u32;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(anyhow!(
+            "MIDI variable-length value too long at byte {}",
+            self.pos
+        ))
+    }
+}
+
+fn write_varlen(out: &mut Vec<u8>, value: u32) {
+    let mut buffer = [0u8; 5];
+    let mut index = buffer.len() - 1;
+    let mut value = value;
+    buffer[index] = (value & 0x7f) as u8;
+    value >>= 7;
+    while value > 0 {
+        index -= 1;
+        buffer[index] = (value & 0x7f) as u8 | 0x80;
+        value >>= 7;
+    }
+    out.extend_from_slice(&buffer[index..]);
+}
+
+impl MidiFile {
+    /// Creates an empty file with the given format and time division.
+    pub fn new(format: u16, ticks_per_quarter: u16) -> Self {
+        Self {
+            format,
+            ticks_per_quarter,
+            tracks: Vec::new(),
+        }
+    }
+
+    /// Parses the contents of a `.mid` file.
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(data);
+        ensure!(reader.bytes(4)? == b"MThd", "Missing MIDI header chunk");
+        let header_len = reader.u32()? as usize;
+        ensure!(header_len >= 6, "MIDI header chunk too short");
+        let mut header = Reader::new(reader.bytes(header_len)?);
+        let format = header.u16()?;
+        let track_count = header.u16()?;
+        let division = header.u16()?;
+        ensure!(format <= 1, "Unsupported MIDI format {}", format);
+        ensure!(
+            division & 0x8000 == 0,
+            "SMPTE time division isn't supported"
+        );
+
+        let mut tracks = Vec::with_capacity(track_count as usize);
+        while tracks.len() < track_count as usize && !reader.is_empty() {
+            let chunk_type = reader.bytes(4)?;
+            let chunk_len = reader.u32()? as usize;
+            let chunk = reader.bytes(chunk_len)?;
+            // Unknown chunk types are allowed by the spec and should be skipped.
+            if chunk_type == b"MTrk" {
+                tracks.push(MidiTrack::parse(chunk)?);
+            }
+        }
+        ensure!(
+            tracks.len() == track_count as usize,
+            "MIDI header promised {} tracks but found {}",
+            track_count,
+            tracks.len()
+        );
+
+        Ok(Self {
+            format,
+            ticks_per_quarter: division,
+            tracks,
+        })
+    }
+
+    /// Serializes the file in Standard MIDI File format.  Fails if a channel message has a
+    /// channel over 15 or a data byte over 127.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        out.extend_from_slice(b"MThd");
+        out.extend_from_slice(&6u32.to_be_bytes());
+        out.extend_from_slice(&self.format.to_be_bytes());
+        out.extend_from_slice(&(self.tracks.len() as u16).to_be_bytes());
+        out.extend_from_slice(&self.ticks_per_quarter.to_be_bytes());
+        for track in &self.tracks {
+            let data = track.to_bytes()?;
+            out.extend_from_slice(b"MTrk");
+            out.extend_from_slice(&(data.len() as u32).to_be_bytes());
+            out.extend_from_slice(&data);
+        }
+        Ok(out)
+    }
+
+    /// Returns all tempo changes in the file, in tick order.  Per the spec these normally live in
+    /// the first track, but all tracks are searched.
+    pub fn tempo_map(&self) -> Vec<TempoChange> {
+        let mut changes: Vec<TempoChange> = self
+            .tracks
+            .iter()
+            .flat_map(|track| track.events.iter())
+            .filter_map(|event| match event.kind {
+                MidiEventKind::Tempo(microseconds_per_quarter) => Some(TempoChange {
+                    tick: event.tick,
+                    microseconds_per_quarter,
+                }),
+                _ => None,
+            })
+            .collect();
+        changes.sort_by_key(|change| change.tick);
+        changes
+    }
+
+    /// Returns the sequence tempo, in steps (ticks) per second, for the file's first tempo.
+    /// `Sequence` has a single tempo, so later changes aren't reflected.
+    pub fn steps_per_second(&self) -> f32 {
+        let microseconds_per_quarter = self
+            .tempo_map()
+            .first()
+            .map_or(DEFAULT_MICROSECONDS_PER_QUARTER, |change| {
+                change.microseconds_per_quarter
+            });
+        self.ticks_per_quarter as f32 * 1_000_000.0 / microseconds_per_quarter as f32
+    }
+
+    /// Transposes every note in the file by the given number of half steps, except on channel
+    /// 10 (index 9), which is reserved for percussion.  Fails without changing anything if a
+    /// note would leave the MIDI range.
+    pub fn transpose(&mut self, half_steps: i32) -> Result<()> {
+        for track in &self.tracks {
+            for event in &track.events {
+                if let Some(key) = event.kind.transposable_key() {
+                    let transposed = key as i32 + half_steps;
+                    ensure!(
+                        (0..=127).contains(&transposed),
+                        "Transposing note {} at tick {} by {} leaves the MIDI range",
+                        key,
+                        event.tick,
+                        half_steps
+                    );
+                }
+            }
+        }
+        for track in &mut self.tracks {
+            for event in &mut track.events {
+                match &mut event.kind {
+                    MidiEventKind::NoteOn { channel, key, .. }
+                    | MidiEventKind::NoteOff { channel, key, .. }
+                    | MidiEventKind::Aftertouch { channel, key, .. }
+                        if *channel != 9 =>
+                    {
+                        *key = (*key as i32 + half_steps) as u8;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl MidiEventKind {
+    // Checks that a channel message fits its status and data bytes.
+    fn check(&self, tick: u32) -> Result<()> {
+        let (channel, data): (u8, &[u16]) = match *self {
+            MidiEventKind::NoteOff {
+                channel,
+                key,
+                velocity,
+            }
+            | MidiEventKind::NoteOn {
+                channel,
+                key,
+                velocity,
+            } => (channel, &[key as u16, velocity as u16]),
+            MidiEventKind::Aftertouch {
+                channel,
+                key,
+                pressure,
+            } => (channel, &[key as u16, pressure as u16]),
+            MidiEventKind::ControlChange {
+                channel,
+                controller,
+                value,
+            } => (channel, &[controller as u16, value as u16]),
+            MidiEventKind::ProgramChange { channel, program } => (channel, &[program as u16]),
+            MidiEventKind::ChannelPressure { channel, pressure } => (channel, &[pressure as u16]),
+            MidiEventKind::PitchBend { channel, value } => {
+                ensure!(
+                    value < 0x4000,
+                    "MIDI pitch bend {} at tick {} is over 16383",
+                    value,
+                    tick
+                );
+                (channel, &[])
+            }
+            _ => return Ok(()),
+        };
+        ensure!(
+            channel < 16,
+            "MIDI channel {} at tick {} is over 15",
+            channel,
+            tick
+        );
+        ensure!(
+            data.iter().all(|&byte| byte < 0x80),
+            "MIDI data byte over 127 at tick {}",
+            tick
+        );
+        Ok(())
+    }
+
+    fn transposable_key(&self) -> Option<u8> {
+        match *self {
+            MidiEventKind::NoteOn { channel, key, .. }
+            | MidiEventKind::NoteOff { channel, key, .. }
+            | MidiEventKind::Aftertouch { channel, key, .. }
+                if channel != 9 =>
+            {
+                Some(key)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl MidiTrack {
+    fn parse(data: &[u8]) -> Result<Self> {
+        let mut reader = Reader::new(data);
+        let mut events = Vec::new();
+        let mut tick = 0u32;
+        let mut running_status = None;
+        while !reader.is_empty() {
+            tick = tick.saturating_add(reader.varlen()?);
+            let mut status = reader.u8()?;
+            let mut first_data = None;
+            if status < 0x80 {
+                // Running status: this byte is data for the previous channel message.
+                first_data = Some(status);
+                status = running_status
+                    .ok_or_else(|| anyhow!("MIDI data byte without a status at tick {}", tick))?;
+            }
+            let kind = match status {
+                0xff => {
+                    let meta_type = reader.u8()?;
+                    let len = reader.varlen()? as usize;
+                    let data = reader.bytes(len)?;
+                    match meta_type {
+                        0x2f => MidiEventKind::EndOfTrack,
+                        0x51 => {
+                            ensure!(len == 3, "MIDI tempo event with length {}", len);
+                            MidiEventKind::Tempo(
+                                (data[0] as u32) << 16 | (data[1] as u32) << 8 | data[2] as u32,
+                            )
+                        }
+                        _ => MidiEventKind::Meta(meta_type, data.to_vec()),
+                    }
+                }
+                0xf0 | 0xf7 => {
+                    let len = reader.varlen()? as usize;
+                    MidiEventKind::SysEx(status, reader.bytes(len)?.to_vec())
+                }
+                0x80..=0xef => {
+                    running_status = Some(status);
+                    let channel = status & 0x0f;
+                    let data1 = match first_data {
+                        Some(data) => data,
+                        None => reader.data_byte()?,
+                    };
+                    match status & 0xf0 {
+                        0xc0 => MidiEventKind::ProgramChange {
+                            channel,
+                            program: data1,
+                        },
+                        0xd0 => MidiEventKind::ChannelPressure {
+                            channel,
+                            pressure: data1,
+                        },
+                        high => {
+                            let data2 = reader.data_byte()?;
+                            match high {
+                                0x80 => MidiEventKind::NoteOff {
+                                    channel,
+                                    key: data1,
+                                    velocity: data2,
+                                },
+                                0x90 => MidiEventKind::NoteOn {
+                                    channel,
+                                    key: data1,
+                                    velocity: data2,
+                                },
+                                0xa0 => MidiEventKind::Aftertouch {
+                                    channel,
+                                    key: data1,
+                                    pressure: data2,
+                                },
+                                0xb0 => MidiEventKind::ControlChange {
+                                    channel,
+                                    controller: data1,
+                                    value: data2,
+                                },
+                                _ => MidiEventKind::PitchBend {
+                                    channel,
+                                    value: (data2 as u16) << 7 | data1 as u16,
+                                },
+                            }
+                        }
+                    }
+                }
+                _ => {
+                    return Err(anyhow!(
+                        "Unsupported MIDI status {:#x} at tick {}",
+                        status,
+                        tick
+                    ))
+                }
+            };
+            let end = kind == MidiEventKind::EndOfTrack;
+            events.push(MidiEvent { tick, kind });
+            if end {
+                break;
+            }
+        }
+        Ok(Self { events })
+    }
+
+    /// Serializes the track's events, adding an end of track event if there isn't one.
+    fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut events: Vec<&MidiEvent> = self
+            .events
+            .iter()
+            .filter(|event| event.kind != MidiEventKind::EndOfTrack)
+            .collect();
+        // Stable, so events at the same tick keep their order.
+        events.sort_by_key(|event| event.tick);
+        let end_tick = self
+            .events
+            .iter()
+            .map(|event| event.tick)
+            .max()
+            .unwrap_or(0);
+
+        let mut out = Vec::new();
+        let mut last_tick = 0;
+        for event in events {
+            write_varlen(&mut out, event.tick - last_tick);
+            last_tick = event.tick;
+            event.kind.check(event.tick)?;
+            match &event.kind {
+                MidiEventKind::NoteOff {
+                    channel,
+                    key,
+                    velocity,
+                } => out.extend_from_slice(&[0x80 | channel, *key, *velocity]),
+                MidiEventKind::NoteOn {
+                    channel,
+                    key,
+                    velocity,
+                } => out.extend_from_slice(&[0x90 | channel, *key, *velocity]),
+                MidiEventKind::Aftertouch {
+                    channel,
+                    key,
+                    pressure,
+                } => out.extend_from_slice(&[0xa0 | channel, *key, *pressure]),
+                MidiEventKind::ControlChange {
+                    channel,
+                    controller,
+                    value,
+                } => out.extend_from_slice(&[0xb0 | channel, *controller, *value]),
+                MidiEventKind::ProgramChange { channel, program } => {
+                    out.extend_from_slice(&[0xc0 | channel, *program])
+                }
+                MidiEventKind::ChannelPressure { channel, pressure } => {
+                    out.extend_from_slice(&[0xd0 | channel, *pressure])
+                }
+                MidiEventKind::PitchBend { channel, value } => out.extend_from_slice(&[
+                    0xe0 | channel,
+                    (value & 0x7f) as u8,
+                    (value >> 7 & 0x7f) as u8,
+                ]),
+                MidiEventKind::Tempo(microseconds_per_quarter) => {
+                    let bytes = microseconds_per_quarter.to_be_bytes();
+                    out.extend_from_slice(&[0xff, 0x51, 3, bytes[1], bytes[2], bytes[3]]);
+                }
+                MidiEventKind::EndOfTrack => {}
+                MidiEventKind::Meta(meta_type, data) => {
+                    out.extend_from_slice(&[0xff, *meta_type]);
+                    write_varlen(&mut out, data.len() as u32);
+                    out.extend_from_slice(data);
+                }
+                MidiEventKind::SysEx(status, data) => {
+                    out.push(*status);
+                    write_varlen(&mut out, data.len() as u32);
+                    out.extend_from_slice(data);
+                }
+            }
+        }
+        write_varlen(&mut out, end_tick - last_tick);
+        out.extend_from_slice(&[0xff, 0x2f, 0]);
+        Ok(out)
+    }
+
+    /// Adds a note on/off pair, e.g. for building music procedurally.  Fails if `channel` is over
+    /// 15 or `key` or `velocity` is over 127.
+    pub fn add_note(
+        &mut self,
+        channel: u8,
+        tick: u32,
+        length: u32,
+        key: u8,
+        velocity: u8,
+    ) -> Result<()> {
+        let note_on = MidiEventKind::NoteOn {
+            channel,
+            key,
+            velocity,
+        };
+        note_on.check(tick)?;
+        self.events.push(MidiEvent {
+            tick,
+            kind: note_on,
+        });
+        self.events.push(MidiEvent {
+            tick: tick.saturating_add(length),
+            kind: MidiEventKind::NoteOff {
+                channel,
+                key,
+                velocity: 0,
+            },
+        });
+        Ok(())
+    }
+
+    /// Adds a control change event.  Fails if `channel` is over 15 or `controller` or `value` is
+    /// over 127.
+    pub fn add_control_change(
+        &mut self,
+        channel: u8,
+        tick: u32,
+        controller: u8,
+        value: u8,
+    ) -> Result<()> {
+        let kind = MidiEventKind::ControlChange {
+            channel,
+            controller,
+            value,
+        };
+        kind.check(tick)?;
+        self.events.push(MidiEvent { tick, kind });
+        Ok(())
+    }
+
+    /// Pairs up note on and off events into `NoteEvent`s, in step order.  A note on with
+    /// velocity 0 counts as a note off, and notes still held at the end of the track end at its
+    /// last event.  Notes with a channel over 15 or a key over 127 are skipped.
+    pub fn note_events(&self) -> Vec<NoteEvent> {
+        let mut events: Vec<&MidiEvent> = self.events.iter().collect();
+        events.sort_by_key(|event| event.tick);
+        let end_tick = events.last().map_or(0, |event| event.tick);
+
+        // Start tick and velocity of the held note for each channel and key.
+        let mut held: Vec<Option<(u32, u8)>> = vec![None; 16 * 128];
+        let mut notes = Vec::new();
+        fn slot(channel: u8, key: u8) -> Option<usize> {
+            (channel < 16 && key < 128).then(|| channel as usize * 128 + key as usize)
+        }
+        fn finish(notes: &mut Vec<NoteEvent>, key: u8, start: (u32, u8), tick: u32) {
+            notes.push(NoteEvent {
+                step: start.0,
+                length: tick - start.0,
+                note: key as MIDINote,
+                velocity: start.1 as f32 / 127.0,
+            });
+        }
+        for event in events {
+            match event.kind {
+                MidiEventKind::NoteOn {
+                    channel,
+                    key,
+                    velocity,
+                } if velocity > 0 => {
+                    let Some(index) = slot(channel, key) else {
+                        continue;
+                    };
+                    let slot = &mut held[index];
+                    // Retriggering a held key ends the previous note.
+                    if let Some(start) = slot.take() {
+                        finish(&mut notes, key, start, event.tick);
+                    }
+                    *slot = Some((event.tick, velocity));
+                }
+                MidiEventKind::NoteOn { channel, key, .. }
+                | MidiEventKind::NoteOff { channel, key, .. } => {
+                    let start = slot(channel, key).and_then(|index| held[index].take());
+                    if let Some(start) = start {
+                        finish(&mut notes, key, start, event.tick);
+                    }
+                }
+                _ => {}
+            }
+        }
+        for (index, slot) in held.iter().enumerate() {
+            if let Some(start) = slot {
+                finish(&mut notes, (index % 128) as u8, *start, end_tick);
+            }
+        }
+        notes.sort_by(|a, b| a.step.cmp(&b.step).then(a.note.total_cmp(&b.note)));
+        notes
+    }
+
+    /// Returns the track's control changes as `ControlEvent`s, in step order.
+    pub fn control_events(&self) -> Vec<ControlEvent> {
+        let mut events: Vec<ControlEvent> = self
+            .events
+            .iter()
+            .filter_map(|event| match event.kind {
+                MidiEventKind::ControlChange {
+                    controller, value, ..
+                } => Some(ControlEvent {
+                    controller: controller as i32,
+                    step: event.tick,
+                    value: value as f32 / 127.0,
+                }),
+                _ => None,
+            })
+            .collect();
+        events.sort_by_key(|event| event.step);
+        events
+    }
+
+    /// Adds the track's notes and control changes to the given `Track`.
+    pub fn apply_to(&self, track: &Track) -> Result<()> {
+        for note in self.note_events() {
+            track.add_note_event(note.step, note.length, note.note, note.velocity)?;
+        }
+        for control in self.control_events() {
+            track.add_control_event(
+                control.controller,
+                control.step as i32,
+                control.value,
+                false,
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl Sequence {
+    /// Builds the sequence from a `MidiFile`: adds a track for each of the file's tracks that has
+    /// notes or control changes, and sets the tempo from the file's first tempo event.  As with
+    /// `load_midi_file`, you'll need to set an instrument on each track before playing.
+    pub fn load_midi(&self, midi: &MidiFile) -> Result<()> {
+        for midi_track in &midi.tracks {
+            let has_events = midi_track.events.iter().any(|event| {
+                matches!(
+                    event.kind,
+                    MidiEventKind::NoteOn { .. } | MidiEventKind::ControlChange { .. }
+                )
+            });
+            if has_events {
+                let track = self.add_track()?;
+                midi_track.apply_to(&track)?;
+            }
+        }
+        self.set_tempo(midi.steps_per_second())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(step: u32, length: u32, note: u8, velocity: u8) -> NoteEvent {
+        NoteEvent {
+            step,
+            length,
+            note: note as MIDINote,
+            velocity: velocity as f32 / 127.0,
+        }
+    }
+
+    fn sample_file() -> MidiFile {
+        let mut conductor = MidiTrack::default();
+        conductor.events.push(MidiEvent {
+            tick: 0,
+            kind: MidiEventKind::Tempo(400_000),
+        });
+        let mut melody = MidiTrack::default();
+        melody.add_note(0, 0, 96, 60, 100).unwrap();
+        melody.add_note(0, 96, 48, 64, 80).unwrap();
+        melody.add_note(9, 96, 24, 36, 127).unwrap();
+        melody.add_control_change(0, 48, 7, 127).unwrap();
+        melody.events.push(MidiEvent {
+            tick: 144,
+            kind: MidiEventKind::PitchBend {
+                channel: 0,
+                value: 0x2345,
+            },
+        });
+        let mut file = MidiFile::new(1, 96);
+        file.tracks = vec![conductor, melody];
+        file
+    }
+
+    #[test]
+    fn round_trip() {
+        let file = sample_file();
+        let bytes = file.to_bytes().unwrap();
+        let parsed = MidiFile::parse(&bytes).unwrap();
+        assert_eq!(parsed.format, 1);
+        assert_eq!(parsed.ticks_per_quarter, 96);
+        assert_eq!(parsed.tracks.len(), 2);
+        assert_eq!(parsed.tempo_map()[0].microseconds_per_quarter, 400_000);
+        assert_eq!(parsed.steps_per_second(), 240.0);
+        assert_eq!(parsed.tracks[1].note_events(), file.tracks[1].note_events());
+        assert_eq!(
+            parsed.tracks[1].control_events(),
+            file.tracks[1].control_events()
+        );
+        assert!(parsed.tracks[1].events.contains(&MidiEvent {
+            tick: 144,
+            kind: MidiEventKind::PitchBend {
+                channel: 0,
+                value: 0x2345,
+            },
+        }));
+        assert_eq!(parsed.to_bytes().unwrap(), bytes);
+    }
+
+    #[test]
+    fn parses_running_status() {
+        let track = [
+            0x00, 0x90, 60, 100, // note on
+            0x10, 62, 90, // note on, running status
+            0x10, 60, 0, // note on with velocity 0, running status
+            0x10, 0x80, 62, 0, // note off
+            0x00, 0xff, 0x2f, 0x00, // end of track
+        ];
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"MThd");
+        bytes.extend_from_slice(&[0, 0, 0, 6, 0, 0, 0, 1, 0, 96]);
+        bytes.extend_from_slice(b"MTrk");
+        bytes.extend_from_slice(&(track.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&track);
+        let file = MidiFile::parse(&bytes).unwrap();
+        assert_eq!(
+            file.tracks[0].note_events(),
+            vec![note(0, 32, 60, 100), note(16, 32, 62, 90)]
+        );
+    }
+
+    #[test]
+    fn note_events_pair_notes() {
+        let mut track = MidiTrack::default();
+        track.add_note(0, 0, 10, 60, 127).unwrap();
+        // Retriggered before its note off, so the first note ends at 5.
+        track.events.push(MidiEvent {
+            tick: 5,
+            kind: MidiEventKind::NoteOn {
+                channel: 0,
+                key: 60,
+                velocity: 127,
+            },
+        });
+        // Held until the end of the track.
+        track.events.push(MidiEvent {
+            tick: 2,
+            kind: MidiEventKind::NoteOn {
+                channel: 1,
+                key: 72,
+                velocity: 127,
+            },
+        });
+        assert_eq!(
+            track.note_events(),
+            vec![
+                note(0, 5, 60, 127),
+                note(2, 8, 72, 127),
+                note(5, 5, 60, 127)
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_out_of_range_values() {
+        let mut track = MidiTrack::default();
+        assert!(track.add_note(0, 0, 1, 200, 100).is_err());
+        assert!(track.add_note(16, 0, 1, 60, 100).is_err());
+        assert!(track.add_control_change(0, 0, 7, 128).is_err());
+        assert!(track.events.is_empty());
+
+        // Built directly, so not checked until serialized; note_events skips them.
+        track.events.push(MidiEvent {
+            tick: 0,
+            kind: MidiEventKind::NoteOn {
+                channel: 20,
+                key: 200,
+                velocity: 100,
+            },
+        });
+        assert!(track.note_events().is_empty());
+        let mut file = MidiFile::new(0, 96);
+        file.tracks.push(track);
+        assert!(file.to_bytes().is_err());
+
+        // A status byte where note on's velocity should be.
+        let track = [0x00, 0x90, 60, 0x90, 0x00, 0xff, 0x2f, 0x00];
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"MThd");
+        bytes.extend_from_slice(&[0, 0, 0, 6, 0, 0, 0, 1, 0, 96]);
+        bytes.extend_from_slice(b"MTrk");
+        bytes.extend_from_slice(&(track.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&track);
+        assert!(MidiFile::parse(&bytes).is_err());
+    }
+}
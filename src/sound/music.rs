@@ -0,0 +1,725 @@
+use super::FilePlayer;
+use alloc::{boxed::Box, collections::VecDeque, string::String, sync::Arc};
+use anyhow::Result;
+use core::sync::atomic::{AtomicU32, Ordering};
+use crankstart_sys::ctypes;
+
+/// What `MusicPlayer` needs from a player.  Implemented for `FilePlayer`; implement it for a
+/// stand-in to drive a `MusicPlayer` on the host with a simulated clock.
+pub trait MusicDeck {
+    /// Loads the given file, replacing whatever was loaded.
+    fn load(&mut self, path: &str) -> Result<()>;
+    /// Starts playback, or resumes it after `pause`.  Plays `repeat_count` times, repeating the
+    /// loop range, or forever if it's 0.
+    fn play(&mut self, repeat_count: u32) -> Result<()>;
+    /// Pauses playback, keeping the position.
+    fn pause(&mut self) -> Result<()>;
+    /// Stops playback.
+    fn stop(&mut self) -> Result<()>;
+    /// Returns whether the deck is playing.
+    fn is_playing(&self) -> Result<bool>;
+    /// Returns the playback position, in seconds.
+    fn get_offset(&self) -> Result<f32>;
+    /// Returns the length of the loaded file, in seconds.
+    fn get_length(&self) -> Result<f32>;
+    /// Sets the volume of both channels, out of 1.
+    fn set_volume(&mut self, volume: f32) -> Result<()>;
+    /// Sets the part of the file that repeats, in seconds; None for `end` is the end of the
+    /// file.
+    fn set_loop_range(&mut self, start: f32, end: Option<f32>) -> Result<()>;
+    /// Sets a callback to be called each time playback jumps back to the start of the loop
+    /// range; None removes it.  It may be called from the audio thread.
+    fn set_loop_callback(&mut self, callback: Option<Box<dyn Fn()>>) -> Result<()>;
+}
+
+impl MusicDeck for FilePlayer {
+    fn load(&mut self, path: &str) -> Result<()> {
+        self.load_into_player(path)
+    }
+
+    fn play(&mut self, repeat_count: u32) -> Result<()> {
+        FilePlayer::play(self, repeat_count as ctypes::c_int)
+    }
+
+    fn pause(&mut self) -> Result<()> {
+        FilePlayer::pause(self)
+    }
+
+    fn stop(&mut self) -> Result<()> {
+        FilePlayer::stop(self)
+    }
+
+    fn is_playing(&self) -> Result<bool> {
+        FilePlayer::is_playing(self)
+    }
+
+    fn get_offset(&self) -> Result<f32> {
+        FilePlayer::get_offset(self)
+    }
+
+    fn get_length(&self) -> Result<f32> {
+        FilePlayer::get_length(self)
+    }
+
+    fn set_volume(&mut self, volume: f32) -> Result<()> {
+        FilePlayer::set_volume(self, volume, volume)
+    }
+
+    fn set_loop_range(&mut self, start: f32, end: Option<f32>) -> Result<()> {
+        FilePlayer::set_loop_range(self, start, end)
+    }
+
+    fn set_loop_callback(&mut self, callback: Option<Box<dyn Fn()>>) -> Result<()> {
+        FilePlayer::set_loop_callback(self, callback)
+    }
+}
+
+/// The part of a track that repeats; see `MusicTrack::looped`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LoopPoints {
+    /// Where each repeat starts, in seconds.
+    pub start: f32,
+    /// Where each repeat jumps back to `start`, in seconds; None loops at the end of the file.
+    pub end: Option<f32>,
+    /// How many times to jump back; None repeats until the player moves on.
+    pub count: Option<u32>,
+}
+
+/// A file for `MusicPlayer` to play.  As with `FilePlayer`, give the compiled filename, e.g.
+/// "music.pda".
+#[derive(Clone, Debug, PartialEq)]
+pub struct MusicTrack {
+    /// The file to play.
+    pub path: String,
+    /// The part of the track that repeats, if any.
+    pub loop_points: Option<LoopPoints>,
+}
+
+impl MusicTrack {
+    /// A track that plays through once.
+    pub fn new(path: &str) -> Self {
+        Self {
+            path: path.into(),
+            loop_points: None,
+        }
+    }
+
+    /// Repeats the track from `start` to `end` (None for the end of the file), `count` times or
+    /// forever if None.  The deck loops by itself, so repeats are seamless.  A forever-looping
+    /// track only moves on when `MusicPlayer::skip` or `MusicPlayer::play` is called.
+    pub fn looped(mut self, start: f32, end: Option<f32>, count: Option<u32>) -> Self {
+        self.loop_points = Some(LoopPoints { start, end, count });
+        self
+    }
+}
+
+// A value moving linearly toward a target at a fixed rate per second.
+#[derive(Clone, Copy, Debug)]
+struct Ramp {
+    value: f32,
+    target: f32,
+    rate: f32,
+}
+
+impl Ramp {
+    fn at(value: f32) -> Self {
+        Self {
+            value,
+            target: value,
+            rate: 0.0,
+        }
+    }
+
+    fn to(&mut self, target: f32, seconds: f32) {
+        self.target = target;
+        if seconds <= 0.0 {
+            self.value = target;
+            self.rate = 0.0;
+        } else {
+            self.rate = (target - self.value).abs() / seconds;
+        }
+    }
+
+    fn step(&mut self, dt: f32) {
+        let delta = self.rate * dt;
+        if (self.target - self.value).abs() <= delta {
+            self.value = self.target;
+        } else if self.target > self.value {
+            self.value += delta;
+        } else {
+            self.value -= delta;
+        }
+    }
+
+    fn is_done(&self) -> bool {
+        self.value == self.target
+    }
+}
+
+// What one deck is doing.
+#[derive(Debug)]
+struct DeckState {
+    track: MusicTrack,
+    gain: Ramp,
+    // Set once the track has been faded out, so it's stopped when the fade ends.
+    leaving: bool,
+}
+
+impl DeckState {
+    fn loops_remaining(&self, loops_done: u32) -> bool {
+        self.track
+            .loop_points
+            .as_ref()
+            .is_some_and(|loop_points| loop_points.count.is_none_or(|count| loops_done < count))
+    }
+
+    // The repeat count to play the rest of the track with: once through, plus a pass for each
+    // jump back still to come, or 0 to loop forever.
+    fn repeat_count(&self, loops_done: u32) -> u32 {
+        match &self.track.loop_points {
+            Some(LoopPoints { count: None, .. }) => 0,
+            Some(LoopPoints {
+                count: Some(count), ..
+            }) => count.saturating_sub(loops_done) + 1,
+            None => 1,
+        }
+    }
+}
+
+/// Plays a queue of `MusicTrack`s, crossfading between them using two decks, with loop points,
+/// ducking, and pausing.  Call `update` once per frame with the time since the last call; all
+/// fades and transitions happen there, so with stand-in decks the whole thing can be run against
+/// a simulated clock.
+#[derive(Debug)]
+pub struct MusicPlayer<D: MusicDeck = FilePlayer> {
+    decks: [D; 2],
+    states: [Option<DeckState>; 2],
+    // How many times each deck has jumped back to its loop start, counted by its loop callback.
+    loops: [Arc<AtomicU32>; 2],
+    active: usize,
+    queue: VecDeque<MusicTrack>,
+    crossfade: f32,
+    volume: f32,
+    duck: Ramp,
+    paused: bool,
+}
+
+impl<D: MusicDeck> MusicPlayer<D> {
+    /// Creates a player using the given decks; see `Sound::get_music_player` for the usual
+    /// FilePlayer version.
+    pub fn with_decks(decks: [D; 2]) -> Self {
+        Self {
+            decks,
+            states: [None, None],
+            loops: [Arc::new(AtomicU32::new(0)), Arc::new(AtomicU32::new(0))],
+            active: 0,
+            queue: VecDeque::new(),
+            crossfade: 0.0,
+            volume: 1.0,
+            duck: Ramp::at(1.0),
+            paused: false,
+        }
+    }
+
+    /// Sets how long transitions between tracks take, in seconds.  0 (the default) starts the
+    /// next track as the previous one ends.
+    pub fn set_crossfade(&mut self, seconds: f32) {
+        self.crossfade = seconds.max(0.0);
+    }
+
+    /// Sets the overall volume, out of 1.
+    pub fn set_volume(&mut self, volume: f32) -> Result<()> {
+        self.volume = volume;
+        self.apply_volumes()
+    }
+
+    /// Adds a track to the end of the queue.  If nothing is playing, it starts on the next
+    /// `update`.
+    pub fn enqueue(&mut self, track: MusicTrack) {
+        self.queue.push_back(track);
+    }
+
+    /// Removes all queued tracks, leaving the current one playing.
+    pub fn clear_queue(&mut self) {
+        self.queue.clear();
+    }
+
+    /// Returns how many tracks are waiting to play.
+    pub fn queue_len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Switches to the given track now, crossfading from the current one.
+    pub fn play(&mut self, track: MusicTrack) -> Result<()> {
+        self.start(track, self.crossfade)
+    }
+
+    /// Switches to the next queued track now, crossfading from the current one.  Fades out if
+    /// the queue is empty.
+    pub fn skip(&mut self) -> Result<()> {
+        match self.queue.pop_front() {
+            Some(track) => self.start(track, self.crossfade),
+            None => self.stop(self.crossfade),
+        }
+    }
+
+    /// Fades out whatever is playing over the given number of seconds, and clears the queue.
+    pub fn stop(&mut self, fade_seconds: f32) -> Result<()> {
+        self.queue.clear();
+        for state in self.states.iter_mut().flatten() {
+            state.gain.to(0.0, fade_seconds);
+            state.leaving = true;
+        }
+        self.update(0.0)
+    }
+
+    /// Returns the track currently playing, not counting one being faded out.
+    pub fn current_track(&self) -> Option<&MusicTrack> {
+        self.states[self.active]
+            .as_ref()
+            .filter(|state| !state.leaving)
+            .map(|state| &state.track)
+    }
+
+    /// Returns the playback position of the current track, in seconds.
+    pub fn get_offset(&self) -> Result<Option<f32>> {
+        if self.current_track().is_some() {
+            Ok(Some(self.decks[self.active].get_offset()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Returns whether anything is playing or about to; false once the queue has run out.
+    pub fn is_playing(&self) -> bool {
+        self.states.iter().any(Option::is_some) || !self.queue.is_empty()
+    }
+
+    /// Lowers the music to `level`, out of 1, over the given number of seconds; e.g. while
+    /// dialog plays.  Restore it with `unduck`.
+    pub fn duck(&mut self, level: f32, seconds: f32) -> Result<()> {
+        self.duck.to(level, seconds);
+        self.apply_volumes()
+    }
+
+    /// Returns the music to full volume over the given number of seconds.
+    pub fn unduck(&mut self, seconds: f32) -> Result<()> {
+        self.duck(1.0, seconds)
+    }
+
+    /// Pauses playback and all fades, e.g. while the system menu is open; see `Game::pause`.
+    pub fn pause(&mut self) -> Result<()> {
+        if !self.paused {
+            self.paused = true;
+            for (deck, state) in self.decks.iter_mut().zip(&self.states) {
+                if state.is_some() {
+                    deck.pause()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Resumes playback after `pause`.
+    pub fn resume(&mut self) -> Result<()> {
+        if self.paused {
+            self.paused = false;
+            for index in 0..2 {
+                if let Some(state) = &self.states[index] {
+                    let loops_done = self.loops[index].load(Ordering::Acquire);
+                    self.decks[index].play(state.repeat_count(loops_done))?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns whether playback is paused.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    /// Advances fades and transitions by `dt` seconds.  Call it once per frame.  Loops don't
+    /// depend on it; the decks repeat by themselves.
+    pub fn update(&mut self, dt: f32) -> Result<()> {
+        if self.paused {
+            return Ok(());
+        }
+        self.duck.step(dt);
+        for index in 0..2 {
+            if let Some(state) = self.states[index].as_mut() {
+                state.gain.step(dt);
+                if state.leaving && state.gain.is_done() {
+                    self.decks[index].stop()?;
+                    self.states[index] = None;
+                }
+            }
+        }
+
+        let active = self.active;
+        let crossfade = self.crossfade;
+        let deck = &mut self.decks[active];
+        let loops_done = self.loops[active].load(Ordering::Acquire);
+        // Where the current track is up to, if it's time to move on.
+        let mut transition = None;
+        match self.states[active].as_ref() {
+            // The deck is still looping; it jumps back by itself.
+            Some(state) if state.loops_remaining(loops_done) => {}
+            Some(state) if !state.leaving => {
+                // Move on once the rest of the track fits in the crossfade, or, with no
+                // crossfade, as soon as it ends.
+                // Once the loops are done, the track plays on to the end of the file.
+                let remaining = if deck.is_playing()? {
+                    (deck.get_length()? - deck.get_offset()?).max(0.0)
+                } else {
+                    0.0
+                };
+                if remaining <= crossfade.max(dt) {
+                    transition = Some(remaining);
+                }
+            }
+            Some(_) => {}
+            None => transition = Some(0.0),
+        }
+        if let Some(remaining) = transition {
+            if let Some(track) = self.queue.pop_front() {
+                self.start(track, remaining)?;
+            } else if remaining == 0.0
+                && self.states[active]
+                    .as_ref()
+                    .is_some_and(|state| !state.leaving)
+            {
+                // Finished with nothing after it.
+                self.decks[active].stop()?;
+                self.states[active] = None;
+            }
+        }
+
+        self.apply_volumes()
+    }
+
+    // Starts `track` on the idle deck, fading it in while the current one fades out.
+    fn start(&mut self, track: MusicTrack, fade_seconds: f32) -> Result<()> {
+        let next = 1 - self.active;
+        // If the idle deck is still fading something out, cut it off.
+        if self.states[next].take().is_some() {
+            self.decks[next].stop()?;
+        }
+        if let Some(state) = self.states[self.active].as_mut() {
+            state.gain.to(0.0, fade_seconds);
+            state.leaving = true;
+        }
+
+        let deck = &mut self.decks[next];
+        deck.load(&track.path)?;
+        let loops = self.loops[next].clone();
+        loops.store(0, Ordering::Release);
+        match &track.loop_points {
+            Some(loop_points) => {
+                deck.set_loop_range(loop_points.start, loop_points.end)?;
+                deck.set_loop_callback(Some(Box::new(move || {
+                    loops.fetch_add(1, Ordering::AcqRel);
+                })))?;
+            }
+            None => deck.set_loop_callback(None)?,
+        }
+        let mut gain = Ramp::at(0.0);
+        gain.to(1.0, fade_seconds);
+        deck.set_volume(0.0)?;
+        let state = DeckState {
+            track,
+            gain,
+            leaving: false,
+        };
+        if !self.paused {
+            deck.play(state.repeat_count(0))?;
+        }
+        self.states[next] = Some(state);
+        self.active = next;
+        self.apply_volumes()
+    }
+
+    fn apply_volumes(&mut self) -> Result<()> {
+        for (deck, state) in self.decks.iter_mut().zip(&self.states) {
+            if let Some(state) = state {
+                deck.set_volume(self.volume * self.duck.value * state.gain.value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::{rc::Rc, vec::Vec};
+    use core::cell::RefCell;
+
+    // A deck playing on a simulated clock.  Tracks are named "name:length".
+    #[derive(Default)]
+    struct FakeState {
+        path: String,
+        length: f32,
+        offset: f32,
+        playing: bool,
+        volume: f32,
+        loop_range: (f32, Option<f32>),
+        // As passed to play; 0 repeats forever.
+        repeats_left: u32,
+        loop_callback: Option<Box<dyn Fn()>>,
+    }
+
+    impl FakeState {
+        fn advance(&mut self, dt: f32) {
+            if !self.playing {
+                return;
+            }
+            self.offset += dt;
+            loop {
+                let end = self.loop_range.1.unwrap_or(self.length);
+                if self.repeats_left == 1 {
+                    // The last time through plays on to the end of the file.
+                    if self.offset >= self.length {
+                        self.offset = self.length;
+                        self.playing = false;
+                    }
+                    break;
+                }
+                if self.offset < end {
+                    break;
+                }
+                self.offset = self.loop_range.0 + (self.offset - end);
+                if self.repeats_left > 1 {
+                    self.repeats_left -= 1;
+                }
+                if let Some(callback) = &self.loop_callback {
+                    callback();
+                }
+            }
+        }
+    }
+
+    struct FakeDeck(Rc<RefCell<FakeState>>);
+
+    impl MusicDeck for FakeDeck {
+        fn load(&mut self, path: &str) -> Result<()> {
+            let (_, length) = path.split_once(':').unwrap();
+            let mut state = self.0.borrow_mut();
+            state.path = path.into();
+            state.length = length.parse().unwrap();
+            state.offset = 0.0;
+            state.playing = false;
+            state.loop_range = (0.0, None);
+            Ok(())
+        }
+
+        fn play(&mut self, repeat_count: u32) -> Result<()> {
+            let mut state = self.0.borrow_mut();
+            state.playing = true;
+            state.repeats_left = repeat_count;
+            Ok(())
+        }
+
+        fn pause(&mut self) -> Result<()> {
+            self.0.borrow_mut().playing = false;
+            Ok(())
+        }
+
+        fn stop(&mut self) -> Result<()> {
+            let mut state = self.0.borrow_mut();
+            state.playing = false;
+            state.offset = 0.0;
+            Ok(())
+        }
+
+        fn is_playing(&self) -> Result<bool> {
+            Ok(self.0.borrow().playing)
+        }
+
+        fn get_offset(&self) -> Result<f32> {
+            Ok(self.0.borrow().offset)
+        }
+
+        fn get_length(&self) -> Result<f32> {
+            Ok(self.0.borrow().length)
+        }
+
+        fn set_volume(&mut self, volume: f32) -> Result<()> {
+            self.0.borrow_mut().volume = volume;
+            Ok(())
+        }
+
+        fn set_loop_range(&mut self, start: f32, end: Option<f32>) -> Result<()> {
+            self.0.borrow_mut().loop_range = (start, end);
+            Ok(())
+        }
+
+        fn set_loop_callback(&mut self, callback: Option<Box<dyn Fn()>>) -> Result<()> {
+            self.0.borrow_mut().loop_callback = callback;
+            Ok(())
+        }
+    }
+
+    struct Harness {
+        player: MusicPlayer<FakeDeck>,
+        decks: [Rc<RefCell<FakeState>>; 2],
+    }
+
+    impl Harness {
+        fn new() -> Self {
+            let decks: [Rc<RefCell<FakeState>>; 2] = Default::default();
+            let player =
+                MusicPlayer::with_decks([FakeDeck(decks[0].clone()), FakeDeck(decks[1].clone())]);
+            Self { player, decks }
+        }
+
+        // Runs the clock forward, then lets the player react, like a frame.
+        fn step(&mut self, dt: f32) {
+            for deck in &self.decks {
+                deck.borrow_mut().advance(dt);
+            }
+            self.player.update(dt).unwrap();
+        }
+
+        fn deck(&self, index: usize) -> core::cell::Ref<'_, FakeState> {
+            self.decks[index].borrow()
+        }
+
+        fn current_path(&self) -> Option<&str> {
+            self.player.current_track().map(|track| track.path.as_str())
+        }
+    }
+
+    #[test]
+    fn plays_queued_tracks_in_order() {
+        let mut harness = Harness::new();
+        harness.player.enqueue(MusicTrack::new("a:2"));
+        harness.player.enqueue(MusicTrack::new("b:1"));
+        harness.step(0.0);
+        assert_eq!(harness.current_path(), Some("a:2"));
+        assert_eq!(harness.player.queue_len(), 1);
+        assert!(harness.deck(1).playing);
+        assert_eq!(harness.deck(1).volume, 1.0);
+        // Tracks alternate between the decks, starting with the second.
+        assert!(!harness.deck(0).playing);
+
+        let mut played = Vec::new();
+        for _ in 0..12 {
+            harness.step(0.25);
+            if let Some(path) = harness.current_path() {
+                if played.last().is_none_or(|last: &String| last != path) {
+                    played.push(path.into());
+                }
+            }
+        }
+        assert_eq!(played, ["a:2", "b:1"]);
+        assert!(!harness.player.is_playing());
+        assert!(!harness.deck(1).playing && !harness.deck(0).playing);
+    }
+
+    #[test]
+    fn crossfades_between_tracks() {
+        let mut harness = Harness::new();
+        harness.player.set_crossfade(2.0);
+        harness.player.play(MusicTrack::new("a:10")).unwrap();
+        harness.player.enqueue(MusicTrack::new("b:10"));
+        // The first track fades in too.
+        harness.step(1.0);
+        assert_eq!(harness.deck(1).volume, 0.5);
+        harness.step(1.0);
+        assert_eq!(harness.deck(1).volume, 1.0);
+
+        // The next track starts once the rest of this one fits in the crossfade.
+        for _ in 0..5 {
+            harness.step(1.0);
+        }
+        assert!(!harness.deck(0).playing);
+        harness.step(1.0);
+        assert_eq!(harness.current_path(), Some("b:10"));
+        assert!(harness.deck(0).playing);
+        assert_eq!((harness.deck(1).volume, harness.deck(0).volume), (1.0, 0.0));
+        harness.step(1.0);
+        assert_eq!((harness.deck(1).volume, harness.deck(0).volume), (0.5, 0.5));
+        harness.step(1.0);
+        assert_eq!(harness.deck(0).volume, 1.0);
+        assert!(!harness.deck(1).playing);
+    }
+
+    #[test]
+    fn leaves_loops_to_the_deck() {
+        let mut harness = Harness::new();
+        let track = MusicTrack::new("a:10").looped(2.0, Some(5.0), Some(2));
+        harness.player.play(track).unwrap();
+        harness.player.enqueue(MusicTrack::new("b:10"));
+        assert_eq!(harness.deck(1).loop_range, (2.0, Some(5.0)));
+        // Once through and two jumps back.
+        assert_eq!(harness.deck(1).repeats_left, 3);
+
+        // Just past the first jump back, with the time over carried into the loop.
+        for _ in 0..11 {
+            harness.step(0.5);
+        }
+        assert_eq!(harness.deck(1).offset, 2.5);
+        assert_eq!(harness.player.loops[1].load(Ordering::Acquire), 1);
+        assert_eq!(harness.current_path(), Some("a:10"));
+        for _ in 0..6 {
+            harness.step(0.5);
+        }
+        assert_eq!(harness.player.loops[1].load(Ordering::Acquire), 2);
+
+        // Then it plays on to the end of the file before moving on.
+        while harness.current_path() == Some("a:10") {
+            assert!(harness.deck(1).playing);
+            harness.step(0.5);
+        }
+        assert_eq!(harness.current_path(), Some("b:10"));
+        assert_eq!(harness.player.loops[0].load(Ordering::Acquire), 0);
+    }
+
+    #[test]
+    fn loops_forever_until_skipped() {
+        let mut harness = Harness::new();
+        let track = MusicTrack::new("a:3").looped(0.0, None, None);
+        harness.player.play(track).unwrap();
+        harness.player.enqueue(MusicTrack::new("b:3"));
+        assert_eq!(harness.deck(1).repeats_left, 0);
+        for _ in 0..20 {
+            harness.step(0.5);
+        }
+        assert_eq!(harness.current_path(), Some("a:3"));
+        assert!(harness.deck(1).playing);
+        harness.player.skip().unwrap();
+        assert_eq!(harness.current_path(), Some("b:3"));
+    }
+
+    #[test]
+    fn ducks_and_scales_volume() {
+        let mut harness = Harness::new();
+        harness.player.play(MusicTrack::new("a:100")).unwrap();
+        harness.player.duck(0.5, 1.0).unwrap();
+        harness.step(0.5);
+        assert_eq!(harness.deck(1).volume, 0.75);
+        harness.step(0.5);
+        assert_eq!(harness.deck(1).volume, 0.5);
+        harness.player.set_volume(0.5).unwrap();
+        assert_eq!(harness.deck(1).volume, 0.25);
+        harness.player.unduck(0.0).unwrap();
+        assert_eq!(harness.deck(1).volume, 0.5);
+    }
+
+    #[test]
+    fn pauses_playback_and_fades() {
+        let mut harness = Harness::new();
+        harness.player.set_crossfade(1.0);
+        harness.player.play(MusicTrack::new("a:10")).unwrap();
+        harness.step(0.5);
+        harness.player.pause().unwrap();
+        assert!(!harness.deck(1).playing);
+        harness.step(5.0);
+        assert_eq!(harness.deck(1).volume, 0.5);
+        assert_eq!(harness.deck(1).offset, 0.5);
+        harness.player.resume().unwrap();
+        assert!(harness.deck(1).playing);
+        assert_eq!(harness.deck(1).repeats_left, 1);
+    }
+}
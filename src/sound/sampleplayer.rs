@@ -1,15 +1,23 @@
 use crate::{log_to_console, pd_func_caller, pd_func_caller_log};
 use crankstart_sys::ctypes;
 
-use alloc::rc::Rc;
+use super::source::{free_callback, sound_source_callback};
+use alloc::{boxed::Box, rc::Rc, vec::Vec};
 use anyhow::{anyhow, ensure, Error, Result};
+use core::{ops::Range, ptr, slice};
+
+pub use crankstart_sys::SoundFormat;
 
 /// Note: Make sure you hold on to a SamplePlayer until the sample has played as much as you want,
 /// because dropping it will stop playback.
 #[derive(Debug)]
 pub struct SamplePlayer {
     raw_subsystem: *const crankstart_sys::playdate_sound_sampleplayer,
-    raw_player: *mut crankstart_sys::SamplePlayer,
+    pub(crate) raw_player: *mut crankstart_sys::SamplePlayer,
+
+    // Callbacks handed to the sound engine, freed once they're replaced or the player is freed.
+    finish_callback: Option<*mut Box<dyn Fn()>>,
+    loop_callback: Option<*mut Box<dyn Fn()>>,
 
     // We store an Rc clone of the audio sample so that it's not freed before the player is
     // finished using it, or until another sample is set.
@@ -20,11 +28,12 @@ impl Drop for SamplePlayer {
     fn drop(&mut self) {
         // Use _log to leak rather than fail
         pd_func_caller_log!((*self.raw_subsystem).freePlayer, self.raw_player);
+        free_callback(self.finish_callback.take());
+        free_callback(self.loop_callback.take());
     }
 }
 
-// Not implemented: newPlayer (use Sound::get_sample_player), and setFinishCallback and setLoopCallback
-// (waiting on crankstart callback strategy).
+// Not implemented: newPlayer (use Sound::get_sample_player).
 impl SamplePlayer {
     pub(crate) fn new(
         raw_subsystem: *const crankstart_sys::playdate_sound_sampleplayer,
@@ -41,6 +50,8 @@ impl SamplePlayer {
         Ok(Self {
             raw_subsystem,
             raw_player,
+            finish_callback: None,
+            loop_callback: None,
             sample: None,
         })
     }
@@ -164,6 +175,42 @@ impl SamplePlayer {
     pub fn get_length(&self) -> Result<f32> {
         pd_func_caller!((*self.raw_subsystem).getLength, self.raw_player)
     }
+
+    /// Sets a callback to be called when playback finishes, whether it reaches the end or is
+    /// stopped; None removes it.
+    pub fn set_finish_callback(&mut self, callback: Option<Box<dyn Fn()>>) -> Result<()> {
+        let raw_callback_ptr = callback.map(|callback| Box::into_raw(Box::new(callback)));
+        pd_func_caller!(
+            (*self.raw_subsystem).setFinishCallback,
+            self.raw_player,
+            raw_callback_ptr.map(|_| sound_source_callback as _),
+            raw_callback_ptr.map_or(ptr::null_mut(), |ptr| ptr as *mut ctypes::c_void)
+        )?;
+        // The old callback can't be called any more, so it's safe to free.
+        free_callback(core::mem::replace(
+            &mut self.finish_callback,
+            raw_callback_ptr,
+        ));
+        Ok(())
+    }
+
+    /// Sets a callback to be called each time playback loops back to the start; None removes
+    /// it.
+    pub fn set_loop_callback(&mut self, callback: Option<Box<dyn Fn()>>) -> Result<()> {
+        let raw_callback_ptr = callback.map(|callback| Box::into_raw(Box::new(callback)));
+        pd_func_caller!(
+            (*self.raw_subsystem).setLoopCallback,
+            self.raw_player,
+            raw_callback_ptr.map(|_| sound_source_callback as _),
+            raw_callback_ptr.map_or(ptr::null_mut(), |ptr| ptr as *mut ctypes::c_void)
+        )?;
+        // The old callback can't be called any more, so it's safe to free.
+        free_callback(core::mem::replace(
+            &mut self.loop_callback,
+            raw_callback_ptr,
+        ));
+        Ok(())
+    }
 }
 
 /// A loaded sound effect.
@@ -171,13 +218,13 @@ impl SamplePlayer {
 // another Rc reference.  We use Rc so we don't free the sample before we're done using it.
 #[derive(Clone, Debug)]
 pub struct AudioSample {
-    inner: Rc<AudioSampleInner>,
+    pub(crate) inner: Rc<AudioSampleInner>,
 }
 
 #[derive(Debug)]
-struct AudioSampleInner {
-    raw_subsystem: *const crankstart_sys::playdate_sound_sample,
-    raw_audio_sample: *mut crankstart_sys::AudioSample,
+pub(crate) struct AudioSampleInner {
+    pub(crate) raw_subsystem: *const crankstart_sys::playdate_sound_sample,
+    pub(crate) raw_audio_sample: *mut crankstart_sys::AudioSample,
 }
 
 impl Drop for AudioSampleInner {
@@ -187,8 +234,8 @@ impl Drop for AudioSampleInner {
     }
 }
 
-// Not implemented: getData, newSampleBuffer, loadIntoSample, newSampleFromData -
-// only Sound::load_audio_sample for now.
+// Not implemented: newSampleBuffer and loadIntoSample (use Sound::load_audio_sample or
+// Sound::new_audio_sample_from_data).
 impl AudioSample {
     pub(crate) fn new(
         raw_subsystem: *const crankstart_sys::playdate_sound_sample,
@@ -217,4 +264,189 @@ impl AudioSample {
             self.inner.raw_audio_sample
         )
     }
+
+    /// Returns the sample's data, along with its format and rate.  Compressed (ADPCM) samples
+    /// are returned as-is; see `decompress`.
+    pub fn get_data(&self) -> Result<SampleData<'_>> {
+        let mut data = ptr::null_mut();
+        let mut format = SoundFormat::kSound16bitMono;
+        let mut sample_rate = 0;
+        let mut byte_length = 0;
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).getData,
+            self.inner.raw_audio_sample,
+            &mut data,
+            &mut format,
+            &mut sample_rate,
+            &mut byte_length,
+        )?;
+        let data = if data.is_null() || byte_length == 0 {
+            &[][..]
+        } else {
+            // The data lives as long as the sample, which we're borrowing.
+            unsafe { slice::from_raw_parts(data, byte_length as usize) }
+        };
+        Ok(SampleData {
+            data,
+            format,
+            sample_rate,
+        })
+    }
+
+    /// Decompresses an ADPCM sample in place, so its data can be read as PCM.
+    pub fn decompress(&self) -> Result<()> {
+        let result = pd_func_caller!(
+            (*self.inner.raw_subsystem).decompress,
+            self.inner.raw_audio_sample
+        )?;
+        ensure!(
+            result == 1,
+            "sample.decompress should return 1; returned {}",
+            result
+        );
+        Ok(())
+    }
+
+    /// Returns a new sample holding a copy of the given range of frames.  Compressed samples
+    /// can't be sliced; `decompress` them first.
+    pub fn slice(&self, frames: Range<usize>) -> Result<AudioSample> {
+        let data = self.get_data()?;
+        let bytes = data.frames(frames.clone()).ok_or_else(|| {
+            anyhow!(
+                "Can't slice frames {:?} from a {:?} sample of {:?} frames",
+                frames,
+                data.format,
+                data.frame_count()
+            )
+        })?;
+        super::Sound::get().new_audio_sample_from_data(
+            bytes.to_vec(),
+            data.format,
+            data.sample_rate,
+        )
+    }
+}
+
+/// Returns the size of one frame of audio in the given format, or None for ADPCM, which is
+/// compressed in blocks.
+pub(crate) fn bytes_per_frame(format: SoundFormat) -> Option<usize> {
+    match format {
+        SoundFormat::kSound8bitMono => Some(1),
+        SoundFormat::kSound8bitStereo | SoundFormat::kSound16bitMono => Some(2),
+        SoundFormat::kSound16bitStereo => Some(4),
+        SoundFormat::kSoundADPCMMono | SoundFormat::kSoundADPCMStereo => None,
+    }
+}
+
+/// The raw data of an `AudioSample`; see `AudioSample::get_data`.  Samples are little-endian and
+/// stereo frames are interleaved left, right.
+#[derive(Clone, Copy, Debug)]
+pub struct SampleData<'a> {
+    /// The bytes of the sample.
+    pub data: &'a [u8],
+    /// How the bytes are encoded.
+    pub format: SoundFormat,
+    /// Frames per second.
+    pub sample_rate: u32,
+}
+
+impl<'a> SampleData<'a> {
+    /// Returns 2 for stereo formats and 1 for mono.
+    pub fn channels(&self) -> usize {
+        match self.format {
+            SoundFormat::kSound8bitStereo
+            | SoundFormat::kSound16bitStereo
+            | SoundFormat::kSoundADPCMStereo => 2,
+            _ => 1,
+        }
+    }
+
+    /// Returns the size of one frame, or None if the data is compressed.
+    pub fn bytes_per_frame(&self) -> Option<usize> {
+        bytes_per_frame(self.format)
+    }
+
+    /// Returns the number of frames, or None if the data is compressed.
+    pub fn frame_count(&self) -> Option<usize> {
+        self.bytes_per_frame()
+            .map(|bytes_per_frame| self.data.len() / bytes_per_frame)
+    }
+
+    /// Returns the bytes of the given range of frames, or None if it's out of bounds or the
+    /// data is compressed.
+    pub fn frames(&self, frames: Range<usize>) -> Option<&'a [u8]> {
+        let bytes_per_frame = self.bytes_per_frame()?;
+        let start = frames.start.checked_mul(bytes_per_frame)?;
+        let end = frames.end.checked_mul(bytes_per_frame)?;
+        self.data.get(start..end)
+    }
+
+    /// Returns one sample from the given frame and channel (0 is left), scaled to 16 bits, or
+    /// None if it's out of bounds or the data is compressed.
+    pub fn sample(&self, frame: usize, channel: usize) -> Option<i16> {
+        if channel >= self.channels() {
+            return None;
+        }
+        let bytes = self.frames(frame..frame.checked_add(1)?)?;
+        match self.format {
+            SoundFormat::kSound8bitMono | SoundFormat::kSound8bitStereo => {
+                Some((bytes[channel] as i8 as i16) << 8)
+            }
+            _ => Some(i16::from_le_bytes([
+                bytes[channel * 2],
+                bytes[channel * 2 + 1],
+            ])),
+        }
+    }
+
+    /// Returns the samples of one channel (0 is left), scaled to 16 bits, or None if the channel
+    /// doesn't exist or the data is compressed.
+    pub fn channel_samples(&self, channel: usize) -> Option<Vec<i16>> {
+        let frame_count = self.frame_count()?;
+        (0..frame_count)
+            .map(|frame| self.sample(frame, channel))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(data: &[u8], format: SoundFormat) -> SampleData<'_> {
+        SampleData {
+            data,
+            format,
+            sample_rate: 44100,
+        }
+    }
+
+    #[test]
+    fn reads_samples() {
+        let stereo = data(
+            &[0x01, 0x02, 0xff, 0xff, 0x00, 0x80, 0x34, 0x12],
+            SoundFormat::kSound16bitStereo,
+        );
+        assert_eq!(stereo.frame_count(), Some(2));
+        assert_eq!(stereo.sample(0, 0), Some(0x0201));
+        assert_eq!(stereo.sample(0, 1), Some(-1));
+        assert_eq!(stereo.sample(1, 0), Some(i16::MIN));
+        assert_eq!(stereo.sample(1, 1), Some(0x1234));
+
+        let mono = data(&[0x7f, 0x80], SoundFormat::kSound8bitMono);
+        assert_eq!(mono.sample(0, 0), Some(0x7f00));
+        assert_eq!(mono.sample(1, 0), Some(i16::MIN));
+    }
+
+    #[test]
+    fn rejects_out_of_range_samples() {
+        let mono = data(&[0, 0, 0, 0], SoundFormat::kSound16bitMono);
+        assert_eq!(mono.sample(2, 0), None);
+        assert_eq!(mono.sample(0, 1), None);
+        assert_eq!(mono.sample(usize::MAX, 0), None);
+        assert_eq!(mono.frames(usize::MAX / 2..usize::MAX), None);
+
+        let compressed = data(&[0; 8], SoundFormat::kSoundADPCMMono);
+        assert_eq!(compressed.sample(0, 0), None);
+    }
 }
@@ -0,0 +1,318 @@
+use crate::{pd_func_caller, pd_func_caller_log};
+use crankstart_sys::ctypes;
+
+use super::{instrument::Instrument, track::Track};
+use alloc::{boxed::Box, collections::BTreeMap, rc::Rc};
+use anyhow::{anyhow, ensure, Error, Result};
+use core::{cell::RefCell, ptr};
+use cstr_core::CString;
+
+/// A set of `Track`s played together at a shared tempo, e.g. music loaded from a MIDI file.
+///
+/// Note: Make sure you hold on to a Sequence until it has played as much as you want, because
+/// dropping it will stop playback.
+// Really a wrapper around an Rc clone of the internal structure; derive Clone so it's easy to get
+// another Rc reference.  Tracks that belong to the sequence hold one too, so it isn't freed while
+// they're in use.
+#[derive(Clone, Debug)]
+pub struct Sequence {
+    inner: Rc<SequenceInner>,
+}
+
+#[derive(Debug)]
+pub(crate) struct SequenceInner {
+    raw_subsystem: *const crankstart_sys::playdate_sound_sequence,
+    raw_track_subsystem: *const crankstart_sys::playdate_sound_track,
+    raw_control_signal: *const crankstart_sys::playdate_control_signal,
+    raw_sequence: *mut crankstart_sys::SoundSequence,
+
+    // Tracks given to set_track_at_index, so what they use isn't freed while the sequence plays
+    // them.  The sequence frees the tracks themselves.
+    tracks: RefCell<BTreeMap<u32, Track>>,
+    // Instruments set on the sequence's own tracks, keyed by track pointer.
+    pub(crate) instruments: RefCell<BTreeMap<usize, Instrument>>,
+    // The callback given to play, if any.
+    finish_callback: RefCell<Option<*mut Box<dyn Fn()>>>,
+}
+
+impl Drop for SequenceInner {
+    fn drop(&mut self) {
+        // Use _log to leak rather than fail
+        pd_func_caller_log!((*self.raw_subsystem).freeSequence, self.raw_sequence);
+        if let Some(raw_callback_ptr) = self.finish_callback.get_mut().take() {
+            unsafe {
+                // Recast into box to let Box deal with freeing the right memory
+                let _ = Box::from_raw(raw_callback_ptr);
+            }
+        }
+    }
+}
+
+extern "C" fn sequence_finished_callback(
+    _seq: *mut crankstart_sys::SoundSequence,
+    user_data: *mut core::ffi::c_void,
+) {
+    unsafe {
+        let callback = user_data as *mut Box<dyn Fn()>;
+        (*callback)()
+    }
+}
+
+// Not implemented: newSequence (use Sound::get_sequence) and getTempo_deprecated.
+impl Sequence {
+    pub(crate) fn new(
+        raw_subsystem: *const crankstart_sys::playdate_sound_sequence,
+        raw_track_subsystem: *const crankstart_sys::playdate_sound_track,
+        raw_control_signal: *const crankstart_sys::playdate_control_signal,
+        raw_sequence: *mut crankstart_sys::SoundSequence,
+    ) -> Result<Self> {
+        ensure!(
+            !raw_subsystem.is_null(),
+            "Null pointer given as subsystem to Sequence::new"
+        );
+        ensure!(
+            !raw_track_subsystem.is_null(),
+            "Null pointer given as track subsystem to Sequence::new"
+        );
+        ensure!(
+            !raw_control_signal.is_null(),
+            "Null pointer given as control signal subsystem to Sequence::new"
+        );
+        ensure!(
+            !raw_sequence.is_null(),
+            "Null pointer given as sequence to Sequence::new"
+        );
+        Ok(Self {
+            inner: Rc::new(SequenceInner {
+                raw_subsystem,
+                raw_track_subsystem,
+                raw_control_signal,
+                raw_sequence,
+                tracks: RefCell::new(BTreeMap::new()),
+                instruments: RefCell::new(BTreeMap::new()),
+                finish_callback: RefCell::new(None),
+            }),
+        })
+    }
+
+    /// Loads a MIDI file into the sequence, creating a track for each of its tracks.  You'll
+    /// need to set an instrument on each track before playing.
+    pub fn load_midi_file(&self, file_path: &str) -> Result<()> {
+        let file_path_c = CString::new(file_path).map_err(Error::msg)?;
+        let arg_ptr = file_path_c.as_ptr() as *const ctypes::c_char;
+        let result = pd_func_caller!(
+            (*self.inner.raw_subsystem).loadMIDIFile,
+            self.inner.raw_sequence,
+            arg_ptr
+        )?;
+        if result == 1 {
+            Ok(())
+        } else {
+            Err(anyhow!("load_midi_file failed to load '{}'", file_path))
+        }
+    }
+
+    /// Starts playing the sequence.  If given, `finish_callback` is called when it ends.
+    pub fn play(&self, finish_callback: Option<Box<dyn Fn()>>) -> Result<()> {
+        let raw_callback_ptr = finish_callback.map(|callback| Box::into_raw(Box::new(callback)));
+        let callback_fn = raw_callback_ptr.map(|_| {
+            sequence_finished_callback
+                as unsafe extern "C" fn(*mut crankstart_sys::SoundSequence, *mut ctypes::c_void)
+        });
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).play,
+            self.inner.raw_sequence,
+            callback_fn,
+            raw_callback_ptr.map_or(ptr::null_mut(), |ptr| ptr as *mut ctypes::c_void)
+        )?;
+        // The old callback can't be called any more, so it's safe to free.
+        if let Some(old_ptr) = self.inner.finish_callback.replace(raw_callback_ptr) {
+            unsafe {
+                let _ = Box::from_raw(old_ptr);
+            }
+        }
+        Ok(())
+    }
+
+    /// Stops playing the sequence.
+    pub fn stop(&self) -> Result<()> {
+        pd_func_caller!((*self.inner.raw_subsystem).stop, self.inner.raw_sequence)
+    }
+
+    /// Returns whether the sequence is currently playing.
+    pub fn is_playing(&self) -> Result<bool> {
+        let result = pd_func_caller!(
+            (*self.inner.raw_subsystem).isPlaying,
+            self.inner.raw_sequence
+        )?;
+        Ok(result == 1)
+    }
+
+    /// Sends a note off event to all of the sequence's instruments.
+    pub fn all_notes_off(&self) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).allNotesOff,
+            self.inner.raw_sequence
+        )
+    }
+
+    /// Returns the current playback time, in sound engine frames (44.1k per second).
+    pub fn get_time(&self) -> Result<u32> {
+        pd_func_caller!((*self.inner.raw_subsystem).getTime, self.inner.raw_sequence)
+    }
+
+    /// Sets the current playback time, in sound engine frames.
+    pub fn set_time(&self, time: u32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setTime,
+            self.inner.raw_sequence,
+            time
+        )
+    }
+
+    /// Loops the steps from `loop_start` to `loop_end` `loops` times; if `loops` is 0, loop
+    /// until stopped.
+    pub fn set_loops(&self, loop_start: i32, loop_end: i32, loops: i32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setLoops,
+            self.inner.raw_sequence,
+            loop_start,
+            loop_end,
+            loops
+        )
+    }
+
+    /// Returns the tempo, in steps per second.
+    pub fn get_tempo(&self) -> Result<f32> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).getTempo,
+            self.inner.raw_sequence
+        )
+    }
+
+    /// Sets the tempo, in steps per second.
+    pub fn set_tempo(&self, steps_per_second: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setTempo,
+            self.inner.raw_sequence,
+            steps_per_second
+        )
+    }
+
+    /// Returns the length of the longest track, in steps.
+    pub fn get_length(&self) -> Result<u32> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).getLength,
+            self.inner.raw_sequence
+        )
+    }
+
+    /// Returns the current step and the offset into it, in frames.
+    pub fn get_current_step(&self) -> Result<(i32, i32)> {
+        let mut time_offset = 0;
+        let step = pd_func_caller!(
+            (*self.inner.raw_subsystem).getCurrentStep,
+            self.inner.raw_sequence,
+            &mut time_offset
+        )?;
+        Ok((step, time_offset))
+    }
+
+    /// Moves playback to the given step and offset, in frames.  If `play_notes` is true, notes
+    /// that span the new position are played.
+    pub fn set_current_step(&self, step: i32, time_offset: i32, play_notes: bool) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setCurrentStep,
+            self.inner.raw_sequence,
+            step,
+            time_offset,
+            play_notes as ctypes::c_int
+        )
+    }
+
+    /// Returns the number of tracks in the sequence.
+    pub fn get_track_count(&self) -> Result<i32> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).getTrackCount,
+            self.inner.raw_sequence
+        )
+    }
+
+    /// Adds a new, empty track to the sequence and returns it.
+    pub fn add_track(&self) -> Result<Track> {
+        let raw_track = pd_func_caller!(
+            (*self.inner.raw_subsystem).addTrack,
+            self.inner.raw_sequence
+        )?;
+        ensure!(!raw_track.is_null(), "Null returned from sequence.addTrack");
+        self.wrap_track(raw_track)
+    }
+
+    /// Returns the track at the given index, or None if there isn't one.
+    pub fn get_track_at_index(&self, index: u32) -> Result<Option<Track>> {
+        if let Some(track) = self.inner.tracks.borrow().get(&index) {
+            return Ok(Some(track.clone()));
+        }
+        let raw_track = pd_func_caller!(
+            (*self.inner.raw_subsystem).getTrackAtIndex,
+            self.inner.raw_sequence,
+            index
+        )?;
+        if raw_track.is_null() {
+            Ok(None)
+        } else {
+            self.wrap_track(raw_track).map(Some)
+        }
+    }
+
+    /// Puts the given track at the given index in the sequence.  The sequence takes ownership of
+    /// the track and frees it along with itself, so a track can only be in one sequence, and
+    /// tracks from another sequence can't be added.
+    pub fn set_track_at_index(&self, track: &Track, index: u32) -> Result<()> {
+        let owned_by_self = match &track.inner.sequence {
+            Some(sequence) => {
+                ensure!(
+                    Rc::ptr_eq(sequence, &self.inner),
+                    "Track belongs to another sequence"
+                );
+                true
+            }
+            None => false,
+        };
+        // Each sequence frees the tracks it was given, so giving one to two would free it twice.
+        let already_here = self
+            .inner
+            .tracks
+            .borrow()
+            .get(&index)
+            .is_some_and(|existing| Rc::ptr_eq(&existing.inner, &track.inner));
+        ensure!(
+            !track.inner.given_to_sequence.get() || already_here,
+            "Track was already given to a sequence"
+        );
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setTrackAtIndex,
+            self.inner.raw_sequence,
+            track.inner.raw_track,
+            index
+        )?;
+        // A track that already belongs to this sequence keeps it alive, so holding on to it here
+        // would be a reference cycle.
+        if owned_by_self {
+            self.inner.tracks.borrow_mut().remove(&index);
+        } else {
+            track.inner.given_to_sequence.set(true);
+            self.inner.tracks.borrow_mut().insert(index, track.clone());
+        }
+        Ok(())
+    }
+
+    fn wrap_track(&self, raw_track: *mut crankstart_sys::SequenceTrack) -> Result<Track> {
+        Track::new(
+            self.inner.raw_track_subsystem,
+            self.inner.raw_control_signal,
+            raw_track,
+            Some(self.inner.clone()),
+        )
+    }
+}
@@ -0,0 +1,246 @@
+use super::{AudioSample, SamplePlayer, Sound};
+use alloc::{collections::BTreeMap, string::String, vec::Vec};
+use anyhow::{anyhow, Result};
+
+/// How an `SfxBank` plays one of its sounds.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SfxOptions {
+    /// When every voice is busy, a sound can only take over a voice playing a sound with the
+    /// same or lower priority.
+    pub priority: u8,
+    /// How many copies of the sound can play at once; when the limit is reached, the oldest copy
+    /// is cut off.  None means no limit beyond the size of the pool.
+    pub max_instances: Option<usize>,
+    /// Volume, out of 1.
+    pub volume: f32,
+    /// Each play lowers the volume by a random fraction up to this, e.g. 0.2 for 80-100%.
+    pub volume_variation: f32,
+    /// Each play changes the playback rate by a random amount up to this either way, e.g. 0.1
+    /// for 0.9-1.1.
+    pub pitch_variation: f32,
+}
+
+impl Default for SfxOptions {
+    fn default() -> Self {
+        Self {
+            priority: 0,
+            max_instances: None,
+            volume: 1.0,
+            volume_variation: 0.0,
+            pitch_variation: 0.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct SfxSound {
+    sample: AudioSample,
+    options: SfxOptions,
+}
+
+#[derive(Debug)]
+struct Voice {
+    player: SamplePlayer,
+    // Index into SfxBank::sounds of the sample set on the player, if any.
+    sound: Option<usize>,
+    // When the voice was last started, in plays, for finding the oldest.
+    started: u32,
+}
+
+/// A set of preloaded, named sound effects played through a fixed pool of `SamplePlayer`s.  All
+/// players and samples are created up front, so `play` doesn't allocate; when more sounds are
+/// played than there are voices, lower priority and older sounds are cut off.
+#[derive(Debug)]
+pub struct SfxBank {
+    sounds: Vec<SfxSound>,
+    names: BTreeMap<String, usize>,
+    voices: Vec<Voice>,
+    plays: u32,
+    // xorshift32 state for pitch and volume variation.
+    random_state: u32,
+}
+
+impl SfxBank {
+    pub(crate) fn new(players: Vec<SamplePlayer>) -> Self {
+        Self {
+            sounds: Vec::new(),
+            names: BTreeMap::new(),
+            voices: players
+                .into_iter()
+                .map(|player| Voice {
+                    player,
+                    sound: None,
+                    started: 0,
+                })
+                .collect(),
+            plays: 0,
+            random_state: 0x2545_f491,
+        }
+    }
+
+    /// Loads a sample from a file and adds it to the bank under `name`, replacing any sound
+    /// with that name.
+    pub fn load(&mut self, name: &str, sample_path: &str, options: SfxOptions) -> Result<()> {
+        let sample = Sound::get().load_audio_sample(sample_path)?;
+        self.add(name, &sample, options)
+    }
+
+    /// Adds an already loaded sample to the bank under `name`, replacing any sound with that
+    /// name.
+    pub fn add(&mut self, name: &str, sample: &AudioSample, options: SfxOptions) -> Result<()> {
+        let sound = SfxSound {
+            sample: sample.clone(),
+            options,
+        };
+        if let Some(&index) = self.names.get(name) {
+            // Voices still playing the old sample need to pick up the new one next time.
+            for voice in &mut self.voices {
+                if voice.sound == Some(index) {
+                    voice.player.stop()?;
+                    voice.sound = None;
+                }
+            }
+            self.sounds[index] = sound;
+        } else {
+            self.names.insert(name.into(), self.sounds.len());
+            self.sounds.push(sound);
+        }
+        Ok(())
+    }
+
+    /// Changes the options of a sound already in the bank.
+    pub fn set_options(&mut self, name: &str, options: SfxOptions) -> Result<()> {
+        let index = self.index(name)?;
+        self.sounds[index].options = options;
+        Ok(())
+    }
+
+    /// Sets the seed for pitch and volume variation, e.g. to make a replay sound the same.
+    pub fn set_seed(&mut self, seed: u32) {
+        // xorshift gets stuck at 0.
+        self.random_state = seed.max(1);
+    }
+
+    /// Returns how many voices are in the pool.
+    pub fn voice_count(&self) -> usize {
+        self.voices.len()
+    }
+
+    /// Returns how many voices are currently playing.
+    pub fn active_voice_count(&self) -> Result<usize> {
+        let mut count = 0;
+        for voice in &self.voices {
+            if voice.sound.is_some() && voice.player.is_playing()? {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Plays the named sound.  Returns false if every voice is busy with higher priority
+    /// sounds, in which case nothing plays.
+    pub fn play(&mut self, name: &str) -> Result<bool> {
+        let index = self.index(name)?;
+        let options = self.sounds[index].options;
+
+        // Find the oldest voice playing this sound, the oldest voice playing anything we're
+        // allowed to cut off, and an idle voice.
+        let mut instances = 0;
+        let mut oldest_instance: Option<usize> = None;
+        let mut idle = None;
+        let mut steal: Option<usize> = None;
+        for (voice_index, voice) in self.voices.iter().enumerate() {
+            let playing = match voice.sound {
+                Some(_) => voice.player.is_playing()?,
+                None => false,
+            };
+            if !playing {
+                idle = idle.or(Some(voice_index));
+                continue;
+            }
+            let voice_sound = voice.sound.unwrap_or(index);
+            if voice_sound == index {
+                instances += 1;
+                if oldest_instance.is_none_or(|oldest| voice.started < self.voices[oldest].started)
+                {
+                    oldest_instance = Some(voice_index);
+                }
+            }
+            let voice_priority = self.sounds[voice_sound].options.priority;
+            if voice_priority <= options.priority {
+                let better = steal.is_none_or(|current| {
+                    let current_priority = self.sounds[self.voices[current].sound.unwrap_or(index)]
+                        .options
+                        .priority;
+                    (voice_priority, voice.started)
+                        < (current_priority, self.voices[current].started)
+                });
+                if better {
+                    steal = Some(voice_index);
+                }
+            }
+        }
+
+        let at_limit = options.max_instances.is_some_and(|max| instances >= max);
+        let voice_index = if at_limit {
+            oldest_instance
+        } else {
+            idle.or(steal)
+        };
+        let Some(voice_index) = voice_index else {
+            return Ok(false);
+        };
+
+        let volume = options.volume * (1.0 - options.volume_variation * self.random());
+        let rate = 1.0 + options.pitch_variation * (self.random() * 2.0 - 1.0);
+        self.plays = self.plays.wrapping_add(1);
+        let voice = &mut self.voices[voice_index];
+        voice.player.stop()?;
+        if voice.sound != Some(index) {
+            voice.player.set_sample(&self.sounds[index].sample)?;
+            voice.sound = Some(index);
+        }
+        voice.started = self.plays;
+        voice.player.set_volume(volume, volume)?;
+        voice.player.play(1, rate)?;
+        Ok(true)
+    }
+
+    /// Stops every voice playing the named sound.
+    pub fn stop(&mut self, name: &str) -> Result<()> {
+        let index = self.index(name)?;
+        for voice in &self.voices {
+            if voice.sound == Some(index) {
+                voice.player.stop()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Stops every voice.
+    pub fn stop_all(&mut self) -> Result<()> {
+        for voice in &self.voices {
+            if voice.sound.is_some() {
+                voice.player.stop()?;
+            }
+        }
+        Ok(())
+    }
+
+    fn index(&self, name: &str) -> Result<usize> {
+        self.names
+            .get(name)
+            .copied()
+            .ok_or_else(|| anyhow!("No sound named '{}' in SfxBank", name))
+    }
+
+    // Returns a number in [0, 1).
+    fn random(&mut self) -> f32 {
+        let mut x = self.random_state;
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+        self.random_state = x;
+        (x >> 8) as f32 / (1u32 << 24) as f32
+    }
+}
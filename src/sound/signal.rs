@@ -0,0 +1,192 @@
+use crate::{pd_func_caller, pd_func_caller_log};
+use crankstart_sys::{ctypes, MIDINote};
+
+use alloc::{boxed::Box, rc::Rc};
+use anyhow::{ensure, Error, Result};
+use core::{any::Any, fmt::Debug};
+
+/// Something that can be used as a modulation source, like an `Lfo` or an `Envelope`.  Setters
+/// that take a `&dyn Signal` keep the signal alive for as long as it's attached, so it's fine to
+/// drop your own handle afterward.
+pub trait Signal: Debug {
+    /// Internal: returns the raw signal along with a handle that keeps it alive.
+    fn modulator(&self) -> Modulator;
+}
+
+/// A signal attached to some other part of the audio system.  Holding one keeps the underlying
+/// signal from being freed while the sound engine is still reading it.
+#[derive(Clone, Debug)]
+pub struct Modulator {
+    pub(crate) raw_signal: *mut crankstart_sys::PDSynthSignalValue,
+    // Only held to keep the signal alive.
+    owner: Rc<dyn Any>,
+}
+
+impl Modulator {
+    pub(crate) fn new(
+        raw_signal: *mut crankstart_sys::PDSynthSignalValue,
+        owner: Rc<dyn Any>,
+    ) -> Self {
+        Self { raw_signal, owner }
+    }
+
+    /// Internal: returns true if the signal is kept alive by `owner`.
+    pub(crate) fn is_owned_by<T: Any>(&self, owner: &Rc<T>) -> bool {
+        core::ptr::eq(
+            Rc::as_ptr(&self.owner) as *const (),
+            Rc::as_ptr(owner) as *const (),
+        )
+    }
+}
+
+/// Internal: returns the raw pointer to pass to a modulator setter, where null clears it.
+pub(crate) fn raw_modulator(
+    modulator: Option<&Modulator>,
+) -> *mut crankstart_sys::PDSynthSignalValue {
+    modulator.map_or(core::ptr::null_mut(), |modulator| modulator.raw_signal)
+}
+
+/// A modulation source implemented in Rust.  Wrap it with `Sound::get_custom_signal` to use it
+/// anywhere a `Signal` is accepted; the sound engine then calls its methods from the audio
+/// thread.
+pub trait SignalGenerator: 'static {
+    /// Returns the signal's value for the next `frames` frames.  To change value partway
+    /// through, lower `frames` to the number of frames the returned value covers.  To ramp
+    /// instead of jumping, set `interpolated_value` to the value at the end of those frames.
+    fn step(&mut self, frames: &mut i32, interpolated_value: &mut f32) -> f32;
+
+    /// Called when a note starts on whatever the signal is modulating.  `length` is the note
+    /// length in seconds, or None if it plays until released.
+    fn note_on(&mut self, note: MIDINote, velocity: f32, length: Option<f32>) {}
+
+    /// Called when the note is released.  If `stop` is true the note ends immediately;
+    /// `offset` is the frame within the current block where the release happens.
+    fn note_off(&mut self, stop: bool, offset: i32) {}
+
+    /// Called when the engine is done with the signal, just before it's dropped.
+    fn dealloc(&mut self) {}
+}
+
+extern "C" fn signal_step<G: SignalGenerator>(
+    userdata: *mut ctypes::c_void,
+    ioframes: *mut ctypes::c_int,
+    ifval: *mut f32,
+) -> f32 {
+    let generator = unsafe { &mut *(userdata as *mut G) };
+    unsafe { generator.step(&mut *ioframes, &mut *ifval) }
+}
+
+extern "C" fn signal_note_on<G: SignalGenerator>(
+    userdata: *mut ctypes::c_void,
+    note: MIDINote,
+    velocity: f32,
+    len: f32,
+) {
+    let generator = unsafe { &mut *(userdata as *mut G) };
+    let length = if len < 0.0 { None } else { Some(len) };
+    generator.note_on(note, velocity, length);
+}
+
+extern "C" fn signal_note_off<G: SignalGenerator>(
+    userdata: *mut ctypes::c_void,
+    stop: ctypes::c_int,
+    offset: ctypes::c_int,
+) {
+    let generator = unsafe { &mut *(userdata as *mut G) };
+    generator.note_off(stop != 0, offset);
+}
+
+extern "C" fn signal_dealloc<G: SignalGenerator>(userdata: *mut ctypes::c_void) {
+    // Recast into box to let Box deal with freeing the right memory
+    let mut generator = unsafe { Box::from_raw(userdata as *mut G) };
+    generator.dealloc();
+}
+
+/// A `SignalGenerator` registered with the sound engine.  The engine owns the generator and
+/// drops it when the signal is freed, which happens once this and every modulator using it are
+/// gone.
+// Really a wrapper around an Rc clone of the internal structure; derive Clone so it's easy to get
+// another Rc reference.  We use Rc so we don't free the signal while it's still a modulator.
+#[derive(Clone, Debug)]
+pub struct CustomSignal {
+    inner: Rc<CustomSignalInner>,
+}
+
+#[derive(Debug)]
+struct CustomSignalInner {
+    raw_subsystem: *const crankstart_sys::playdate_sound_signal,
+    raw_signal: *mut crankstart_sys::PDSynthSignal,
+}
+
+impl Drop for CustomSignalInner {
+    fn drop(&mut self) {
+        // Use _log to leak rather than fail
+        pd_func_caller_log!((*self.raw_subsystem).freeSignal, self.raw_signal);
+    }
+}
+
+// Not implemented: newSignal (use Sound::get_custom_signal).
+impl CustomSignal {
+    pub(crate) fn new<G: SignalGenerator>(
+        raw_subsystem: *const crankstart_sys::playdate_sound_signal,
+        generator: G,
+    ) -> Result<Self> {
+        ensure!(
+            !raw_subsystem.is_null(),
+            "Null pointer given as subsystem to CustomSignal::new"
+        );
+        let userdata = Box::into_raw(Box::new(generator));
+        let raw_signal = pd_func_caller!(
+            (*raw_subsystem).newSignal,
+            Some(signal_step::<G>),
+            Some(signal_note_on::<G>),
+            Some(signal_note_off::<G>),
+            Some(signal_dealloc::<G>),
+            userdata as *mut ctypes::c_void
+        )?;
+        if raw_signal.is_null() {
+            unsafe {
+                let _ = Box::from_raw(userdata);
+            }
+            return Err(anyhow::anyhow!("Null returned from signal.newSignal"));
+        }
+        Ok(Self {
+            inner: Rc::new(CustomSignalInner {
+                raw_subsystem,
+                raw_signal,
+            }),
+        })
+    }
+
+    /// Returns the signal's current value.
+    pub fn get_value(&self) -> Result<f32> {
+        pd_func_caller!((*self.inner.raw_subsystem).getValue, self.inner.raw_signal)
+    }
+
+    /// Multiplies the generator's output by `scale`.
+    pub fn set_value_scale(&self, scale: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setValueScale,
+            self.inner.raw_signal,
+            scale
+        )
+    }
+
+    /// Adds `offset` to the generator's output, after scaling.
+    pub fn set_value_offset(&self, offset: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setValueOffset,
+            self.inner.raw_signal,
+            offset
+        )
+    }
+}
+
+impl Signal for CustomSignal {
+    fn modulator(&self) -> Modulator {
+        Modulator::new(
+            self.inner.raw_signal as *mut crankstart_sys::PDSynthSignalValue,
+            self.inner.clone(),
+        )
+    }
+}
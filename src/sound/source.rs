@@ -0,0 +1,214 @@
+use crate::{pd_func_caller, pd_func_caller_log};
+use crankstart_sys::ctypes;
+
+use alloc::boxed::Box;
+use anyhow::{ensure, Error, Result};
+use core::slice;
+
+/// An audio source implemented in Rust.  Register it with `Sound::add_source` to have it mixed
+/// into the default channel.
+///
+/// Since `render` takes plain slices, a generator can be exercised on the host by calling it
+/// directly on a buffer.
+pub trait SourceGenerator: 'static {
+    /// Fills `left` and, for stereo sources, `right` with 16-bit samples.  Returns false if
+    /// the source produced no audio this time, which lets the mixer skip it.
+    fn render(&mut self, left: &mut [i16], right: Option<&mut [i16]>) -> bool;
+}
+
+// The context handed to the sound engine: the generator plus what it needs to build slices.
+struct SourceState<T> {
+    generator: T,
+    stereo: bool,
+}
+
+extern "C" fn source_render<T: SourceGenerator>(
+    context: *mut ctypes::c_void,
+    left: *mut i16,
+    right: *mut i16,
+    len: ctypes::c_int,
+) -> ctypes::c_int {
+    let state = unsafe { &mut *(context as *mut SourceState<T>) };
+    let len = len.max(0) as usize;
+    let left = unsafe { slice::from_raw_parts_mut(left, len) };
+    let right = if state.stereo && !right.is_null() {
+        Some(unsafe { slice::from_raw_parts_mut(right, len) })
+    } else {
+        None
+    };
+    state.generator.render(left, right) as ctypes::c_int
+}
+
+/// A `SourceGenerator` that's been registered with the sound engine.  The generator is owned
+/// here; dropping the `CustomSource` removes it from the mixer and then frees it.
+#[derive(Debug)]
+pub struct CustomSource<T: SourceGenerator> {
+    raw_sound: *const crankstart_sys::playdate_sound,
+    raw_source: *mut crankstart_sys::SoundSource,
+    state: *mut SourceState<T>,
+}
+
+impl<T: SourceGenerator> Drop for CustomSource<T> {
+    fn drop(&mut self) {
+        // The audio callback must be gone before we free the state it points to.  Use _log to
+        // leak rather than fail.
+        pd_func_caller_log!((*self.raw_sound).removeSource, self.raw_source);
+        unsafe {
+            // Recast into box to let Box deal with freeing the right memory
+            let _ = Box::from_raw(self.state);
+        }
+    }
+}
+
+impl<T: SourceGenerator> CustomSource<T> {
+    pub(crate) fn new(
+        raw_sound: *const crankstart_sys::playdate_sound,
+        generator: T,
+        stereo: bool,
+    ) -> Result<Self> {
+        ensure!(
+            !raw_sound.is_null(),
+            "Null pointer given as sound to CustomSource::new"
+        );
+        let state = Box::into_raw(Box::new(SourceState { generator, stereo }));
+        let raw_source = pd_func_caller!(
+            (*raw_sound).addSource,
+            Some(source_render::<T>),
+            state as *mut ctypes::c_void,
+            stereo as ctypes::c_int
+        )
+        .and_then(|raw_source| {
+            ensure!(!raw_source.is_null(), "Null returned from sound.addSource");
+            Ok(raw_source)
+        });
+        let raw_source = match raw_source {
+            Ok(raw_source) => raw_source,
+            Err(err) => {
+                // The mixer never got the state, so it's still ours to free.
+                unsafe {
+                    let _ = Box::from_raw(state);
+                }
+                return Err(err);
+            }
+        };
+        Ok(Self {
+            raw_sound,
+            raw_source,
+            state,
+        })
+    }
+
+    /// Removes the source from the mixer and gives back the generator.
+    pub fn remove(self) -> Result<T> {
+        let result = pd_func_caller!((*self.raw_sound).removeSource, self.raw_source)?;
+        ensure!(
+            result == 1,
+            "sound.removeSource should return 1; returned {}",
+            result
+        );
+        let state = unsafe { Box::from_raw(self.state) };
+        core::mem::forget(self);
+        Ok(state.generator)
+    }
+}
+
+// Callback trampoline for the players' finish, loop and fade callbacks; the user data is a
+// `*mut Box<dyn Fn()>` owned by the player.
+pub(crate) extern "C" fn sound_source_callback(
+    _source: *mut crankstart_sys::SoundSource,
+    user_data: *mut ctypes::c_void,
+) {
+    unsafe {
+        let callback = user_data as *mut Box<dyn Fn()>;
+        (*callback)()
+    }
+}
+
+// Frees a callback handed out to the sound engine once it can no longer be called.
+pub(crate) fn free_callback(raw_callback_ptr: Option<*mut Box<dyn Fn()>>) {
+    if let Some(raw_callback_ptr) = raw_callback_ptr {
+        unsafe {
+            // Recast into box to let Box deal with freeing the right memory
+            let _ = Box::from_raw(raw_callback_ptr);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A square wave with a period of `period` samples.
+    struct Square {
+        amplitude: i16,
+        period: usize,
+        position: usize,
+    }
+
+    impl SourceGenerator for Square {
+        fn render(&mut self, left: &mut [i16], right: Option<&mut [i16]>) -> bool {
+            for sample in left.iter_mut() {
+                *sample = if self.position < self.period / 2 {
+                    self.amplitude
+                } else {
+                    -self.amplitude
+                };
+                self.position = (self.position + 1) % self.period;
+            }
+            if let Some(right) = right {
+                right.copy_from_slice(left);
+            }
+            true
+        }
+    }
+
+    // Renders through the callback the sound engine calls.
+    fn render(state: &mut SourceState<Square>, left: &mut [i16], right: &mut [i16]) -> bool {
+        let context = state as *mut SourceState<Square> as *mut ctypes::c_void;
+        source_render::<Square>(
+            context,
+            left.as_mut_ptr(),
+            right.as_mut_ptr(),
+            left.len() as ctypes::c_int,
+        ) != 0
+    }
+
+    #[test]
+    fn renders_square_wave() {
+        let mut state = SourceState {
+            generator: Square {
+                amplitude: 1000,
+                period: 8,
+                position: 0,
+            },
+            stereo: true,
+        };
+        let (mut left, mut right) = ([0; 20], [0; 20]);
+        assert!(render(&mut state, &mut left, &mut right));
+        assert_eq!(left.iter().max(), Some(&1000));
+        assert_eq!(left.iter().min(), Some(&-1000));
+        assert_eq!(left[..4], [1000; 4]);
+        assert_eq!(left[4..8], [-1000; 4]);
+        // The wave repeats every period, and carries on across calls.
+        assert_eq!(left[8..16], left[..8]);
+        assert_eq!(right, left);
+        render(&mut state, &mut left, &mut right);
+        assert_eq!(left[..4], [-1000; 4]);
+    }
+
+    #[test]
+    fn leaves_right_alone_for_mono_sources() {
+        let mut state = SourceState {
+            generator: Square {
+                amplitude: 1,
+                period: 2,
+                position: 0,
+            },
+            stereo: false,
+        };
+        let (mut left, mut right) = ([0; 4], [7; 4]);
+        render(&mut state, &mut left, &mut right);
+        assert_eq!(left, [1, -1, 1, -1]);
+        assert_eq!(right, [7; 4]);
+    }
+}
@@ -0,0 +1,558 @@
+use crate::{pd_func_caller, pd_func_caller_log};
+use crankstart_sys::ctypes;
+
+use super::{
+    signal::{raw_modulator, Modulator, Signal},
+    AudioSample,
+};
+use alloc::{boxed::Box, collections::BTreeMap};
+use anyhow::{ensure, Error, Result};
+use core::slice;
+
+pub use crankstart_sys::{MIDINote, SoundWaveform};
+
+/// A synthesizer voice that can play notes using one of the built-in waveforms or a sample.
+///
+/// Note: Make sure you hold on to a Synth until its notes have played as much as you want,
+/// because dropping it will stop playback.
+#[derive(Debug)]
+pub struct Synth {
+    raw_subsystem: *const crankstart_sys::playdate_sound_synth,
+    pub(crate) raw_synth: *mut crankstart_sys::PDSynth,
+
+    // We store an Rc clone of the audio sample so that it's not freed before the synth is
+    // finished using it, or until another sample or waveform is set.
+    sample: Option<AudioSample>,
+
+    // Modulators currently attached to the synth, held so they aren't freed while in use.
+    frequency_modulator: Option<Modulator>,
+    amplitude_modulator: Option<Modulator>,
+    parameter_modulators: BTreeMap<i32, Modulator>,
+
+    // False once the synth is a voice of an instrument, which frees it along with itself.
+    pub(crate) owned: bool,
+}
+
+impl Drop for Synth {
+    fn drop(&mut self) {
+        if self.owned {
+            // Use _log to leak rather than fail
+            pd_func_caller_log!((*self.raw_subsystem).freeSynth, self.raw_synth);
+        }
+    }
+}
+
+// Not implemented: newSynth (use Sound::get_synth), setGenerator_deprecated, getEnvelope, and
+// setWavetable.
+impl Synth {
+    pub(crate) fn new(
+        raw_subsystem: *const crankstart_sys::playdate_sound_synth,
+        raw_synth: *mut crankstart_sys::PDSynth,
+    ) -> Result<Self> {
+        ensure!(
+            !raw_subsystem.is_null(),
+            "Null pointer given as subsystem to Synth::new"
+        );
+        ensure!(
+            !raw_synth.is_null(),
+            "Null pointer given as synth to Synth::new"
+        );
+        Ok(Self {
+            raw_subsystem,
+            raw_synth,
+            sample: None,
+            frequency_modulator: None,
+            amplitude_modulator: None,
+            parameter_modulators: BTreeMap::new(),
+            owned: true,
+        })
+    }
+
+    /// Sets the waveform the synth uses to generate sound.  This replaces any sample set with
+    /// `set_sample`.
+    pub fn set_waveform(&mut self, waveform: SoundWaveform) -> Result<()> {
+        pd_func_caller!((*self.raw_subsystem).setWaveform, self.raw_synth, waveform)?;
+        self.sample = None;
+        Ok(())
+    }
+
+    /// Makes the synth play the given sample instead of a waveform.  If `sustain_end` is greater
+    /// than zero, the frames between `sustain_start` and `sustain_end` are looped while the note
+    /// is held.
+    pub fn set_sample(
+        &mut self,
+        audio_sample: &AudioSample,
+        sustain_start: u32,
+        sustain_end: u32,
+    ) -> Result<()> {
+        // We store an Rc clone of the audio sample so that it's not freed before the synth is
+        // finished using it, or until another sample is set.
+        self.sample = Some(audio_sample.clone());
+
+        pd_func_caller!(
+            (*self.raw_subsystem).setSample,
+            self.raw_synth,
+            audio_sample.inner.raw_audio_sample,
+            sustain_start,
+            sustain_end
+        )
+    }
+
+    /// Replaces the synth's waveform with a generator implemented in Rust.  The synth takes
+    /// ownership of the generator and drops it when the synth is freed or another generator,
+    /// waveform, or sample is set.
+    pub fn set_generator<G: SynthGenerator>(&mut self, generator: G, stereo: bool) -> Result<()> {
+        let state = Box::new(GeneratorState { generator, stereo });
+        let userdata = Box::into_raw(state);
+        let result = pd_func_caller!(
+            (*self.raw_subsystem).setGenerator,
+            self.raw_synth,
+            stereo as ctypes::c_int,
+            Some(generator_render::<G>),
+            Some(generator_note_on::<G>),
+            Some(generator_release::<G>),
+            Some(generator_set_parameter::<G>),
+            Some(generator_dealloc::<G>),
+            Some(generator_copy::<G>),
+            userdata as *mut ctypes::c_void
+        );
+        if let Err(err) = result {
+            // The synth never got the generator, so it's still ours to free.
+            unsafe {
+                let _ = Box::from_raw(userdata);
+            }
+            return Err(err);
+        }
+        self.sample = None;
+        Ok(())
+    }
+
+    /// Returns the number of parameters the synth's generator accepts.
+    pub fn get_parameter_count(&self) -> Result<i32> {
+        pd_func_caller!((*self.raw_subsystem).getParameterCount, self.raw_synth)
+    }
+
+    /// Sets a generator parameter; see `SynthGenerator::set_parameter`.  Returns whether the
+    /// generator used the value.
+    pub fn set_parameter(&self, parameter: i32, value: f32) -> Result<bool> {
+        let result = pd_func_caller!(
+            (*self.raw_subsystem).setParameter,
+            self.raw_synth,
+            parameter,
+            value
+        )?;
+        Ok(result != 0)
+    }
+
+    /// Sets a signal to modulate the synth's frequency, in half steps; None removes it.
+    pub fn set_frequency_modulator(&mut self, signal: Option<&dyn Signal>) -> Result<()> {
+        let modulator = signal.map(|signal| signal.modulator());
+        pd_func_caller!(
+            (*self.raw_subsystem).setFrequencyModulator,
+            self.raw_synth,
+            raw_modulator(modulator.as_ref())
+        )?;
+        self.frequency_modulator = modulator;
+        Ok(())
+    }
+
+    /// Sets a signal to modulate the synth's amplitude; None removes it.
+    pub fn set_amplitude_modulator(&mut self, signal: Option<&dyn Signal>) -> Result<()> {
+        let modulator = signal.map(|signal| signal.modulator());
+        pd_func_caller!(
+            (*self.raw_subsystem).setAmplitudeModulator,
+            self.raw_synth,
+            raw_modulator(modulator.as_ref())
+        )?;
+        self.amplitude_modulator = modulator;
+        Ok(())
+    }
+
+    /// Sets a signal to modulate a generator parameter; None removes it.
+    pub fn set_parameter_modulator(
+        &mut self,
+        parameter: i32,
+        signal: Option<&dyn Signal>,
+    ) -> Result<()> {
+        let modulator = signal.map(|signal| signal.modulator());
+        pd_func_caller!(
+            (*self.raw_subsystem).setParameterModulator,
+            self.raw_synth,
+            parameter,
+            raw_modulator(modulator.as_ref())
+        )?;
+        match modulator {
+            Some(modulator) => self.parameter_modulators.insert(parameter, modulator),
+            None => self.parameter_modulators.remove(&parameter),
+        };
+        Ok(())
+    }
+
+    /// Sets the attack time of the synth's envelope, in seconds.
+    pub fn set_attack_time(&self, attack: f32) -> Result<()> {
+        pd_func_caller!((*self.raw_subsystem).setAttackTime, self.raw_synth, attack)
+    }
+
+    /// Sets the decay time of the synth's envelope, in seconds.
+    pub fn set_decay_time(&self, decay: f32) -> Result<()> {
+        pd_func_caller!((*self.raw_subsystem).setDecayTime, self.raw_synth, decay)
+    }
+
+    /// Sets the sustain level of the synth's envelope, from 0 to 1.
+    pub fn set_sustain_level(&self, sustain: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.raw_subsystem).setSustainLevel,
+            self.raw_synth,
+            sustain
+        )
+    }
+
+    /// Sets the release time of the synth's envelope, in seconds.
+    pub fn set_release_time(&self, release: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.raw_subsystem).setReleaseTime,
+            self.raw_synth,
+            release
+        )
+    }
+
+    /// Sets all four envelope parameters at once; see the individual setters for units.
+    pub fn set_adsr(&self, attack: f32, decay: f32, sustain: f32, release: f32) -> Result<()> {
+        self.set_attack_time(attack)?;
+        self.set_decay_time(decay)?;
+        self.set_sustain_level(sustain)?;
+        self.set_release_time(release)
+    }
+
+    /// Transposes the synth's output by the given number of half steps.
+    pub fn set_transpose(&self, half_steps: f32) -> Result<()> {
+        pd_func_caller!(
+            (*self.raw_subsystem).setTranspose,
+            self.raw_synth,
+            half_steps
+        )
+    }
+
+    /// Plays a note at the given frequency, in Hz, and velocity, from 0 to 1.  If `length` is
+    /// None, the note plays until `note_off` is called; otherwise it's the note length in
+    /// seconds.  `when` is the sound engine time (see `Sound::get_current_time`) to start the
+    /// note; 0 plays it immediately.
+    pub fn play_note(
+        &self,
+        frequency: f32,
+        velocity: f32,
+        length: Option<f32>,
+        when: ctypes::c_uint,
+    ) -> Result<()> {
+        pd_func_caller!(
+            (*self.raw_subsystem).playNote,
+            self.raw_synth,
+            frequency,
+            velocity,
+            length.unwrap_or(-1.0),
+            when
+        )
+    }
+
+    /// Like `play_note`, but takes a MIDI note number, where 60 is middle C.  Fractional values
+    /// are allowed.
+    pub fn play_midi_note(
+        &self,
+        note: MIDINote,
+        velocity: f32,
+        length: Option<f32>,
+        when: ctypes::c_uint,
+    ) -> Result<()> {
+        pd_func_caller!(
+            (*self.raw_subsystem).playMIDINote,
+            self.raw_synth,
+            note,
+            velocity,
+            length.unwrap_or(-1.0),
+            when
+        )
+    }
+
+    /// Sends a note off event at the given sound engine time, starting the release phase of
+    /// the envelope; 0 releases the note immediately.
+    pub fn note_off(&self, when: ctypes::c_uint) -> Result<()> {
+        pd_func_caller!((*self.raw_subsystem).noteOff, self.raw_synth, when)
+    }
+
+    /// Stops the synth immediately, without playing the release phase of the envelope.
+    pub fn stop(&self) -> Result<()> {
+        pd_func_caller!((*self.raw_subsystem).stop, self.raw_synth)
+    }
+
+    /// Returns whether the synth is currently playing a note.
+    pub fn is_playing(&self) -> Result<bool> {
+        let result = pd_func_caller!((*self.raw_subsystem).isPlaying, self.raw_synth)?;
+        Ok(result == 1)
+    }
+
+    /// Gets the current volume of the left and right audio channels, out of 1.
+    pub fn get_volume(&self) -> Result<(f32, f32)> {
+        let mut left = 0.0;
+        let mut right = 0.0;
+        pd_func_caller!(
+            (*self.raw_subsystem).getVolume,
+            self.raw_synth,
+            &mut left,
+            &mut right,
+        )?;
+        Ok((left, right))
+    }
+
+    /// Sets the volume of the left and right audio channels, out of 1.
+    pub fn set_volume(&self, left: f32, right: f32) -> Result<()> {
+        pd_func_caller!((*self.raw_subsystem).setVolume, self.raw_synth, left, right)
+    }
+
+    /// Returns a new synth with the same settings as this one, e.g. to play the same patch
+    /// polyphonically.
+    pub fn copy(&self) -> Result<Self> {
+        let raw_synth = pd_func_caller!((*self.raw_subsystem).copy, self.raw_synth)?;
+        ensure!(!raw_synth.is_null(), "Null returned from synth.copy");
+        let mut synth = Self::new(self.raw_subsystem, raw_synth)?;
+        synth.sample = self.sample.clone();
+        synth.frequency_modulator = self.frequency_modulator.clone();
+        synth.amplitude_modulator = self.amplitude_modulator.clone();
+        synth.parameter_modulators = self.parameter_modulators.clone();
+        Ok(synth)
+    }
+}
+
+/// A custom sound generator for a `Synth`, implemented in Rust.  Set it on a synth with
+/// `Synth::set_generator`; the synth then owns it and calls its methods from the audio thread.
+///
+/// Since the methods take plain slices, a generator can be exercised on the host by calling
+/// `render` directly on a buffer.
+pub trait SynthGenerator: Clone + 'static {
+    /// Adds (not replaces) the generated audio to `left` and, if the generator was set as
+    /// stereo, `right`.  Samples are in Q8.24 fixed point.  `rate` is the amount to advance a
+    /// Q32 phase accumulator each sample for the current note, and `drate` is how much `rate`
+    /// changes each sample.  Returns the number of frames rendered; returning 0 tells the synth
+    /// the voice has finished.
+    fn render(
+        &mut self,
+        left: &mut [i32],
+        right: Option<&mut [i32]>,
+        rate: u32,
+        drate: i32,
+    ) -> usize;
+
+    /// Called when the synth starts a note.  `length` is the note length in seconds, or None if
+    /// it plays until released.
+    fn note_on(&mut self, note: MIDINote, velocity: f32, length: Option<f32>);
+
+    /// Called when the note is released.  If `stop` is true the voice should go silent
+    /// immediately; otherwise it should begin its release phase.
+    fn release(&mut self, stop: bool);
+
+    /// Called with values from `Synth::set_parameter`.  Returns whether the parameter was used.
+    fn set_parameter(&mut self, parameter: i32, value: f32) -> bool {
+        false
+    }
+
+    /// Called when the synth is done with the generator, just before it's dropped.
+    fn dealloc(&mut self) {}
+}
+
+// The userdata handed to the synth: the generator plus what it needs to build slices.
+struct GeneratorState<G> {
+    generator: G,
+    stereo: bool,
+}
+
+extern "C" fn generator_render<G: SynthGenerator>(
+    userdata: *mut ctypes::c_void,
+    left: *mut i32,
+    right: *mut i32,
+    nsamples: ctypes::c_int,
+    rate: u32,
+    drate: i32,
+) -> ctypes::c_int {
+    let state = unsafe { &mut *(userdata as *mut GeneratorState<G>) };
+    let len = nsamples.max(0) as usize;
+    let left = unsafe { slice::from_raw_parts_mut(left, len) };
+    let right = if state.stereo && !right.is_null() {
+        Some(unsafe { slice::from_raw_parts_mut(right, len) })
+    } else {
+        None
+    };
+    state.generator.render(left, right, rate, drate) as ctypes::c_int
+}
+
+extern "C" fn generator_note_on<G: SynthGenerator>(
+    userdata: *mut ctypes::c_void,
+    note: MIDINote,
+    velocity: f32,
+    len: f32,
+) {
+    let state = unsafe { &mut *(userdata as *mut GeneratorState<G>) };
+    let length = if len < 0.0 { None } else { Some(len) };
+    state.generator.note_on(note, velocity, length);
+}
+
+extern "C" fn generator_release<G: SynthGenerator>(
+    userdata: *mut ctypes::c_void,
+    stop: ctypes::c_int,
+) {
+    let state = unsafe { &mut *(userdata as *mut GeneratorState<G>) };
+    state.generator.release(stop != 0);
+}
+
+extern "C" fn generator_set_parameter<G: SynthGenerator>(
+    userdata: *mut ctypes::c_void,
+    parameter: ctypes::c_int,
+    value: f32,
+) -> ctypes::c_int {
+    let state = unsafe { &mut *(userdata as *mut GeneratorState<G>) };
+    state.generator.set_parameter(parameter, value) as ctypes::c_int
+}
+
+extern "C" fn generator_dealloc<G: SynthGenerator>(userdata: *mut ctypes::c_void) {
+    // Recast into box to let Box deal with freeing the right memory
+    let mut state = unsafe { Box::from_raw(userdata as *mut GeneratorState<G>) };
+    state.generator.dealloc();
+}
+
+extern "C" fn generator_copy<G: SynthGenerator>(
+    userdata: *mut ctypes::c_void,
+) -> *mut ctypes::c_void {
+    let state = unsafe { &*(userdata as *mut GeneratorState<G>) };
+    let copy = Box::new(GeneratorState {
+        generator: state.generator.clone(),
+        stereo: state.stereo,
+    });
+    Box::into_raw(copy) as *mut ctypes::c_void
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ONE: i32 = 1 << 24;
+
+    // A square wave driven by the synth's Q32 phase accumulator, at half volume.
+    #[derive(Clone, Debug, Default)]
+    struct Square {
+        phase: u32,
+        velocity: f32,
+        length: Option<f32>,
+        released: bool,
+    }
+
+    impl SynthGenerator for Square {
+        fn render(
+            &mut self,
+            left: &mut [i32],
+            right: Option<&mut [i32]>,
+            rate: u32,
+            drate: i32,
+        ) -> usize {
+            if self.released {
+                return 0;
+            }
+            for sample in left.iter_mut() {
+                *sample += if self.phase < 1 << 31 {
+                    ONE / 2
+                } else {
+                    -ONE / 2
+                };
+                self.phase = self.phase.wrapping_add(rate);
+            }
+            left.len()
+        }
+
+        fn note_on(&mut self, note: MIDINote, velocity: f32, length: Option<f32>) {
+            self.velocity = velocity;
+            self.length = length;
+        }
+
+        fn release(&mut self, stop: bool) {
+            self.released = stop;
+        }
+    }
+
+    fn userdata(state: &mut GeneratorState<Square>) -> *mut ctypes::c_void {
+        state as *mut GeneratorState<Square> as *mut ctypes::c_void
+    }
+
+    #[test]
+    fn renders_square_wave() {
+        let mut state = GeneratorState {
+            generator: Square::default(),
+            stereo: false,
+        };
+        let mut left = [0; 24];
+        // A period of 8 samples.
+        let rate = 1 << 29;
+        let rendered = generator_render::<Square>(
+            userdata(&mut state),
+            left.as_mut_ptr(),
+            core::ptr::null_mut(),
+            left.len() as ctypes::c_int,
+            rate,
+            0,
+        );
+        assert_eq!(rendered, 24);
+        assert_eq!(left.iter().max(), Some(&(ONE / 2)));
+        assert_eq!(left.iter().min(), Some(&(-ONE / 2)));
+        assert_eq!(left[..4], [ONE / 2; 4]);
+        assert_eq!(left[4..8], [-ONE / 2; 4]);
+        assert_eq!(left[8..16], left[..8]);
+        assert_eq!(left[16..24], left[..8]);
+    }
+
+    #[test]
+    fn adds_to_the_buffer() {
+        let mut generator = Square::default();
+        let mut left = [ONE; 4];
+        generator.render(&mut left, None, 1 << 30, 0);
+        assert_eq!(left, [ONE + ONE / 2, ONE + ONE / 2, ONE / 2, ONE / 2]);
+    }
+
+    #[test]
+    fn passes_note_events_through() {
+        let mut state = GeneratorState {
+            generator: Square::default(),
+            stereo: false,
+        };
+        generator_note_on::<Square>(userdata(&mut state), 60.0, 0.5, -1.0);
+        assert_eq!(state.generator.length, None);
+        generator_note_on::<Square>(userdata(&mut state), 60.0, 0.5, 2.0);
+        assert_eq!(
+            (state.generator.velocity, state.generator.length),
+            (0.5, Some(2.0))
+        );
+
+        generator_release::<Square>(userdata(&mut state), 1);
+        let mut left = [0; 4];
+        let rendered = generator_render::<Square>(
+            userdata(&mut state),
+            left.as_mut_ptr(),
+            core::ptr::null_mut(),
+            4,
+            1 << 30,
+            0,
+        );
+        assert_eq!(rendered, 0);
+    }
+
+    #[test]
+    fn copies_and_frees_state() {
+        let state = Box::into_raw(Box::new(GeneratorState {
+            generator: Square {
+                phase: 123,
+                ..Square::default()
+            },
+            stereo: true,
+        }));
+        let copy = generator_copy::<Square>(state as *mut ctypes::c_void);
+        let copied = unsafe { &*(copy as *mut GeneratorState<Square>) };
+        assert_eq!((copied.generator.phase, copied.stereo), (123, true));
+        generator_dealloc::<Square>(copy);
+        generator_dealloc::<Square>(state as *mut ctypes::c_void);
+    }
+}
@@ -0,0 +1,299 @@
+use crate::{pd_func_caller, pd_func_caller_log};
+use crankstart_sys::ctypes;
+
+use super::{
+    control::ControlSignal, instrument::Instrument, sequence::SequenceInner, synth::MIDINote,
+};
+use alloc::{rc::Rc, vec::Vec};
+use anyhow::{ensure, Error, Result};
+use core::cell::{Cell, RefCell};
+
+/// A note in a `Track`.  `step` and `length` are in sequence steps; see `Sequence::set_tempo`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct NoteEvent {
+    pub step: u32,
+    pub length: u32,
+    pub note: MIDINote,
+    pub velocity: f32,
+}
+
+/// A list of note and control events played on an `Instrument` as part of a `Sequence`.
+// Really a wrapper around an Rc clone of the internal structure; derive Clone so it's easy to get
+// another Rc reference.  We use Rc so we don't free the track while a sequence still plays it.
+#[derive(Clone, Debug)]
+pub struct Track {
+    pub(crate) inner: Rc<TrackInner>,
+}
+
+#[derive(Debug)]
+pub(crate) struct TrackInner {
+    raw_subsystem: *const crankstart_sys::playdate_sound_track,
+    raw_control_signal: *const crankstart_sys::playdate_control_signal,
+    pub(crate) raw_track: *mut crankstart_sys::SequenceTrack,
+
+    // Tracks that belong to a sequence are freed by it, so they keep it alive instead of being
+    // freed themselves.
+    pub(crate) sequence: Option<Rc<SequenceInner>>,
+    // Set once the track is given to a sequence with set_track_at_index; freeing the sequence
+    // frees its tracks, so the track mustn't be freed again.
+    pub(crate) given_to_sequence: Cell<bool>,
+
+    // The instrument given to set_instrument, so it's not freed while the track uses it.
+    instrument: RefCell<Option<Instrument>>,
+}
+
+impl Drop for TrackInner {
+    fn drop(&mut self) {
+        if self.sequence.is_none() && !self.given_to_sequence.get() {
+            // Use _log to leak rather than fail
+            pd_func_caller_log!((*self.raw_subsystem).freeTrack, self.raw_track);
+        }
+    }
+}
+
+// Not implemented: newTrack (use Sound::get_track), and getInstrument for instruments not set
+// from Rust.
+impl Track {
+    pub(crate) fn new(
+        raw_subsystem: *const crankstart_sys::playdate_sound_track,
+        raw_control_signal: *const crankstart_sys::playdate_control_signal,
+        raw_track: *mut crankstart_sys::SequenceTrack,
+        sequence: Option<Rc<SequenceInner>>,
+    ) -> Result<Self> {
+        ensure!(
+            !raw_subsystem.is_null(),
+            "Null pointer given as subsystem to Track::new"
+        );
+        ensure!(
+            !raw_control_signal.is_null(),
+            "Null pointer given as control signal subsystem to Track::new"
+        );
+        ensure!(
+            !raw_track.is_null(),
+            "Null pointer given as track to Track::new"
+        );
+        Ok(Self {
+            inner: Rc::new(TrackInner {
+                raw_subsystem,
+                raw_control_signal,
+                raw_track,
+                sequence,
+                given_to_sequence: Cell::new(false),
+                instrument: RefCell::new(None),
+            }),
+        })
+    }
+
+    /// Sets the instrument that plays the track's notes.  The instrument is kept alive for as
+    /// long as the track uses it.
+    pub fn set_instrument(&self, instrument: &Instrument) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setInstrument,
+            self.inner.raw_track,
+            instrument.inner.raw_instrument
+        )?;
+        if let Some(sequence) = self.inner.sequence.as_ref() {
+            // Other handles to this track may come and go, so let the sequence hold on to it.
+            sequence
+                .instruments
+                .borrow_mut()
+                .insert(self.inner.raw_track as usize, instrument.clone());
+        }
+        *self.inner.instrument.borrow_mut() = Some(instrument.clone());
+        Ok(())
+    }
+
+    /// Returns the instrument set with `set_instrument`, if any.
+    pub fn get_instrument(&self) -> Option<Instrument> {
+        if let Some(sequence) = self.inner.sequence.as_ref() {
+            let instruments = sequence.instruments.borrow();
+            if let Some(instrument) = instruments.get(&(self.inner.raw_track as usize)) {
+                return Some(instrument.clone());
+            }
+        }
+        self.inner.instrument.borrow().clone()
+    }
+
+    /// Adds a note at the given step, lasting `length` steps.
+    pub fn add_note_event(
+        &self,
+        step: u32,
+        length: u32,
+        note: MIDINote,
+        velocity: f32,
+    ) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).addNoteEvent,
+            self.inner.raw_track,
+            step,
+            length,
+            note,
+            velocity
+        )
+    }
+
+    /// Removes the note at the given step.
+    pub fn remove_note_event(&self, step: u32, note: MIDINote) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).removeNoteEvent,
+            self.inner.raw_track,
+            step,
+            note
+        )
+    }
+
+    /// Removes all notes from the track.
+    pub fn clear_notes(&self) -> Result<()> {
+        pd_func_caller!((*self.inner.raw_subsystem).clearNotes, self.inner.raw_track)
+    }
+
+    /// Returns all of the track's notes, in step order.
+    pub fn get_notes(&self) -> Result<Vec<NoteEvent>> {
+        let mut notes = Vec::new();
+        let mut index = pd_func_caller!(
+            (*self.inner.raw_subsystem).getIndexForStep,
+            self.inner.raw_track,
+            0
+        )?;
+        loop {
+            let mut event = NoteEvent {
+                step: 0,
+                length: 0,
+                note: 0.0,
+                velocity: 0.0,
+            };
+            let found = pd_func_caller!(
+                (*self.inner.raw_subsystem).getNoteAtIndex,
+                self.inner.raw_track,
+                index,
+                &mut event.step,
+                &mut event.length,
+                &mut event.note,
+                &mut event.velocity
+            )?;
+            if found == 0 {
+                break;
+            }
+            notes.push(event);
+            index += 1;
+        }
+        Ok(notes)
+    }
+
+    /// Adds an event for the given MIDI controller at the given step.  If `interpolate` is true,
+    /// the value ramps from the previous event instead of jumping.
+    pub fn add_control_event(
+        &self,
+        controller: i32,
+        step: i32,
+        value: f32,
+        interpolate: bool,
+    ) -> Result<()> {
+        let raw_signal = pd_func_caller!(
+            (*self.inner.raw_subsystem).getSignalForController,
+            self.inner.raw_track,
+            controller,
+            1
+        )?;
+        ensure!(
+            !raw_signal.is_null(),
+            "Null returned from track.getSignalForController"
+        );
+        pd_func_caller!(
+            (*self.inner.raw_control_signal).addEvent,
+            raw_signal,
+            step,
+            value,
+            interpolate as ctypes::c_int
+        )
+    }
+
+    /// Returns the track's control signal at the given index, if there is one; see
+    /// `get_control_signal_count`.
+    pub fn get_control_signal(&self, index: i32) -> Result<Option<ControlSignal>> {
+        let raw_signal = pd_func_caller!(
+            (*self.inner.raw_subsystem).getControlSignal,
+            self.inner.raw_track,
+            index
+        )?;
+        self.wrap_control_signal(raw_signal)
+    }
+
+    /// Returns the track's control signal for the given MIDI controller.  If there isn't one,
+    /// creates it if `create` is true and otherwise returns None.
+    pub fn get_signal_for_controller(
+        &self,
+        controller: i32,
+        create: bool,
+    ) -> Result<Option<ControlSignal>> {
+        let raw_signal = pd_func_caller!(
+            (*self.inner.raw_subsystem).getSignalForController,
+            self.inner.raw_track,
+            controller,
+            create as ctypes::c_int
+        )?;
+        self.wrap_control_signal(raw_signal)
+    }
+
+    // The track owns its signals, so the wrapper holds the track rather than freeing them.
+    fn wrap_control_signal(
+        &self,
+        raw_signal: *mut crankstart_sys::ControlSignal,
+    ) -> Result<Option<ControlSignal>> {
+        if raw_signal.is_null() {
+            return Ok(None);
+        }
+        ControlSignal::new(
+            self.inner.raw_control_signal,
+            raw_signal,
+            Some(self.inner.clone()),
+        )
+        .map(Some)
+    }
+
+    /// Removes all control events from the track.
+    pub fn clear_control_events(&self) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).clearControlEvents,
+            self.inner.raw_track
+        )
+    }
+
+    /// Returns the number of control signals on the track.
+    pub fn get_control_signal_count(&self) -> Result<i32> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).getControlSignalCount,
+            self.inner.raw_track
+        )
+    }
+
+    /// Returns the maximum number of notes the track plays at once.
+    pub fn get_polyphony(&self) -> Result<i32> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).getPolyphony,
+            self.inner.raw_track
+        )
+    }
+
+    /// Returns the number of voices currently playing on the track's instrument.
+    pub fn active_voice_count(&self) -> Result<i32> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).activeVoiceCount,
+            self.inner.raw_track
+        )
+    }
+
+    /// Mutes or unmutes the track.
+    pub fn set_muted(&self, muted: bool) -> Result<()> {
+        pd_func_caller!(
+            (*self.inner.raw_subsystem).setMuted,
+            self.inner.raw_track,
+            muted as ctypes::c_int
+        )
+    }
+
+    /// Returns the length of the track, in steps.
+    pub fn get_length(&self) -> Result<u32> {
+        pd_func_caller!((*self.inner.raw_subsystem).getLength, self.inner.raw_track)
+    }
+}
@@ -2,6 +2,7 @@ extern crate alloc;
 
 use {
     crate::{
+        geometry::ScreenSize,
         graphics::{Bitmap, Graphics, LCDBitmapDrawMode, LCDBitmapFlip, LCDColor, PDRect},
         log_to_console, pd_func_caller, pd_func_caller_log,
         system::System,
@@ -12,7 +13,7 @@ use {
         collections::BTreeMap,
         rc::{Rc, Weak},
     },
-    anyhow::{anyhow, Error, Result},
+    anyhow::{anyhow, ensure, Error, Result},
     core::{
         cell::{Ref, RefCell},
         fmt::Debug,
@@ -20,7 +21,8 @@ use {
         slice,
     },
     crankstart_sys::{
-        playdate_sprite, LCDRect, LCDSprite, LCDSpriteCollisionFilterProc, SpriteCollisionInfo,
+        ctypes::c_int, playdate_sprite, LCDPattern, LCDRect, LCDSprite,
+        LCDSpriteCollisionFilterProc, SpriteCollisionInfo,
     },
     euclid::default::Vector2D,
     euclid::{point2, size2, vec2},
@@ -29,7 +31,8 @@ use {
 
 pub use crankstart_sys::SpriteCollisionResponseType;
 
-// Currently no font:getHeight in C API.
+// The height of the system font; getFontHeight needs a font, and there's no way to get the
+// system one.
 const SYSTEM_FONT_HEIGHT: i32 = 18;
 
 pub type SpriteUpdateFunction = unsafe extern "C" fn(sprite: *mut crankstart_sys::LCDSprite);
@@ -116,6 +119,8 @@ pub struct SpriteInner {
     pub raw_sprite: *mut crankstart_sys::LCDSprite,
     playdate_sprite: *const playdate_sprite,
     image: Option<Bitmap>,
+    // Held so the stencil isn't freed while the sprite uses it.
+    stencil: Option<Bitmap>,
     userdata: Option<Rc<dyn core::any::Any>>,
 }
 
@@ -231,6 +236,60 @@ impl SpriteInner {
         Ok(())
     }
 
+    /// Only draws the sprite where the stencil bitmap has set (white) pixels.  The stencil is
+    /// kept alive until it's replaced or cleared.
+    pub fn set_stencil(&mut self, stencil: &Bitmap) -> Result<(), Error> {
+        pd_func_caller!(
+            (*self.playdate_sprite).setStencil,
+            self.raw_sprite,
+            stencil.inner.borrow().raw_bitmap,
+        )?;
+        self.stencil = Some(stencil.clone());
+        Ok(())
+    }
+
+    /// Like `set_stencil`, but if `tile` is true, the stencil is repeated to cover the whole
+    /// sprite; tiled stencils must be a multiple of 32 pixels wide.
+    pub fn set_stencil_image(&mut self, stencil: &Bitmap, tile: bool) -> Result<(), Error> {
+        if tile {
+            let width = stencil.get_data()?.width;
+            ensure!(
+                width % 32 == 0,
+                "Tiled stencil width {} isn't a multiple of 32",
+                width
+            );
+        }
+        pd_func_caller!(
+            (*self.playdate_sprite).setStencilImage,
+            self.raw_sprite,
+            stencil.inner.borrow().raw_bitmap,
+            tile as c_int,
+        )?;
+        self.stencil = Some(stencil.clone());
+        Ok(())
+    }
+
+    /// Only draws the sprite where the 8x8 pattern has set (white) pixels, repeated over the
+    /// whole sprite.  Only the first 8 bytes of the pattern, the pixels, are used.
+    pub fn set_stencil_pattern(&mut self, pattern: &LCDPattern) -> Result<(), Error> {
+        // The system copies the pattern, so it can be a temporary.
+        let mut pattern = *pattern;
+        pd_func_caller!(
+            (*self.playdate_sprite).setStencilPattern,
+            self.raw_sprite,
+            pattern.as_mut_ptr(),
+        )?;
+        self.stencil = None;
+        Ok(())
+    }
+
+    /// Removes the sprite's stencil.
+    pub fn clear_stencil(&mut self) -> Result<(), Error> {
+        pd_func_caller!((*self.playdate_sprite).clearStencil, self.raw_sprite)?;
+        self.stencil = None;
+        Ok(())
+    }
+
     pub fn set_tag(&mut self, tag: u8) -> Result<(), Error> {
         pd_func_caller!((*self.playdate_sprite).setTag, self.raw_sprite, tag)
     }
@@ -430,6 +489,34 @@ impl Sprite {
             .set_image(bitmap, flip)
     }
 
+    pub fn set_stencil(&mut self, stencil: &Bitmap) -> Result<(), Error> {
+        self.inner
+            .try_borrow_mut()
+            .map_err(Error::msg)?
+            .set_stencil(stencil)
+    }
+
+    pub fn set_stencil_image(&mut self, stencil: &Bitmap, tile: bool) -> Result<(), Error> {
+        self.inner
+            .try_borrow_mut()
+            .map_err(Error::msg)?
+            .set_stencil_image(stencil, tile)
+    }
+
+    pub fn set_stencil_pattern(&mut self, pattern: &LCDPattern) -> Result<(), Error> {
+        self.inner
+            .try_borrow_mut()
+            .map_err(Error::msg)?
+            .set_stencil_pattern(pattern)
+    }
+
+    pub fn clear_stencil(&mut self) -> Result<(), Error> {
+        self.inner
+            .try_borrow_mut()
+            .map_err(Error::msg)?
+            .clear_stencil()
+    }
+
     pub fn set_tag(&mut self, tag: u8) -> Result<(), Error> {
         self.inner
             .try_borrow_mut()
@@ -570,6 +657,7 @@ impl SpriteManager {
                 raw_sprite,
                 playdate_sprite: self.playdate_sprite,
                 image: None,
+                stencil: None,
                 userdata: None,
             };
             sprite.set_update_function(unsafe { SPRITE_UPDATE.expect("SPRITE_UPDATE") })?;
@@ -630,8 +718,8 @@ impl SpriteManager {
 /// `get_sprite_mut` to access the `Sprite` for other operations like `move_to` and `get_bounds`
 /// (which can tell you the height and width of the generated bitmap).
 ///
-/// Note: it's assumed that you're using the system font and haven't changed its tracking; we have
-/// no way to retrieve the current font or tracking with C APIs.
+/// The text is drawn with the current font and tracking, and the bitmap is sized to fit one line
+/// of it.
 #[derive(Clone, Debug)]
 pub struct TextSprite {
     sprite: Sprite,
@@ -649,13 +737,7 @@ impl TextSprite {
         let graphics = Graphics::get();
         let sprite_manager = SpriteManager::get_mut();
 
-        // Currently no getTextTracking C API; assume none has been set.
-        let tracking = 0;
-
-        let width = graphics.get_system_text_width(text, tracking)?;
-
-        let text_bitmap =
-            graphics.new_bitmap(size2(width, SYSTEM_FONT_HEIGHT), background.clone())?;
+        let text_bitmap = graphics.new_bitmap(Self::text_size(text)?, background.clone())?;
         graphics.with_context(&text_bitmap, || {
             graphics.draw_text(text, point2(0, 0))?;
             Ok(())
@@ -685,13 +767,7 @@ impl TextSprite {
         let text = text.as_ref();
         let graphics = Graphics::get();
 
-        // Currently no getTextTracking C API; assume none has been set.
-        let tracking = 0;
-
-        let width = graphics.get_system_text_width(text, tracking)?;
-
-        let text_bitmap =
-            graphics.new_bitmap(size2(width, SYSTEM_FONT_HEIGHT), self.background.clone())?;
+        let text_bitmap = graphics.new_bitmap(Self::text_size(text)?, self.background.clone())?;
         graphics.with_context(&text_bitmap, || {
             graphics.draw_text(text, point2(0, 0))?;
             Ok(())
@@ -702,6 +778,22 @@ impl TextSprite {
 
         Ok(())
     }
+
+    // The size of the text in the font and tracking it will be drawn with.
+    fn text_size(text: &str) -> Result<ScreenSize, Error> {
+        let graphics = Graphics::get();
+        let tracking = graphics.get_text_tracking();
+        match graphics.get_font() {
+            Some(font) => Ok(size2(
+                graphics.get_text_width(&font, text, tracking)?,
+                graphics.get_font_height(&font)? as i32,
+            )),
+            None => Ok(size2(
+                graphics.get_system_text_width(text, tracking)?,
+                SYSTEM_FONT_HEIGHT,
+            )),
+        }
+    }
 }
 
 /// This is a helper type for rotating and scaling an image in a sprite.